@@ -0,0 +1,93 @@
+#ifndef SCRIPTABLE_INPUT_SIMULATOR_H
+#define SCRIPTABLE_INPUT_SIMULATOR_H
+
+/* Generated with cbindgen from src/c_api.rs, do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// How a run ended. The exit code is given separately, since an exit: command can choose any code
+typedef enum SisRunStatus {
+  // The script ran as many times, or for as long, as asked. The exit code is 0
+  SIS_RUN_STATUS_FINISHED = 0,
+  // A stop condition or an exit: command stopped the run. The exit code is the one the program exits with
+  SIS_RUN_STATUS_STOPPED = 1,
+  // The run could not start, and sis_last_error has the reason. The exit code is not set
+  SIS_RUN_STATUS_ERROR = -1,
+} SisRunStatus;
+
+// A parsed script, made by sis_script_parse or sis_script_parse_file and freed by sis_free
+typedef struct SisScript SisScript;
+
+// Called before each command with the iteration, the line number and the text of the command
+typedef void (*SisProgressCallback)(void *user_data, uint64_t iteration, size_t line_number, const char *text);
+
+typedef struct SisRunOptions {
+  // How many times to run the script. 0 with a duration_ms of 0 runs it once
+  uint64_t times;
+  // Starts the script again until this long has passed, when times is 0
+  uint64_t duration_ms;
+  bool use_seed;
+  uint64_t seed;
+  // From 0 to 1000. Anything else makes the run an error
+  double wait_jitter_percentage;
+  // Logs what would be done instead of doing it, with screen comparisons scoring dry_run_match percent
+  bool dry_run;
+  double dry_run_match;
+  SisProgressCallback progress_callback;
+  void *progress_user_data;
+} SisRunOptions;
+
+// Called for each log message, with the level from 1 for errors to 5 for trace messages
+typedef void (*SisLogCallback)(void *user_data, int32_t level, const char *message);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The message of the last error on this thread, or NULL if there has been none. It stays valid until
+// the next failing call on the same thread
+const char *sis_last_error(void);
+
+// Parses the text of a script. Returns NULL if it is not valid
+//
+// # Safety
+// text must be NULL or a NUL terminated string
+SisScript *sis_script_parse(const char *text);
+
+// Reads a script file in any of the formats --file_name takes. Returns NULL if it cannot be read or is not valid
+//
+// # Safety
+// path must be NULL or a NUL terminated string
+SisScript *sis_script_parse_file(const char *path);
+
+SisRunOptions sis_run_options_default(void);
+
+// Runs the script and blocks until it finishes. options can be NULL for the defaults.
+// Unless there was an error, exit_code is set to the code the program would exit with, as listed
+// in the README. It can be NULL when the code is not needed
+//
+// # Safety
+// script must be NULL or come from sis_script_parse or sis_script_parse_file and not have been freed.
+// options must be NULL or point to options, which can start from sis_run_options_default.
+// exit_code must be NULL or point to an int32_t
+SisRunStatus sis_script_run(const SisScript *script, const SisRunOptions *options, int32_t *exit_code);
+
+// Sends the log messages of every later call to the callback, or stops sending them when it is NULL.
+// Returns -1 if another logger was set up first
+int32_t sis_set_log_callback(SisLogCallback callback, void *user_data);
+
+// Frees a script from sis_script_parse or sis_script_parse_file. NULL is ignored
+//
+// # Safety
+// script must not be used again, or freed again
+void sis_free(SisScript *script);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif // SCRIPTABLE_INPUT_SIMULATOR_H
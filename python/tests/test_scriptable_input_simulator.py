@@ -0,0 +1,61 @@
+# Run with pytest after "maturin develop". Everything here uses the dry run backend, so no
+# keyboard, mouse or screen is needed
+import pytest
+
+import scriptable_input_simulator as sis
+
+
+def test_parse_and_run_script():
+    script = sis.Script.parse("mouse_move: 10 20\nwait: 100\nkey: a click\n")
+    executor = sis.Executor(dry_run=True)
+
+    assert len(script) == 3
+    assert script.lines()[1] == (2, "wait: 100")
+    assert executor.run(script, times=2)
+    assert executor.elapsed_ms() == pytest.approx(200.0)
+    assert executor.mouse_location() == (10, 20)
+
+
+def test_actions():
+    executor = sis.Executor(dry_run=True)
+
+    executor.mouse_move(100, 200, duration_ms=50)
+    executor.mouse_move_relative(5, -5)
+    executor.key_hold("shift", 30)
+    executor.type_text("hello")
+
+    assert executor.mouse_location() == (105, 195)
+    assert executor.elapsed_ms() == pytest.approx(80.0)
+
+
+def test_exit_code():
+    executor = sis.Executor(dry_run=True)
+
+    assert not executor.run(sis.Script.parse("wait: 10\nexit: 4\n"))
+    assert executor.exit_code == 4
+    with pytest.raises(sis.StoppedError):
+        executor.key_click("a")
+
+    assert executor.run(sis.Script.parse("wait: 10\n"))
+    executor.key_click("a")
+
+
+def test_errors(tmp_path):
+    with pytest.raises(sis.ParseError):
+        sis.Script.parse("wait: soon")
+    with pytest.raises(sis.BlockError):
+        sis.Script.parse("every: 100\nwait: 10")
+    with pytest.raises(OSError):
+        sis.Script.from_file("no_such_script.txt")
+    with pytest.raises(ValueError):
+        sis.Executor(dry_run=True).key_click("no_such_key")
+    with pytest.raises(ValueError):
+        sis.Executor(dry_run=True).run(sis.Script.parse("wait: 10"), jitter=float("nan"))
+    with pytest.raises(FileNotFoundError):
+        sis.Executor(dry_run=True).compare_screen_to_image_file("no_such_image.png", 0, 0)
+    not_an_image = tmp_path / "not_an_image.png"
+    not_an_image.write_text("not an image")
+    with pytest.raises(ValueError):
+        sis.find_image_on_screen(str(not_an_image))
+    assert issubclass(sis.ParseError, sis.ScriptError)
+    assert issubclass(sis.StoppedError, sis.ScriptError)
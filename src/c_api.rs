@@ -0,0 +1,369 @@
+// A C API over the parser and executor, built into the cdylib with the c_api feature. The header is
+// include/scriptable_input_simulator.h, made with cbindgen from this file.
+//
+// Functions that fail return NULL, -1 or SIS_RUN_STATUS_ERROR and leave a message for sis_last_error on the same thread
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::time::Duration;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::command_executor::*;
+use crate::command_types::*;
+use crate::error::panic_message;
+use crate::input_backend::*;
+use crate::script::Script;
+use crate::timing::check_jitter_percentage;
+
+/// Called before each command with the iteration, the line number and the text of the command
+pub type SisProgressCallback = Option<extern "C" fn(user_data: *mut c_void, iteration: u64, line_number: usize, text: *const c_char)>;
+/// Called for each log message, with the level from 1 for errors to 5 for trace messages
+pub type SisLogCallback = Option<extern "C" fn(user_data: *mut c_void, level: i32, message: *const c_char)>;
+
+/// A parsed script, made by sis_script_parse or sis_script_parse_file and freed by sis_free
+pub struct SisScript {
+    script: Script
+}
+
+/// How a run ended. The exit code is given separately, since an exit: command can choose any code
+#[repr(C)]
+#[derive(PartialEq, Debug)]
+pub enum SisRunStatus {
+    /// The script ran as many times, or for as long, as asked. The exit code is 0
+    Finished = 0,
+    /// A stop condition or an exit: command stopped the run. The exit code is the one the program exits with
+    Stopped = 1,
+    /// The run could not start, and sis_last_error has the reason. The exit code is not set
+    Error = -1
+}
+
+#[repr(C)]
+pub struct SisRunOptions {
+    /// How many times to run the script. 0 with a duration_ms of 0 runs it once
+    pub times: u64,
+    /// Starts the script again until this long has passed, when times is 0
+    pub duration_ms: u64,
+    pub use_seed: bool,
+    pub seed: u64,
+    /// From 0 to 1000. Anything else makes the run an error
+    pub wait_jitter_percentage: f64,
+    /// Logs what would be done instead of doing it, with screen comparisons scoring dry_run_match percent
+    pub dry_run: bool,
+    pub dry_run_match: f64,
+    pub progress_callback: SisProgressCallback,
+    pub progress_user_data: *mut c_void
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).expect("Null bytes were replaced");
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+// A panic must not unwind into C, so it becomes an error like any other
+fn catch_panic<T, F: FnOnce() -> Result<T, String>>(function: F) -> Result<T, String> {
+    return match panic::catch_unwind(AssertUnwindSafe(function)) {
+        Ok(result) => result,
+        Err(panic_payload) => Err(format!("Panicked: {}", panic_message(panic_payload.as_ref())))
+    };
+}
+
+unsafe fn read_c_string<'a>(c_string: *const c_char, name: &str) -> Result<&'a str, String> {
+    if c_string.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+
+    return CStr::from_ptr(c_string).to_str().map_err(|_| format!("{} is not valid UTF-8", name));
+}
+
+fn into_script_pointer(result: Result<Script, String>) -> *mut SisScript {
+    return match result {
+        Ok(script) => Box::into_raw(Box::new(SisScript{script})),
+        Err(message) => {
+            set_last_error(message);
+            std::ptr::null_mut()
+        }
+    };
+}
+
+#[derive(Clone, Copy)]
+struct LogCallback {
+    callback: extern "C" fn(*mut c_void, i32, *const c_char),
+    // Kept as a number so the logger can be shared between threads. C owns what it points to
+    user_data: usize
+}
+
+static LOG_CALLBACK: Mutex<Option<LogCallback>> = Mutex::new(None);
+
+struct CallbackLogger;
+
+static CALLBACK_LOGGER: CallbackLogger = CallbackLogger;
+
+impl Log for CallbackLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        return true;
+    }
+
+    fn log(&self, record: &Record) {
+        // The lock is let go before the callback, which may log or set the callback itself
+        let log_callback = match *LOG_CALLBACK.lock().unwrap() {
+            Some(log_callback) => log_callback,
+            None => { return; }
+        };
+        let level = match record.level() {
+            Level::Error => 1,
+            Level::Warn => 2,
+            Level::Info => 3,
+            Level::Debug => 4,
+            Level::Trace => 5
+        };
+        let message = CString::new(record.args().to_string().replace('\0', " ")).expect("Null bytes were replaced");
+
+        (log_callback.callback)(log_callback.user_data as *mut c_void, level, message.as_ptr());
+    }
+
+    fn flush(&self) {}
+}
+
+/// The message of the last error on this thread, or NULL if there has been none. It stays valid until
+/// the next failing call on the same thread
+#[no_mangle]
+pub extern "C" fn sis_last_error() -> *const c_char {
+    return LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(message) => message.as_ptr(),
+        None => std::ptr::null()
+    });
+}
+
+/// Parses the text of a script. Returns NULL if it is not valid
+///
+/// # Safety
+/// text must be NULL or a NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn sis_script_parse(text: *const c_char) -> *mut SisScript {
+    return into_script_pointer(catch_panic(|| {
+        let text = read_c_string(text, "text")?;
+        return Script::parse(text).map_err(|error_reason| error_reason.to_string());
+    }));
+}
+
+/// Reads a script file in any of the formats --file_name takes. Returns NULL if it cannot be read or is not valid
+///
+/// # Safety
+/// path must be NULL or a NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn sis_script_parse_file(path: *const c_char) -> *mut SisScript {
+    return into_script_pointer(catch_panic(|| {
+        let path = read_c_string(path, "path")?;
+        return Script::from_file(path).map_err(|error_reason| error_reason.to_string());
+    }));
+}
+
+#[no_mangle]
+pub extern "C" fn sis_run_options_default() -> SisRunOptions {
+    return SisRunOptions {
+        times: 0,
+        duration_ms: 0,
+        use_seed: false,
+        seed: 0,
+        wait_jitter_percentage: 0.0,
+        dry_run: false,
+        dry_run_match: 100.0,
+        progress_callback: None,
+        progress_user_data: std::ptr::null_mut()
+    };
+}
+
+/// Runs the script and blocks until it finishes. options can be NULL for the defaults.
+/// Unless there was an error, exit_code is set to the code the program would exit with, as listed
+/// in the README. It can be NULL when the code is not needed
+///
+/// # Safety
+/// script must be NULL or come from sis_script_parse or sis_script_parse_file and not have been freed.
+/// options must be NULL or point to options, which can start from sis_run_options_default.
+/// exit_code must be NULL or point to an int32_t
+#[no_mangle]
+pub unsafe extern "C" fn sis_script_run(script: *const SisScript, options: *const SisRunOptions, exit_code: *mut i32) -> SisRunStatus {
+    let run_result = catch_panic(|| {
+        let script = match script.as_ref() {
+            Some(script) => &script.script,
+            None => { return Err(String::from("script is NULL")); }
+        };
+        let default_options = sis_run_options_default();
+        let options = options.as_ref().unwrap_or(&default_options);
+
+        let mut backend: Box<dyn InputBackend> = if options.dry_run {
+            Box::new(DryRunBackend::new(None, options.dry_run_match))
+        }
+        else {
+            Box::new(EnigoBackend::new())
+        };
+        if let Some(progress_callback) = options.progress_callback {
+            let progress_user_data = options.progress_user_data;
+            backend = Box::new(ProgressBackend::new(backend, Box::new(move |iteration, script_line: &ScriptLine| {
+                let text = CString::new(script_line.text.replace('\0', " ")).expect("Null bytes were replaced");
+                progress_callback(progress_user_data, iteration, script_line.line_number, text.as_ptr());
+            })));
+        }
+
+        let repeat = if options.times > 0 {
+            Repeat::Times(options.times)
+        }
+        else if options.duration_ms > 0 {
+            Repeat::For(Duration::from_millis(options.duration_ms))
+        }
+        else {
+            Repeat::Once
+        };
+        let run_options = RunOptions {
+            repeat,
+            seed: if options.use_seed { Some(options.seed) } else { None },
+            wait_jitter_percentage: check_jitter_percentage(options.wait_jitter_percentage)?,
+            ..Default::default()
+        };
+
+        let mut executor = CommandExecutor::new(backend);
+        return Ok(match executor.run(script, run_options) {
+            ExecutionResult::Finished => (SisRunStatus::Finished, 0),
+            ExecutionResult::Stopped => (SisRunStatus::Stopped, executor.stop_reason().map_or(0, |stop_reason| stop_reason.exit_code()))
+        });
+    });
+
+    return match run_result {
+        Ok((run_status, run_exit_code)) => {
+            if let Some(exit_code) = exit_code.as_mut() {
+                *exit_code = run_exit_code;
+            }
+            run_status
+        },
+        Err(message) => {
+            set_last_error(message);
+            SisRunStatus::Error
+        }
+    };
+}
+
+/// Sends the log messages of every later call to the callback, or stops sending them when it is NULL.
+/// Returns -1 if another logger was set up first
+#[no_mangle]
+pub extern "C" fn sis_set_log_callback(callback: SisLogCallback, user_data: *mut c_void) -> i32 {
+    *LOG_CALLBACK.lock().unwrap() = callback.map(|callback| LogCallback{callback, user_data: user_data as usize});
+
+    if callback.is_none() {
+        return 0;
+    }
+
+    // The logger can only be set up once, and stays in place with the callback switched under it
+    let is_callback_logger_set = std::ptr::eq(log::logger() as *const dyn Log as *const (), &CALLBACK_LOGGER as *const CallbackLogger as *const ());
+    if !is_callback_logger_set {
+        if let Err(error_reason) = log::set_logger(&CALLBACK_LOGGER) {
+            set_last_error(format!("The log callback could not be set: {}", error_reason));
+            return -1;
+        }
+        log::set_max_level(LevelFilter::Trace);
+    }
+
+    return 0;
+}
+
+/// Frees a script from sis_script_parse or sis_script_parse_file. NULL is ignored
+///
+/// # Safety
+/// script must not be used again, or freed again
+#[no_mangle]
+pub unsafe extern "C" fn sis_free(script: *mut SisScript) {
+    if !script.is_null() {
+        drop(Box::from_raw(script));
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    extern "C" fn count_progress(user_data: *mut c_void, _iteration: u64, _line_number: usize, _text: *const c_char) {
+        unsafe { *(user_data as *mut u64) += 1; }
+    }
+
+    #[test]
+    fn test_parse_and_run() {
+        let text = CString::new("wait: 100\nmouse_move: 10 20\nexit: 4\n").unwrap();
+        let mut progress_count: u64 = 0;
+
+        unsafe {
+            let script = sis_script_parse(text.as_ptr());
+            assert!(!script.is_null());
+
+            let mut options = sis_run_options_default();
+            options.dry_run = true;
+            options.progress_callback = Some(count_progress);
+            options.progress_user_data = &mut progress_count as *mut u64 as *mut c_void;
+
+            let mut exit_code: i32 = -1;
+            assert_eq!(sis_script_run(script, &options, &mut exit_code), SisRunStatus::Stopped);
+            assert_eq!(exit_code, 4);
+            sis_free(script);
+        }
+
+        assert_eq!(progress_count, 3);
+    }
+
+    #[test]
+    fn test_run_finished() {
+        let text = CString::new("wait: 100\n").unwrap();
+
+        unsafe {
+            let script = sis_script_parse(text.as_ptr());
+            let mut options = sis_run_options_default();
+            options.dry_run = true;
+
+            let mut exit_code: i32 = -1;
+            assert_eq!(sis_script_run(script, &options, &mut exit_code), SisRunStatus::Finished);
+            assert_eq!(exit_code, 0);
+            assert_eq!(sis_script_run(script, &options, std::ptr::null_mut()), SisRunStatus::Finished);
+
+            options.wait_jitter_percentage = f64::NAN;
+            assert_eq!(sis_script_run(script, &options, &mut exit_code), SisRunStatus::Error);
+            sis_free(script);
+        }
+    }
+
+    static LOGGED_LEVELS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+    // Takes the callback away again from inside itself, which needs the callback lock
+    extern "C" fn log_once(_user_data: *mut c_void, level: i32, message: *const c_char) {
+        if unsafe { CStr::from_ptr(message) }.to_str().unwrap() == "c_api log test" {
+            LOGGED_LEVELS.lock().unwrap().push(level);
+            sis_set_log_callback(None, std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_log_callback() {
+        assert_eq!(sis_set_log_callback(Some(log_once), std::ptr::null_mut()), 0);
+        log::trace!("c_api log test");
+        log::trace!("c_api log test");
+
+        assert_eq!(*LOGGED_LEVELS.lock().unwrap(), vec![5]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let text = CString::new("wait: soon").unwrap();
+
+        unsafe {
+            assert!(sis_script_parse(text.as_ptr()).is_null());
+            assert_eq!(CStr::from_ptr(sis_last_error()).to_str().unwrap(), "Line 1 is not formatted properly: wait: soon");
+
+            assert!(sis_script_parse(std::ptr::null()).is_null());
+            let mut exit_code: i32 = -1;
+            assert_eq!(sis_script_run(std::ptr::null(), std::ptr::null(), &mut exit_code), SisRunStatus::Error);
+            assert_eq!(exit_code, -1);
+            assert_eq!(CStr::from_ptr(sis_last_error()).to_str().unwrap(), "script is NULL");
+        }
+    }
+}
@@ -1,24 +1,672 @@
 use enigo::*;
 use log::{error, info};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde_json::{json, Value};
+use image::RgbaImage;
 
 use crate::command_types::*; 
-use crate::screen_compare::*;
+use crate::mouse_path::*;
+use crate::timing::*;
+use crate::held_inputs::*;
+use crate::emergency_stop::*;
+use crate::stop_conditions::*;
+use crate::pause_control::*;
+use crate::input_backend::*;
+use crate::debugger::*;
+use crate::screen_compare::{capture_screenshot, CaptureError};
+use crate::run_statistics::*;
+use crate::event_trace::*;
+use crate::script::Script;
 
-pub fn execute_commands(command_vector: &Vec<ParsedCommand>) {
-    let mut enigo = Enigo::new();
+// How often an intermediate mouse position is sent while dragging
+const MOUSE_DRAG_STEP_INTERVAL_MS: u64 = 10;
+// How often the emergency stop and the stop conditions are checked during long waits
+const STOP_CHECK_INTERVAL_MS: u64 = 10;
+// A wait after commands that ran late is shortened to catch up, as long as they are no later than this
+const MAX_SCHEDULE_LAG_MS: u64 = 1000;
+// With busy waiting, the end of every wait is spent checking the time instead of sleeping
+const BUSY_WAIT_MS: u64 = 1;
 
-    for parsed_command in command_vector{
-        match parsed_command{
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ExecutionResult {
+    Finished,
+    Stopped
+}
+
+// How many times a run goes through the script
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Repeat {
+    Once,
+    Times(u64),
+    // Starts the script again until this much time has passed
+    For(Duration)
+}
+
+// How far a run has got, for deciding whether to go through the script again
+pub struct RunProgress {
+    repeat: Repeat,
+    start_time: Duration,
+    paused_duration_before: Duration,
+    pub completed_iterations: u64
+}
+
+pub struct RunOptions {
+    pub repeat: Repeat,
+    // A random seed is used if this is not given
+    pub seed: Option<u64>,
+    pub wait_jitter_percentage: f64,
+    pub emergency_stop: Option<EmergencyStop>,
+    pub pause_control: Option<PauseControl>,
+    // When false, time spent paused uses up neither waits nor a Repeat::For duration
+    pub pause_counts_toward_time: bool,
+    // Ignored for simulated backends, since a simulated clock does not move while being checked
+    pub busy_wait: bool,
+    pub stop_conditions: StopConditions,
+    pub event_trace: Option<EventTrace>,
+    pub debugger: Option<Debugger>
+}
+
+impl Default for RunOptions {
+    fn default() -> RunOptions {
+        return RunOptions {
+            repeat: Repeat::Once,
+            seed: None,
+            wait_jitter_percentage: 0.0,
+            emergency_stop: None,
+            pause_control: None,
+            pause_counts_toward_time: false,
+            busy_wait: false,
+            stop_conditions: StopConditions::new(),
+            event_trace: None,
+            debugger: None
+        };
+    }
+}
+
+// An every: block that is currently running
+struct EveryLoop {
+    first_line_index: usize,
+    interval: Duration,
+    remaining_times: Option<u64>,
+    start_time: Duration
+}
+
+// Where a script is up to, and the every: blocks it is inside
+struct BlockState {
+    line_index: usize,
+    line_count: usize,
+    every_block_ends: HashMap<usize, usize>,
+    every_loops: Vec<EveryLoop>
+}
+
+impl BlockState {
+    fn new(script_lines: &[ScriptLine]) -> BlockState {
+        return BlockState {
+            line_index: 0,
+            line_count: script_lines.len(),
+            every_block_ends: find_every_block_ends(script_lines),
+            every_loops: vec![]
+        };
+    }
+}
+
+// Finds the end_every: that closes each every: in the script, by line index
+fn find_every_block_ends(script_lines: &[ScriptLine]) -> HashMap<usize, usize> {
+    let mut block_ends = HashMap::new();
+    let mut open_blocks: Vec<usize> = vec![];
+
+    for (line_index, script_line) in script_lines.iter().enumerate() {
+        match script_line.command {
+            ParsedCommand::Every{..} => { open_blocks.push(line_index); },
+            ParsedCommand::EndEvery => {
+                match open_blocks.pop() {
+                    Some(every_line_index) => { block_ends.insert(every_line_index, line_index); },
+                    None => { error!(target: "commands_debug", "Line {} has an end_every: without an every:, ignoring it", script_line.line_number); }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    for every_line_index in open_blocks {
+        error!(target: "commands_debug", "Line {} has an every: without an end_every:, so the lines after it run once", script_lines[every_line_index].line_number);
+    }
+
+    return block_ends;
+}
+
+pub struct CommandExecutor {
+    backend: Box<dyn InputBackend>,
+    // Every random decision goes through this, so a run can be reproduced from its seed
+    rng: StdRng,
+    wait_jitter_percentage: f64,
+    // Set by key_sequence_speed, used by every key_sequence without its own speed
+    key_sequence_delay_ms: u64,
+    held_inputs: HeldInputs,
+    emergency_stop: Option<EmergencyStop>,
+    stop_conditions: StopConditions,
+    stop_reason: Option<StopReason>,
+    pause_control: Option<PauseControl>,
+    // When false, time spent paused does not use up waits
+    pause_counts_toward_waits: bool,
+    total_paused_duration: Duration,
+    // When the last wait was meant to end. The next wait is measured from here rather than from
+    // when the commands in between actually finished
+    scheduled_time: Option<Duration>,
+    busy_wait: bool,
+    // How many times execute_commands has been started
+    iteration: u64,
+    debugger: Option<Debugger>,
+    last_match_percentage: Option<f64>,
+    statistics: RunStatistics,
+    // Set from outside, for example by a signal, to print the statistics at the next command
+    statistics_requested: Arc<AtomicBool>,
+    event_trace: Option<EventTrace>,
+    // Filled in while a command runs, for its trace event
+    resolved_arguments: serde_json::Map<String, Value>,
+    command_match_percentage: Option<f64>,
+    command_outcome: &'static str,
+    // Pause notices, statistics asked for with a signal and the debugger's output
+    output: Option<OutputSink>
+}
+
+impl CommandExecutor {
+    pub fn new(backend: Box<dyn InputBackend>) -> CommandExecutor {
+        return CommandExecutor {
+            backend,
+            rng: StdRng::seed_from_u64(rand::random::<u64>()),
+            wait_jitter_percentage: 0.0,
+            key_sequence_delay_ms: 0,
+            held_inputs: HeldInputs::new(),
+            emergency_stop: None,
+            stop_conditions: StopConditions::new(),
+            stop_reason: None,
+            pause_control: None,
+            pause_counts_toward_waits: false,
+            total_paused_duration: Duration::ZERO,
+            scheduled_time: None,
+            busy_wait: false,
+            iteration: 0,
+            debugger: None,
+            last_match_percentage: None,
+            statistics: RunStatistics::new(),
+            statistics_requested: Arc::new(AtomicBool::new(false)),
+            event_trace: None,
+            resolved_arguments: serde_json::Map::new(),
+            command_match_percentage: None,
+            command_outcome: "done",
+            output: None
+        };
+    }
+
+    // Presses keys and mouse buttons into a set shared with others, like a signal handler that
+    // releases them, and that outlives this executor
+    pub fn with_held_inputs(backend: Box<dyn InputBackend>, held_inputs: HeldInputs) -> CommandExecutor {
+        let mut command_executor = CommandExecutor::new(backend);
+        command_executor.held_inputs = held_inputs;
+        return command_executor;
+    }
+
+    // Without an output, what would be written to it is logged
+    pub fn set_output(&mut self, output: OutputSink) {
+        self.output = Some(output);
+    }
+
+    fn print(&self, message: &str) {
+        write_output(self.output.as_ref(), message);
+    }
+
+    pub fn statistics(&self) -> RunStatistics {
+        let mut statistics = self.statistics.clone();
+        statistics.set_total_duration(self.backend.elapsed());
+
+        return statistics;
+    }
+
+    pub fn statistics_requested(&self) -> Arc<AtomicBool> {
+        return Arc::clone(&self.statistics_requested);
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        return self.stop_reason;
+    }
+
+    // Runs the script as many times as run_options.repeat asks, or until something stops it
+    pub fn run(&mut self, script: &Script, run_options: RunOptions) -> ExecutionResult {
+        let mut run_progress = self.start_run(run_options);
+
+        while !self.is_run_finished(&run_progress) {
+            if self.execute_commands(script.lines()) == ExecutionResult::Stopped {
+                return ExecutionResult::Stopped;
+            }
+            run_progress.completed_iterations += 1;
+        }
+
+        return ExecutionResult::Finished;
+    }
+
+    // Applies the options of a run. Callers that go through their script themselves use this with
+    // is_run_finished, start_iteration and finish_iteration in place of run
+    pub fn start_run(&mut self, run_options: RunOptions) -> RunProgress {
+        let seed = run_options.seed.unwrap_or_else(rand::random::<u64>);
+        info!(target: "commands_debug", "Using random seed {}", seed);
+
+        self.rng = StdRng::seed_from_u64(seed);
+        self.wait_jitter_percentage = run_options.wait_jitter_percentage;
+        self.emergency_stop = run_options.emergency_stop;
+        self.pause_control = run_options.pause_control;
+        self.pause_counts_toward_waits = run_options.pause_counts_toward_time;
+        self.busy_wait = run_options.busy_wait;
+        self.stop_conditions = run_options.stop_conditions;
+        self.event_trace = run_options.event_trace;
+        self.debugger = run_options.debugger;
+        // A run does not make up for time that passed before it started, and is not stopped by what stopped the last one
+        self.scheduled_time = None;
+        self.stop_reason = None;
+
+        return RunProgress {
+            repeat: run_options.repeat,
+            start_time: self.backend.elapsed(),
+            paused_duration_before: self.total_paused_duration,
+            completed_iterations: 0
+        };
+    }
+
+    pub fn is_run_finished(&self, run_progress: &RunProgress) -> bool {
+        return match run_progress.repeat {
+            Repeat::Once => run_progress.completed_iterations >= 1,
+            Repeat::Times(times) => run_progress.completed_iterations >= times,
+            Repeat::For(duration) => {
+                self.backend.elapsed() > run_progress.start_time + duration + self.paused_duration_to_exclude(run_progress.paused_duration_before)
+            }
+        };
+    }
+
+    fn paused_duration_to_exclude(&self, paused_duration_before: Duration) -> Duration {
+        if self.pause_counts_toward_waits {
+            return Duration::ZERO;
+        }
+
+        return self.total_paused_duration - paused_duration_before;
+    }
+
+    pub fn held_inputs(&self) -> HeldInputs {
+        return self.held_inputs.clone();
+    }
+
+    // Time according to the backend, which is simulated in a dry run
+    pub fn elapsed(&self) -> Duration {
+        return self.backend.elapsed();
+    }
+
+    pub fn mouse_location(&self) -> (i32, i32) {
+        return self.backend.mouse_location();
+    }
+
+    // Gives the score from 0 to 1 without pressing anything, which the debugger shows like any other comparison
+    pub fn compare_screen_to_image_file(&mut self, input_file_path: &String, start_x: i32, start_y: i32) -> Result<f64, CaptureError> {
+        let match_percentage = self.backend.compare_screen_to_image_file(input_file_path, start_x, start_y)?;
+        self.last_match_percentage = Some(match_percentage);
+
+        return Ok(match_percentage);
+    }
+
+    pub fn find_image_on_screen(&mut self, input_file_path: &String) -> Result<Option<(i32, i32)>, CaptureError> {
+        return self.backend.find_image_on_screen(input_file_path);
+    }
+
+    pub fn capture_screen_area(&mut self, start_x: i32, start_y: i32, width: u32, height: u32) -> Result<RgbaImage, CaptureError> {
+        return self.backend.capture_screen_area(start_x, start_y, width, height);
+    }
+
+    // Pauses if asked to, then tells whether the run has been stopped. For callers that do work
+    // between commands, so that it can be stopped too
+    pub fn check_stop(&mut self) -> bool {
+        self.pause_if_requested();
+        return self.is_stop_requested();
+    }
+
+    fn is_stop_requested(&mut self) -> bool {
+        if self.stop_reason.is_none() {
+            if let Some(emergency_stop) = &self.emergency_stop {
+                self.stop_reason = emergency_stop.check();
+            }
+            if self.stop_reason.is_none() {
+                self.stop_reason = self.stop_conditions.check(self.backend.as_mut());
+            }
+
+            if let Some(stop_reason) = self.stop_reason {
+                info!(target: "commands_debug", "Stopping because {}", stop_reason);
+            }
+        }
+
+        return self.stop_reason.is_some();
+    }
+
+    fn is_pause_toggle_requested(&mut self) -> bool {
+        return match &mut self.pause_control {
+            Some(pause_control) => pause_control.is_toggle_requested(),
+            None => false
+        };
+    }
+
+    // Releases held inputs and blocks until resumed or stopped, then presses them again.
+    // Returns how long execution was paused
+    fn pause_if_requested(&mut self) -> Duration {
+        if !self.is_pause_toggle_requested() {
+            return Duration::ZERO;
+        }
+
+        info!(target: "commands_debug", "Pausing");
+        self.print("Paused. Press the pause hotkey again to resume");
+        let released_inputs = self.held_inputs.release_all(self.backend.as_mut());
+        let pause_start_time = Instant::now();
+
+        while !self.is_stop_requested() {
+            std::thread::sleep(Duration::from_millis(STOP_CHECK_INTERVAL_MS));
+
+            if self.is_pause_toggle_requested() {
+                info!(target: "commands_debug", "Resuming");
+                self.print("Resumed");
+
+                for input in released_inputs {
+                    self.held_inputs.press(self.backend.as_mut(), input);
+                }
+                break;
+            }
+        }
+
+        let paused_duration = pause_start_time.elapsed();
+        self.total_paused_duration += paused_duration;
+        if !self.pause_counts_toward_waits {
+            self.scheduled_time = self.scheduled_time.map(|scheduled_time| scheduled_time + paused_duration);
+        }
+
+        return paused_duration;
+    }
+
+    // When the last wait was meant to end, so time taken by the commands since then is made up for
+    // instead of adding up over a long run. Only waits and every: blocks use it
+    fn schedule_start_time(&self) -> Duration {
+        let now = self.backend.elapsed();
+        return match self.scheduled_time {
+            Some(scheduled_time) if now.saturating_sub(scheduled_time) <= Duration::from_millis(MAX_SCHEDULE_LAG_MS) => scheduled_time,
+            _ => now
+        };
+    }
+
+    fn sleep_on_schedule(&mut self, duration_ms: u64) {
+        let start_time = self.schedule_start_time();
+        self.sleep_until(start_time + Duration::from_millis(duration_ms));
+    }
+
+    // For the length of a hold, which should not be cut short by the commands before it
+    fn sleep(&mut self, duration_ms: u64) {
+        let now = self.backend.elapsed();
+        self.sleep_until(now + Duration::from_millis(duration_ms));
+    }
+
+    // Sleeps in small steps so that the emergency stop and pause can interrupt a long wait
+    fn sleep_until(&mut self, mut end_time: Duration) {
+        while !self.is_stop_requested() {
+            let paused_duration = self.pause_if_requested();
+            if !self.pause_counts_toward_waits {
+                end_time += paused_duration;
+            }
+
+            let now = self.backend.elapsed();
+            if now >= end_time {
+                break;
+            }
+
+            let mut sleep_duration = end_time - now;
+            // Nothing changes part way through a simulated wait except reaching the stop time, so
+            // it is not cut into pieces, and a dry run prints each wait once
+            if self.backend.is_simulated() {
+                if let Some(stop_time) = self.stop_conditions.stop_time {
+                    if stop_time > now {
+                        sleep_duration = sleep_duration.min(stop_time - now);
+                    }
+                }
+                self.backend.sleep(sleep_duration);
+                continue;
+            }
+
+            if self.busy_wait {
+                if sleep_duration <= Duration::from_millis(BUSY_WAIT_MS) {
+                    while self.backend.elapsed() < end_time {
+                        std::hint::spin_loop();
+                    }
+                    break;
+                }
+                sleep_duration -= Duration::from_millis(BUSY_WAIT_MS);
+            }
+            if self.emergency_stop.is_some() || self.pause_control.is_some() || self.stop_conditions.needs_polling() {
+                sleep_duration = sleep_duration.min(Duration::from_millis(STOP_CHECK_INTERVAL_MS));
+            }
+            self.backend.sleep(sleep_duration);
+        }
+
+        self.scheduled_time = Some(end_time);
+    }
+
+    fn print_debug_state(&self, script_line: &ScriptLine) {
+        self.print(&format!("line {}: {}", script_line.line_number, script_line.text));
+        self.print(&format!("    iteration: {}", self.iteration));
+        self.print(&format!("    elapsed: {:.3}s", self.backend.elapsed().as_secs_f64()));
+        self.print(&format!("    mouse location: {:?}", self.backend.mouse_location()));
+        self.print(&format!("    key_sequence delay: {} ms", self.key_sequence_delay_ms));
+        self.print(&format!("    held inputs: {:?}", self.held_inputs.held()));
+    }
+
+    // Pauses before the command when the debugger asks to, and takes debugger commands until told to go on
+    fn debug_before_command(&mut self, script_line: &ScriptLine) {
+        let should_pause = match &self.debugger {
+            Some(debugger) => debugger.should_pause(script_line),
+            None => false
+        };
+        if !should_pause {
+            return;
+        }
+
+        self.print_debug_state(script_line);
+        // Time spent in the debugger is not made up for by shortening the next wait
+        self.scheduled_time = None;
+
+        loop {
+            let debugger_command = read_debugger_command();
+
+            match &debugger_command {
+                DebuggerCommand::PrintScore => {
+                    match self.last_match_percentage {
+                        Some(match_percentage) => { self.print(&format!("Last screen comparison score: {}%", match_percentage * 100.0)); },
+                        None => { self.print("No screen comparison has been made yet"); }
+                    }
+                },
+                DebuggerCommand::Screenshot(output_file_path) => {
+                    match capture_screenshot(output_file_path) {
+                        Ok(_) => { self.print(&format!("Saved a screenshot to {}", output_file_path)); },
+                        Err(reason) => { self.print(&format!("Could not save a screenshot to {}: {}", output_file_path, reason)); }
+                    }
+                },
+                DebuggerCommand::PrintState => {
+                    self.print_debug_state(script_line);
+                },
+                DebuggerCommand::Help => {
+                    self.print(DEBUGGER_HELP);
+                },
+                _ => {
+                    if let Some(debugger) = &mut self.debugger {
+                        if debugger.handle_command(&debugger_command) {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn wait(&mut self, wait_time_ms: u64) {
+        let jittered_wait_time_ms = apply_jitter(&mut self.rng, wait_time_ms, self.wait_jitter_percentage);
+
+        let waited_ms = self.resolved_arguments.get("waited_ms").and_then(Value::as_u64).unwrap_or(0);
+        self.resolved_arguments.insert(String::from("waited_ms"), json!(waited_ms + jittered_wait_time_ms));
+
+        self.sleep_on_schedule(jittered_wait_time_ms);
+    }
+
+    // A comparison that could not be made, such as with a missing image file, counts as not matching
+    fn compare_for_command(&mut self, input_file_path: &String, start_x: i32, start_y: i32) -> f64 {
+        return match self.backend.compare_screen_to_image_file(input_file_path, start_x, start_y) {
+            Ok(match_percentage) => match_percentage,
+            Err(error_reason) => {
+                error!(target: "commands_debug", "Comparing {} at {} {} failed because {}", input_file_path, start_x, start_y, error_reason);
+                0.0
+            }
+        };
+    }
+
+    fn stop_if_comparisons_failed(&mut self, matched: bool) {
+        if let Some(stop_reason) = self.stop_conditions.record_comparison(matched) {
+            if self.stop_reason.is_none() {
+                info!(target: "commands_debug", "Stopping because {}", stop_reason);
+                self.stop_reason = Some(stop_reason);
+            }
+        }
+    }
+
+    fn trace_command(&mut self, script_line: &ScriptLine, command_start_time: Duration) {
+        let event_trace = match &mut self.event_trace {
+            Some(event_trace) => event_trace,
+            None => { return; }
+        };
+
+        let mut arguments = command_arguments(&script_line.command);
+        if let Value::Object(argument_map) = &mut arguments {
+            argument_map.append(&mut self.resolved_arguments);
+        }
+
+        let outcome = if self.stop_reason.is_some() { "stopped" } else { self.command_outcome };
+        let trace_event = TraceEvent {
+            timestamp_ms: command_start_time.as_secs_f64() * 1000.0,
+            source_file: event_trace.source_file(),
+            line: script_line.line_number,
+            iteration: self.iteration,
+            command: command_kind(&script_line.command),
+            arguments,
+            score: self.command_match_percentage,
+            outcome
+        };
+
+        if let Err(reason) = event_trace.write_event(&trace_event) {
+            error!(target: "commands_debug", "Failed to write to the event trace, no more events will be written: {}", reason);
+            self.event_trace = None;
+        }
+    }
+
+    fn type_key_sequence(&mut self, sequence: &str, delay_ms: u64) {
+        if delay_ms == 0 {
+            self.backend.key_sequence(sequence);
+            return;
+        }
+
+        // The delays between characters are timed from the first one, not from the last wait
+        self.scheduled_time = Some(self.backend.elapsed());
+        for (index, character) in sequence.chars().enumerate() {
+            if index > 0 {
+                self.wait(delay_ms);
+            }
+            if self.stop_reason.is_some() {
+                break;
+            }
+            self.backend.key_sequence(&character.to_string());
+        }
+    }
+
+    // Starts counting a new pass through the script, returning when it started
+    pub fn start_iteration(&mut self) -> Duration {
+        self.iteration += 1;
+        return self.backend.elapsed();
+    }
+
+    pub fn finish_iteration(&mut self, iteration_start_time: Duration) -> ExecutionResult {
+        if self.stop_reason.is_some() {
+            self.held_inputs.release_all(self.backend.as_mut());
+            return ExecutionResult::Stopped;
+        }
+
+        self.statistics.record_iteration(self.backend.elapsed().saturating_sub(iteration_start_time));
+
+        return ExecutionResult::Finished;
+    }
+
+    pub fn execute_commands(&mut self, script_lines: &[ScriptLine]) -> ExecutionResult {
+        let iteration_start_time = self.start_iteration();
+        let mut block_state = BlockState::new(script_lines);
+
+        while block_state.line_index < script_lines.len() {
+            let script_line = &script_lines[block_state.line_index];
+            block_state.line_index += 1;
+
+            if !self.execute_line(script_line, &mut block_state) {
+                break;
+            }
+        }
+
+        return self.finish_iteration(iteration_start_time);
+    }
+
+    // Runs one command on its own, for callers that decide what runs next themselves,
+    // like the embedded scripting engine. An every: block needs a whole script, so every: and end_every: do nothing here
+    pub fn execute_command(&mut self, script_line: &ScriptLine) -> ExecutionResult {
+        // The caller may have taken any amount of time since the last command, which a wait should not make up for
+        self.scheduled_time = None;
+        let mut block_state = BlockState::new(std::slice::from_ref(script_line));
+        block_state.line_index = 1;
+        self.execute_line(script_line, &mut block_state);
+
+        if self.stop_reason.is_some() {
+            self.held_inputs.release_all(self.backend.as_mut());
+            return ExecutionResult::Stopped;
+        }
+
+        return ExecutionResult::Finished;
+    }
+
+    // Returns false without running the command if the run has been stopped
+    fn execute_line(&mut self, script_line: &ScriptLine, block_state: &mut BlockState) -> bool {
+        self.pause_if_requested();
+        if self.is_stop_requested() {
+            return false;
+        }
+
+        if self.statistics_requested.swap(false, Ordering::Relaxed) {
+            self.print(&self.statistics().summary());
+        }
+
+        self.debug_before_command(script_line);
+        self.backend.command_started(self.iteration, script_line);
+        let command_start_time = self.backend.elapsed();
+        self.resolved_arguments.clear();
+        self.command_match_percentage = None;
+        self.command_outcome = "done";
+
+        match &script_line.command{
             ParsedCommand::LayoutKeyUse(key, button_action) => { 
                 match button_action {
                     ButtonAction::Press => {
-                        enigo.key_down(Key::Layout(*key));
+                        self.held_inputs.press(self.backend.as_mut(), HeldInput::Key(Key::Layout(*key)));
                     },
                     ButtonAction::Release => {
-                        enigo.key_up(Key::Layout(*key));
+                        self.held_inputs.release(self.backend.as_mut(), HeldInput::Key(Key::Layout(*key)));
                     },
                     ButtonAction::Click => {
-                        enigo.key_click(Key::Layout(*key));
+                        self.backend.key_click(Key::Layout(*key));
                     },
                     ButtonAction::None => { error!(target: "commands_debug", "This should not happen! Doing nothing"); }
                 }
@@ -26,52 +674,154 @@ pub fn execute_commands(command_vector: &Vec<ParsedCommand>) {
             ParsedCommand::FunctionKeyUse(key, button_action) => { 
                 match button_action {
                     ButtonAction::Press => {
-                        enigo.key_down(*key);
+                        self.held_inputs.press(self.backend.as_mut(), HeldInput::Key(*key));
                     },
                     ButtonAction::Release => {
-                        enigo.key_up(*key);
+                        self.held_inputs.release(self.backend.as_mut(), HeldInput::Key(*key));
                     },
                     ButtonAction::Click => {
-                        enigo.key_click(*key);
+                        self.backend.key_click(*key);
                     },
                     ButtonAction::None => { error!(target: "commands_debug", "This should not happen! Doing nothing"); }
                 } 
             },
             ParsedCommand::KeySequence(sequence) => {
-                enigo.key_sequence(sequence.as_str());
+                self.type_key_sequence(sequence.as_str(), self.key_sequence_delay_ms);
+            },
+            ParsedCommand::KeySequenceWithDelay{sequence, delay_ms} => {
+                self.type_key_sequence(sequence.as_str(), *delay_ms);
+            },
+            ParsedCommand::SetKeySequenceDelay(delay_ms) => {
+                self.key_sequence_delay_ms = *delay_ms;
             },
             ParsedCommand::MouseClick(mouse_button) => {
-                enigo.mouse_click(*mouse_button);
+                self.backend.mouse_click(*mouse_button);
             },
             ParsedCommand::MouseDown(mouse_button) => {
-                enigo.mouse_down(*mouse_button);
+                self.held_inputs.press(self.backend.as_mut(), HeldInput::MouseButton(*mouse_button));
             },
             ParsedCommand::MouseRelease(mouse_button) => {
-                enigo.mouse_up(*mouse_button);
+                self.held_inputs.release(self.backend.as_mut(), HeldInput::MouseButton(*mouse_button));
             },
             ParsedCommand::MouseMove{x, y} => {
-                enigo.mouse_move_to(*x, *y);
+                self.backend.mouse_move_to(*x, *y);
+            },
+            ParsedCommand::MouseMoveSmooth{x, y, movement} => {
+                let (start_x, start_y) = self.backend.mouse_location();
+                self.resolved_arguments.insert(String::from("start_x"), json!(start_x));
+                self.resolved_arguments.insert(String::from("start_y"), json!(start_y));
+
+                // The steps keep to their interval from the start of the movement, not from the last wait
+                self.scheduled_time = Some(self.backend.elapsed());
+                for (path_x, path_y) in generate_mouse_path(start_x, start_y, *x, *y, movement, &mut self.rng) {
+                    self.sleep_on_schedule(movement.step_interval_ms);
+                    if self.stop_reason.is_some() {
+                        break;
+                    }
+                    self.backend.mouse_move_to(path_x, path_y);
+                }
             },
             ParsedCommand::MouseMoveRelative{x, y} => {
-                enigo.mouse_move_relative(*x, *y);
+                self.backend.mouse_move_relative(*x, *y);
+            },
+            ParsedCommand::KeyHold{key, duration_ms} => {
+                self.held_inputs.press(self.backend.as_mut(), HeldInput::Key(*key));
+                self.sleep(*duration_ms);
+                self.held_inputs.release(self.backend.as_mut(), HeldInput::Key(*key));
+            },
+            ParsedCommand::MouseHold{mouse_button, duration_ms} => {
+                self.held_inputs.press(self.backend.as_mut(), HeldInput::MouseButton(*mouse_button));
+                self.sleep(*duration_ms);
+                self.held_inputs.release(self.backend.as_mut(), HeldInput::MouseButton(*mouse_button));
+            },
+            ParsedCommand::MouseDrag{mouse_button, start_x, start_y, end_x, end_y, duration_ms} => {
+                let steps = duration_ms / MOUSE_DRAG_STEP_INTERVAL_MS;
+
+                self.backend.mouse_move_to(*start_x, *start_y);
+                self.held_inputs.press(self.backend.as_mut(), HeldInput::MouseButton(*mouse_button));
+
+                self.scheduled_time = Some(self.backend.elapsed());
+                for (x, y) in interpolate_linear_path(*start_x, *start_y, *end_x, *end_y, steps) {
+                    self.sleep_on_schedule(MOUSE_DRAG_STEP_INTERVAL_MS);
+                    if self.stop_reason.is_some() {
+                        break;
+                    }
+                    self.backend.mouse_move_to(x, y);
+                }
+
+                self.held_inputs.release(self.backend.as_mut(), HeldInput::MouseButton(*mouse_button));
             },
             ParsedCommand::Wait(wait_time_ms) => {
-                let wait_duration = std::time::Duration::from_millis(*wait_time_ms);
-                std::thread::sleep(wait_duration);
+                self.wait(*wait_time_ms);
+            },
+            ParsedCommand::WaitRandom{min_ms, max_ms, distribution} => {
+                let wait_time_ms = sample_wait_ms(&mut self.rng, *min_ms, *max_ms, *distribution);
+                self.resolved_arguments.insert(String::from("sampled_ms"), json!(wait_time_ms));
+                self.wait(wait_time_ms);
+            },
+            ParsedCommand::Label(_) => {},
+            ParsedCommand::Breakpoint => {},
+            ParsedCommand::Every{interval_ms, times} => {
+                let start_time = self.schedule_start_time();
+                self.scheduled_time = Some(start_time);
+
+                if *times == Some(0) {
+                    block_state.line_index = block_state.every_block_ends.get(&(block_state.line_index - 1)).map_or(block_state.line_count, |end_line_index| end_line_index + 1);
+                }
+                else {
+                    block_state.every_loops.push(EveryLoop {
+                        first_line_index: block_state.line_index,
+                        interval: Duration::from_millis(*interval_ms),
+                        remaining_times: times.map(|times| times - 1),
+                        start_time
+                    });
+                }
+            },
+            ParsedCommand::EndEvery => {
+                if let Some(every_loop) = block_state.every_loops.last_mut() {
+                    if every_loop.remaining_times == Some(0) {
+                        block_state.every_loops.pop();
+                    }
+                    else {
+                        every_loop.remaining_times = every_loop.remaining_times.map(|remaining_times| remaining_times - 1);
+                        block_state.line_index = every_loop.first_line_index;
+
+                        // A body that took longer than the interval runs again straight away and the cadence restarts from there
+                        let next_start_time = every_loop.start_time + every_loop.interval;
+                        let now = self.backend.elapsed();
+                        if next_start_time > now {
+                            every_loop.start_time = next_start_time;
+                            self.sleep_until(next_start_time);
+                        }
+                        else {
+                            every_loop.start_time = now;
+                            self.scheduled_time = Some(now);
+                        }
+                    }
+                }
+            },
+            ParsedCommand::Exit(exit_code) => {
+                self.stop_reason = Some(StopReason::ExitCommand(*exit_code));
+                info!(target: "commands_debug", "Stopping because {}", StopReason::ExitCommand(*exit_code));
             },
             ParsedCommand::ScreenCompareLayoutKeyClick{layout_key, 
                 input_file_path, 
                 start_x,
                 start_y,
                 match_threshold} => {
-                
-                    let match_percentage = compare_screen_to_image_file(input_file_path, 
+            
+                    let match_percentage = self.compare_for_command(input_file_path, 
                         *start_x,
                         *start_y);
-                
+                    self.last_match_percentage = Some(match_percentage);
+                    self.statistics.record_comparison(script_line, match_percentage, *match_threshold <= (match_percentage * 100.0));
+                    self.command_match_percentage = Some(match_percentage);
+                    self.command_outcome = if *match_threshold <= (match_percentage * 100.0) { "matched" } else { "not_matched" };
+                    self.stop_if_comparisons_failed(*match_threshold <= (match_percentage * 100.0));
+            
                     if *match_threshold <= (match_percentage * 100.0) { 
                         info!(target: "commands_debug", "successful match_percentage = {}, match_threadhold_percentage = {}", (match_percentage * 100.0), match_threshold); 
-                        enigo.key_click(Key::Layout(*layout_key));
+                        self.backend.key_click(Key::Layout(*layout_key));
                     }
             },
             ParsedCommand::ScreenCompareFunctionKeyClick{function_key, 
@@ -79,16 +829,272 @@ pub fn execute_commands(command_vector: &Vec<ParsedCommand>) {
                 start_x,
                 start_y,
                 match_threshold} => {
-                
-                    let match_percentage = compare_screen_to_image_file(input_file_path, 
+            
+                    let match_percentage = self.compare_for_command(input_file_path, 
                         *start_x,
                         *start_y);
+                    self.last_match_percentage = Some(match_percentage);
+                    self.statistics.record_comparison(script_line, match_percentage, *match_threshold <= (match_percentage * 100.0));
+                    self.command_match_percentage = Some(match_percentage);
+                    self.command_outcome = if *match_threshold <= (match_percentage * 100.0) { "matched" } else { "not_matched" };
+                    self.stop_if_comparisons_failed(*match_threshold <= (match_percentage * 100.0));
 
                     if *match_threshold <= (match_percentage * 100.0) { 
                         info!(target: "commands_debug", "successful match_percentage = {}, match_threadhold_percentage = {}", (match_percentage * 100.0), match_threshold); 
-                        enigo.key_click(*function_key);
+                        self.backend.key_click(*function_key);
                     }
             }
         }
+
+        self.statistics.record_command(script_line, self.backend.elapsed().saturating_sub(command_start_time));
+        self.trace_command(script_line, command_start_time);
+
+        return true;
+    }
+}
+
+// Runs on normal completion and while unwinding from a panic, so nothing is left pressed
+impl Drop for CommandExecutor {
+    fn drop(&mut self) {
+        self.held_inputs.release_all(self.backend.as_mut());
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    // A dry run that counts how many times it was asked to sleep, and whose key clicks take click_duration
+    struct TestBackend {
+        backend: DryRunBackend,
+        sleep_count: Arc<AtomicUsize>,
+        click_duration: Duration
+    }
+
+    impl TestBackend {
+        fn new(click_duration: Duration) -> TestBackend {
+            return TestBackend {
+                backend: DryRunBackend::new(None, 100.0),
+                sleep_count: Arc::new(AtomicUsize::new(0)),
+                click_duration
+            };
+        }
+    }
+
+    impl InputBackend for TestBackend {
+        fn key_down(&mut self, key: enigo::Key) {
+            self.backend.key_down(key);
+        }
+
+        fn key_up(&mut self, key: enigo::Key) {
+            self.backend.key_up(key);
+        }
+
+        fn key_click(&mut self, key: enigo::Key) {
+            self.backend.key_click(key);
+            self.backend.sleep(self.click_duration);
+        }
+
+        fn key_sequence(&mut self, sequence: &str) {
+            self.backend.key_sequence(sequence);
+        }
+
+        fn mouse_down(&mut self, mouse_button: MouseButton) {
+            self.backend.mouse_down(mouse_button);
+        }
+
+        fn mouse_up(&mut self, mouse_button: MouseButton) {
+            self.backend.mouse_up(mouse_button);
+        }
+
+        fn mouse_click(&mut self, mouse_button: MouseButton) {
+            self.backend.mouse_click(mouse_button);
+        }
+
+        fn mouse_move_to(&mut self, x: i32, y: i32) {
+            self.backend.mouse_move_to(x, y);
+        }
+
+        fn mouse_move_relative(&mut self, x: i32, y: i32) {
+            self.backend.mouse_move_relative(x, y);
+        }
+
+        fn mouse_location(&self) -> (i32, i32) {
+            return self.backend.mouse_location();
+        }
+
+        fn compare_screen_to_image_file(&mut self, input_file_path: &String, start_x: i32, start_y: i32) -> Result<f64, CaptureError> {
+            return self.backend.compare_screen_to_image_file(input_file_path, start_x, start_y);
+        }
+
+        fn find_image_on_screen(&mut self, input_file_path: &String) -> Result<Option<(i32, i32)>, CaptureError> {
+            return self.backend.find_image_on_screen(input_file_path);
+        }
+
+        fn capture_screen_area(&mut self, start_x: i32, start_y: i32, width: u32, height: u32) -> Result<RgbaImage, CaptureError> {
+            return self.backend.capture_screen_area(start_x, start_y, width, height);
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.sleep_count.fetch_add(1, Ordering::Relaxed);
+            self.backend.sleep(duration);
+        }
+
+        fn elapsed(&self) -> Duration {
+            return self.backend.elapsed();
+        }
+
+        fn is_simulated(&self) -> bool {
+            return self.backend.is_simulated();
+        }
+    }
+
+    fn parse_script(lines: &[&str]) -> Vec<ScriptLine> {
+        return lines.iter().enumerate().map(|(line_index, line)| ScriptLine {
+            line_number: line_index + 1,
+            text: line.to_string(),
+            command: parse_command_from_line(&line.to_string())
+        }).collect();
+    }
+
+    #[test]
+    fn test_dry_run_simulates_waits() {
+        let script = parse_script(&["wait: 7200000", "mouse_drag: left 0 0 100 100 1000", "wait: 250"]);
+        let mut command_executor = CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+
+        assert_eq!(command_executor.execute_commands(&script), ExecutionResult::Finished);
+        assert_eq!(command_executor.elapsed(), Duration::from_millis(7201250));
+    }
+
+    #[test]
+    fn test_every_keeps_its_cadence() {
+        let script = parse_script(&["every: 100 3", "wait: 30", "end_every:", "wait: 5"]);
+        let mut command_executor = CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+
+        assert_eq!(command_executor.execute_commands(&script), ExecutionResult::Finished);
+        assert_eq!(command_executor.elapsed(), Duration::from_millis(235));
+    }
+
+    #[test]
+    fn test_every_restarts_after_a_slow_body() {
+        let script = parse_script(&["every: 100 2", "wait: 150", "end_every:"]);
+        let mut command_executor = CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+
+        assert_eq!(command_executor.execute_commands(&script), ExecutionResult::Finished);
+        assert_eq!(command_executor.elapsed(), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_run_repeats_for_a_duration() {
+        let script = Script::parse("wait: 300").unwrap();
+        let mut command_executor = CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+        let run_options = RunOptions{repeat: Repeat::For(Duration::from_millis(1000)), seed: Some(0), ..Default::default()};
+
+        assert_eq!(command_executor.run(&script, run_options), ExecutionResult::Finished);
+        assert_eq!(command_executor.statistics().iterations.completed, 4);
+    }
+
+    #[test]
+    fn test_busy_wait_is_ignored_in_a_dry_run() {
+        let script = Script::parse("wait: 300").unwrap();
+        let mut command_executor = CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+        let run_options = RunOptions{repeat: Repeat::Times(2), busy_wait: true, ..Default::default()};
+
+        assert_eq!(command_executor.run(&script, run_options), ExecutionResult::Finished);
+        assert_eq!(command_executor.elapsed(), Duration::from_millis(600));
+    }
+
+    #[test]
+    fn test_dry_run_waits_are_not_cut_into_pieces() {
+        let script = Script::parse("wait: 7200000").unwrap();
+        let backend = TestBackend::new(Duration::ZERO);
+        let sleep_count = Arc::clone(&backend.sleep_count);
+        let mut command_executor = CommandExecutor::new(Box::new(backend));
+        let mut stop_conditions = StopConditions::new();
+        stop_conditions.max_consecutive_failed_comparisons = Some(3);
+        stop_conditions.stop_requested = Some(Arc::new(AtomicBool::new(false)));
+
+        assert_eq!(command_executor.run(&script, RunOptions{stop_conditions, ..Default::default()}), ExecutionResult::Finished);
+        assert_eq!(sleep_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_waits_make_up_for_slow_commands_but_holds_do_not() {
+        let script = parse_script(&["wait: 100", "key: a click", "wait: 500", "key: a click", "key_hold: w 300", "key: a click", "mouse_drag: left 0 0 100 100 200"]);
+        let mut command_executor = CommandExecutor::new(Box::new(TestBackend::new(Duration::from_millis(400))));
+
+        assert_eq!(command_executor.execute_commands(&script), ExecutionResult::Finished);
+        assert_eq!(command_executor.elapsed(), Duration::from_millis(100 + 400 + 100 + 400 + 300 + 400 + 200));
+    }
+
+    #[test]
+    fn test_wait_after_a_gap_between_commands_is_not_shortened() {
+        let script = parse_script(&["wait: 500", "key: a click", "every: 1000 2", "end_every:"]);
+        let mut command_executor = CommandExecutor::new(Box::new(TestBackend::new(Duration::from_millis(400))));
+
+        for script_line in &script[0..2] {
+            assert_eq!(command_executor.execute_command(script_line), ExecutionResult::Finished);
+        }
+        assert_eq!(command_executor.execute_command(&script[0]), ExecutionResult::Finished);
+        assert_eq!(command_executor.elapsed(), Duration::from_millis(500 + 400 + 500));
+
+        // Likewise for a new run, and for the first interval of an every: block
+        command_executor.start_run(RunOptions::default());
+        assert_eq!(command_executor.execute_commands(&script[1..]), ExecutionResult::Finished);
+        assert_eq!(command_executor.elapsed(), Duration::from_millis(1400 + 400 + 1000));
+    }
+
+    #[test]
+    fn test_dry_run_wait_stops_at_the_stop_time() {
+        let script = Script::parse("wait: 7200000").unwrap();
+        let mut command_executor = CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+        let mut stop_conditions = StopConditions::new();
+        stop_conditions.stop_time = Some(Duration::from_secs(60));
+
+        assert_eq!(command_executor.run(&script, RunOptions{stop_conditions, ..Default::default()}), ExecutionResult::Stopped);
+        assert_eq!(command_executor.stop_reason(), Some(StopReason::TimeReached));
+        assert_eq!(command_executor.elapsed(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_dry_run_actions_and_statistics_go_to_the_output() {
+        let script = parse_script(&["wait: 10", "key: a click"]);
+        let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let output_lines = Arc::clone(&lines);
+        let output: OutputSink = Arc::new(move |line: &str| output_lines.lock().unwrap().push(String::from(line)));
+        let mut command_executor = CommandExecutor::new(Box::new(DryRunBackend::with_output(None, 100.0, Arc::clone(&output))));
+        command_executor.set_output(output);
+        command_executor.statistics_requested().store(true, Ordering::Relaxed);
+
+        assert_eq!(command_executor.execute_commands(&script), ExecutionResult::Finished);
+        let lines = lines.lock().unwrap();
+        assert!(lines[0].starts_with("Run statistics"));
+        assert!(lines.iter().any(|line| line.ends_with("line 2: key: a click")));
+        assert!(lines.iter().any(|line| line.ends_with("key_click Layout('a')")));
+    }
+
+    #[test]
+    fn test_exit_command_stops_with_its_code() {
+        let script = parse_script(&["wait: 10", "exit: 4", "wait: 10"]);
+        let mut command_executor = CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+
+        assert_eq!(command_executor.execute_commands(&script), ExecutionResult::Stopped);
+        assert_eq!(command_executor.stop_reason(), Some(StopReason::ExitCommand(4)));
+        assert_eq!(command_executor.elapsed(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_run_after_a_stopped_run() {
+        let mut command_executor = CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+
+        let exiting_script = Script::parse("wait: 10\nexit: 4").unwrap();
+        assert_eq!(command_executor.run(&exiting_script, RunOptions::default()), ExecutionResult::Stopped);
+        assert_eq!(command_executor.stop_reason(), Some(StopReason::ExitCommand(4)));
+
+        let script = Script::parse("wait: 10\nkey: a click").unwrap();
+        assert_eq!(command_executor.run(&script, RunOptions{repeat: Repeat::Times(2), ..Default::default()}), ExecutionResult::Finished);
+        assert_eq!(command_executor.stop_reason(), None);
+        assert_eq!(command_executor.elapsed(), Duration::from_millis(10 + 10 + 10));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,243 @@
+// A line protocol over a Unix domain socket, for programs that decide what to press as they go.
+// Each line sent is one command in the script syntax, run straight away, and answered with a line of
+// its own: "ok", "error <message>" when it could not be read, or "stopped <reason>" before the
+// connection is closed. Connections are served one at a time, each with its own executor, so
+// anything a client leaves held is released when it disconnects
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use log::{error, info};
+
+use crate::command_executor::*;
+use crate::command_types::*;
+use crate::emergency_stop::*;
+use crate::held_inputs::HeldInputs;
+use crate::input_backend::*;
+use crate::script::is_comment;
+
+// Only the user running the program can connect, since anyone who can connect can press keys
+const SOCKET_PERMISSIONS: u32 = 0o600;
+const PRIVATE_DIRECTORY_PERMISSIONS: u32 = 0o700;
+
+pub struct CommandSocketOptions {
+    pub path: PathBuf,
+    pub dry_run: bool,
+    pub dry_run_match: f64,
+    pub stop_hotkey: Option<Hotkey>,
+    pub corner_failsafe: bool,
+    // Every connection presses into this, so a stop signal handler can release whatever a client left held
+    pub held_inputs: HeldInputs,
+    // Where the actions of a dry run go. Without one they are logged
+    pub output: Option<OutputSink>
+}
+
+fn reply(writer: &mut dyn Write, message: &str) -> io::Result<()> {
+    writer.write_all(format!("{}\n", message).as_bytes())?;
+    return writer.flush();
+}
+
+// Runs each line from reader as it arrives, until the reader ends or the executor is stopped
+pub fn stream_commands(reader: &mut dyn BufRead, writer: &mut dyn Write, executor: &mut CommandExecutor) -> io::Result<()> {
+    let mut line_number = 0;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        line_number += 1;
+
+        let text = line.trim().to_string();
+        if text.is_empty() || is_comment(&text) {
+            reply(writer, "ok")?;
+            continue;
+        }
+
+        let command = match try_parse_command_from_line(&text) {
+            Some(ParsedCommand::Every{..}) | Some(ParsedCommand::EndEvery) => {
+                reply(writer, "error every: blocks need a whole script")?;
+                continue;
+            },
+            Some(command) => command,
+            None => {
+                reply(writer, &format!("error Line {} is not formatted properly: {}", line_number, text))?;
+                continue;
+            }
+        };
+
+        let script_line = ScriptLine{line_number, text, command};
+        if executor.execute_command(&script_line) == ExecutionResult::Stopped {
+            let stop_reason = executor.stop_reason().map_or(String::from("unknown"), |stop_reason| stop_reason.to_string());
+            return reply(writer, &format!("stopped {}", stop_reason));
+        }
+
+        reply(writer, "ok")?;
+    }
+}
+
+fn create_executor(options: &CommandSocketOptions) -> Result<CommandExecutor, String> {
+    if options.dry_run {
+        let dry_run_backend = match &options.output {
+            Some(output) => DryRunBackend::with_output(None, options.dry_run_match, output.clone()),
+            None => DryRunBackend::new(None, options.dry_run_match)
+        };
+        return Ok(CommandExecutor::new(Box::new(dry_run_backend)));
+    }
+
+    let emergency_stop = EmergencyStop::new(options.stop_hotkey.clone(), options.corner_failsafe)?;
+    let mut executor = CommandExecutor::with_held_inputs(Box::new(EnigoBackend::new()), options.held_inputs.clone());
+    executor.start_run(RunOptions {
+        emergency_stop: Some(emergency_stop),
+        ..Default::default()
+    });
+
+    return Ok(executor);
+}
+
+// A socket file left behind by an earlier run is replaced, but nothing else is
+fn remove_stale_socket(options: &CommandSocketOptions) -> Result<(), String> {
+    return match fs::symlink_metadata(&options.path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(&options.path)
+            .map_err(|error_reason| format!("Could not remove the old socket {}: {}", options.path.display(), error_reason)),
+        Ok(_) => Err(format!("{} already exists and is not a socket", options.path.display())),
+        Err(_) => Ok(())
+    };
+}
+
+fn bind_privately(private_directory: &Path, path: &Path) -> Result<UnixListener, String> {
+    let private_path = private_directory.join("socket");
+    let listener = UnixListener::bind(&private_path)
+        .map_err(|error_reason| format!("Could not listen on {}: {}", path.display(), error_reason))?;
+    fs::set_permissions(&private_path, fs::Permissions::from_mode(SOCKET_PERMISSIONS))
+        .map_err(|error_reason| format!("Could not set the permissions of {}: {}", path.display(), error_reason))?;
+    fs::rename(&private_path, path)
+        .map_err(|error_reason| format!("Could not move the socket to {}: {}", path.display(), error_reason))?;
+
+    return Ok(listener);
+}
+
+// The socket is made in a directory only this user can enter and moved into place once its permissions
+// are set, so there is no moment when someone else could connect to it
+fn bind(path: &Path) -> Result<UnixListener, String> {
+    let file_name = path.file_name().ok_or_else(|| format!("{} is not a file path", path.display()))?;
+    let private_directory = path.with_file_name(format!(".{}.{}", file_name.to_string_lossy(), std::process::id()));
+    fs::DirBuilder::new().mode(PRIVATE_DIRECTORY_PERMISSIONS).create(&private_directory)
+        .map_err(|error_reason| format!("Could not create {}: {}", private_directory.display(), error_reason))?;
+
+    let bind_result = bind_privately(&private_directory, path);
+    let _ = fs::remove_file(private_directory.join("socket"));
+    let _ = fs::remove_dir(&private_directory);
+
+    return bind_result;
+}
+
+// Handles connections until the process is stopped
+pub fn listen(options: &CommandSocketOptions) -> Result<(), String> {
+    remove_stale_socket(options)?;
+    let listener = bind(&options.path)?;
+    info!(target: "commands_debug", "Command socket listening on {}", options.path.display());
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error_reason) => {
+                error!(target: "commands_debug", "Failed to accept a command socket connection: {}", error_reason);
+                continue;
+            }
+        };
+
+        let mut reader = match stream.try_clone() {
+            Ok(read_stream) => BufReader::new(read_stream),
+            Err(error_reason) => {
+                error!(target: "commands_debug", "Failed to read from a command socket connection: {}", error_reason);
+                continue;
+            }
+        };
+        let mut writer = stream;
+        // Commands are not run without the emergency stop that was asked for
+        let mut executor = match create_executor(options) {
+            Ok(executor) => executor,
+            Err(error_reason) => {
+                error!(target: "commands_debug", "{}", error_reason);
+                let _ = reply(&mut writer, &format!("error {}", error_reason));
+                continue;
+            }
+        };
+
+        info!(target: "commands_debug", "Command socket client connected");
+        if let Err(error_reason) = stream_commands(&mut reader, &mut writer, &mut executor) {
+            error!(target: "commands_debug", "Command socket connection failed: {}", error_reason);
+        }
+        info!(target: "commands_debug", "Command socket client disconnected");
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::io::Cursor;
+    use std::os::unix::net::UnixStream;
+
+    fn dry_run_executor() -> CommandExecutor {
+        return CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+    }
+
+    fn stream_text(text: &str) -> String {
+        let mut output: Vec<u8> = vec![];
+        stream_commands(&mut Cursor::new(text), &mut output, &mut dry_run_executor()).unwrap();
+        return String::from_utf8(output).unwrap();
+    }
+
+    #[test]
+    fn test_stream_commands() {
+        assert_eq!(stream_text("mouse_move: 10 20\n\n# comment\nkey: a click\n"), "ok\nok\nok\nok\n");
+        assert_eq!(stream_text("wait: soon\nevery: 100\nwait: 5"), "error Line 1 is not formatted properly: wait: soon\nerror every: blocks need a whole script\nok\n");
+        assert_eq!(stream_text("exit: 4\nwait: 5\n"), "stopped an exit command ran with code 4\n");
+    }
+
+    #[test]
+    fn test_stream_held_keys_released_on_disconnect() {
+        let mut executor = dry_run_executor();
+        let held_inputs = executor.held_inputs();
+        let mut output: Vec<u8> = vec![];
+
+        stream_commands(&mut Cursor::new("key: a press\n"), &mut output, &mut executor).unwrap();
+        assert_eq!(held_inputs.held().len(), 1);
+
+        drop(executor);
+        assert!(held_inputs.held().is_empty());
+    }
+
+    #[test]
+    fn test_listen() {
+        let path = std::env::temp_dir().join(format!("scriptable_input_simulator_test_{}.sock", std::process::id()));
+        let options = CommandSocketOptions{path: path.clone(), dry_run: true, dry_run_match: 100.0, stop_hotkey: None, corner_failsafe: false, held_inputs: HeldInputs::new(), output: None};
+        std::thread::spawn(move || listen(&options));
+
+        let stream = loop {
+            if let Ok(stream) = UnixStream::connect(&path) {
+                break stream;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, SOCKET_PERMISSIONS);
+        assert!(!path.with_file_name(format!(".{}.{}", path.file_name().unwrap().to_string_lossy(), std::process::id())).exists());
+
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut reply_line = String::new();
+        for (line, expected_reply) in [("mouse_move: 1 2\n", "ok\n"), ("nothing\n", "error Line 2 is not formatted properly: nothing\n")] {
+            writer.write_all(line.as_bytes()).unwrap();
+            reply_line.clear();
+            reader.read_line(&mut reply_line).unwrap();
+            assert_eq!(reply_line, expected_reply);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
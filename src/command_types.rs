@@ -1,26 +1,68 @@
+use std::fmt;
 use enigo::*;
 use phf::phf_map;
 use log::{error, info};
+use serde::{Serialize, Deserialize};
 
-#[derive(PartialEq, Debug)]
+use crate::mouse_path::*;
+use crate::timing::*;
+use crate::stop_conditions::is_valid_exit_code;
+
+// Reads a number from a command, failing the command instead of panicking when it is not one
+macro_rules! parse_number {
+    ($number_string:expr, $number_type:ty) => {
+        match $number_string.parse::<$number_type>() {
+            Ok(number) => number,
+            Err(_) => { return (ParsedCommand::Wait(1), ParseResult::Fail); }
+        }
+    };
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ButtonAction {
     Press,
     Release,
     Click,
     None
 }
+
+// Structured scripts name each command by its variant, like {"mouse_move": {"x": 10, "y": 20}}.
+// Keys and mouse buttons are written by the names the text format uses
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ParsedCommand {
     LayoutKeyUse(char, ButtonAction),
-    FunctionKeyUse(enigo::Key, ButtonAction),
+    FunctionKeyUse(#[serde(with = "key_serde")] enigo::Key, ButtonAction),
     KeySequence(String),
-    MouseClick(MouseButton),
-    MouseDown(MouseButton),
-    MouseRelease(MouseButton),
+    KeySequenceWithDelay{sequence: String, delay_ms: u64},
+    SetKeySequenceDelay(u64),
+    MouseClick(#[serde(with = "mouse_button_serde")] MouseButton),
+    MouseDown(#[serde(with = "mouse_button_serde")] MouseButton),
+    MouseRelease(#[serde(with = "mouse_button_serde")] MouseButton),
     MouseMove{x: i32, y: i32},
+    MouseMoveSmooth{x: i32, y: i32, movement: MouseMovement},
     MouseMoveRelative{x: i32, y: i32},
+    KeyHold{#[serde(with = "key_serde")] key: enigo::Key, duration_ms: u64},
+    MouseHold{#[serde(with = "mouse_button_serde")] mouse_button: MouseButton, duration_ms: u64},
+    MouseDrag{#[serde(with = "mouse_button_serde")] mouse_button: MouseButton, start_x: i32, start_y: i32, end_x: i32, end_y: i32, duration_ms: u64},
     Wait(u64),
+    WaitRandom{min_ms: u64, max_ms: u64, #[serde(default)] distribution: WaitDistribution},
+    Label(String),
+    Breakpoint,
+    Exit(i32),
+    // Runs the lines up to the matching EndEvery once every interval_ms, times times or until stopped
+    Every{interval_ms: u64, #[serde(default)] times: Option<u64>},
+    EndEvery,
     ScreenCompareLayoutKeyClick{layout_key: char, input_file_path: String, start_x: i32, start_y: i32, match_threshold: f64},
-    ScreenCompareFunctionKeyClick{function_key: enigo::Key, input_file_path: String, start_x: i32, start_y: i32, match_threshold: f64}
+    ScreenCompareFunctionKeyClick{#[serde(with = "key_serde")] function_key: enigo::Key, input_file_path: String, start_x: i32, start_y: i32, match_threshold: f64}
+}
+
+// A parsed command along with where it came from in the script
+pub struct ScriptLine {
+    pub line_number: usize,
+    pub text: String,
+    pub command: ParsedCommand
 }
 
 enum ParseResult {
@@ -71,17 +113,51 @@ static STR_TO_ENIGO_KEY_MAP: phf::Map<&str, enigo::Key> = phf_map! {
     "up_arrow" => Key::UpArrow
 };
 
+// Key names are not case sensitive, so Tab and TAB are tab
+fn lookup_function_key(name: &str) -> Option<enigo::Key> {
+    return STR_TO_ENIGO_KEY_MAP.get(name.to_lowercase().as_str()).copied();
+}
+
 
 impl ParsedCommand {
     fn parse_key_sequence(cmd_string: &str) -> (ParsedCommand, ParseResult) {
-        if !cmd_string.is_empty()
-        {
-            return (ParsedCommand::KeySequence(String::from(cmd_string)), ParseResult::Success);
+        return (ParsedCommand::KeySequence(String::from(cmd_string)), ParseResult::Success);
+    }
+
+    // The typing speed applies to this sequence only, and the rest of the line is typed. It is a
+    // command of its own so that key_sequence: types text starting with delay= or cpm= as written
+    fn parse_key_sequence_delay(cmd_string: &str) -> (ParsedCommand, ParseResult) {
+        let (option, sequence) = cmd_string.split_once(" ").unwrap_or((cmd_string, ""));
+        if let Some(delay_ms) = ParsedCommand::parse_typing_speed_option(option) {
+            return (ParsedCommand::KeySequenceWithDelay{sequence: String::from(sequence), delay_ms}, ParseResult::Success);
         }
 
         return (ParsedCommand::Wait(1), ParseResult::Fail);
     }
 
+    fn parse_key_sequence_speed(cmd_string: &str) -> (ParsedCommand, ParseResult) {
+        if let Some(delay_ms) = ParsedCommand::parse_typing_speed_option(cmd_string) {
+            return (ParsedCommand::SetKeySequenceDelay(delay_ms), ParseResult::Success);
+        }
+
+        return (ParsedCommand::Wait(1), ParseResult::Fail);
+    }
+
+    fn parse_typing_speed_option(option: &str) -> Option<u64> {
+        if let Some(delay) = option.strip_prefix("delay=") {
+            return delay.parse::<u64>().ok();
+        }
+        else if let Some(characters_per_minute) = option.strip_prefix("cpm=") {
+            if let Ok(characters_per_minute) = characters_per_minute.parse::<u64>() {
+                if characters_per_minute > 0 {
+                    return Some(60_000 / characters_per_minute);
+                }
+            }
+        }
+
+        return None;
+    }
+
     fn parse_key(cmd_string: &str) -> (ParsedCommand, ParseResult) {
         let split_line_key_and_action: Vec<&str> = cmd_string.split(" ").collect();
 
@@ -108,8 +184,8 @@ impl ParsedCommand {
                     if let Ok(parsed_char) = parse_char_result {
                         return (ParsedCommand::LayoutKeyUse(parsed_char, button_action), ParseResult::Success);
                     }
-                    else if let Some(enigo_key) = STR_TO_ENIGO_KEY_MAP.get(split_line_key_and_action[0]) {
-                        return (ParsedCommand::FunctionKeyUse(*enigo_key, button_action), ParseResult::Success);
+                    else if let Some(enigo_key) = lookup_function_key(split_line_key_and_action[0]) {
+                        return (ParsedCommand::FunctionKeyUse(enigo_key, button_action), ParseResult::Success);
                     }
                 }
             }
@@ -118,8 +194,62 @@ impl ParsedCommand {
         return (ParsedCommand::Wait(1), ParseResult::Fail);
     }
 
+    fn parse_key_hold(cmd_string: &str) -> (ParsedCommand, ParseResult) {
+        let split_line: Vec<&str> = cmd_string.split(" ").collect();
+
+        if split_line.len() == 2 {
+            let duration_ms = parse_number!(split_line[1], u64);
+
+            if let Ok(layout_key) = split_line[0].parse::<char>() {
+                return (ParsedCommand::KeyHold{key: Key::Layout(layout_key), duration_ms}, ParseResult::Success);
+            }
+            else if let Some(function_key) = lookup_function_key(split_line[0]) {
+                return (ParsedCommand::KeyHold{key: function_key, duration_ms}, ParseResult::Success);
+            }
+        }
+
+        return (ParsedCommand::Wait(1), ParseResult::Fail);
+    }
+
+    fn parse_mouse_hold(cmd_string: &str) -> (ParsedCommand, ParseResult) {
+        let split_line: Vec<&str> = cmd_string.split(" ").collect();
+
+        if split_line.len() == 2 {
+            let mouse_button = match split_line[0] {
+                "left" => MouseButton::Left,
+                "right" => MouseButton::Right,
+                "middle" => MouseButton::Middle,
+                _ => { return (ParsedCommand::Wait(1), ParseResult::Fail); }
+            };
+            let duration_ms = parse_number!(split_line[1], u64);
+
+            return (ParsedCommand::MouseHold{mouse_button, duration_ms}, ParseResult::Success);
+        }
+
+        return (ParsedCommand::Wait(1), ParseResult::Fail);
+    }
+
     fn parse_wait(cmd_string: &str) -> (ParsedCommand, ParseResult) {
-        let wait_time = cmd_string.parse::<u64>().expect("");
+        let split_line: Vec<&str> = cmd_string.split(" ").collect();
+
+        if let Some((min_string, max_string)) = split_line[0].split_once("..") {
+            let min_ms = parse_number!(min_string, u64);
+            let max_ms = parse_number!(max_string, u64);
+
+            let distribution = match split_line.get(1) {
+                None | Some(&"uniform") => WaitDistribution::Uniform,
+                Some(&"normal") => WaitDistribution::Normal,
+                _ => { return (ParsedCommand::Wait(1), ParseResult::Fail); }
+            };
+
+            if split_line.len() <= 2 && min_ms <= max_ms {
+                return (ParsedCommand::WaitRandom{min_ms, max_ms, distribution}, ParseResult::Success);
+            }
+
+            return (ParsedCommand::Wait(1), ParseResult::Fail);
+        }
+
+        let wait_time = parse_number!(cmd_string, u64);
 
         return (ParsedCommand::Wait(wait_time), ParseResult::Success);
     }
@@ -173,21 +303,64 @@ impl ParsedCommand {
         let split_line_coordinates: Vec<&str> = cmd_string.split(" ").collect();
 
         if split_line_coordinates.len() == 2 {
-            let x = split_line_coordinates[0].parse::<i32>().expect("");
-            let y = split_line_coordinates[1].parse::<i32>().expect("");
+            let x = parse_number!(split_line_coordinates[0], i32);
+            let y = parse_number!(split_line_coordinates[1], i32);
 
             return (ParsedCommand::MouseMove{x, y}, ParseResult::Success);
         }
+        else if split_line_coordinates.len() > 2 {
+            let x = parse_number!(split_line_coordinates[0], i32);
+            let y = parse_number!(split_line_coordinates[1], i32);
+            let duration_ms = parse_number!(split_line_coordinates[2], u64);
+
+            if let Some(movement) = ParsedCommand::parse_mouse_movement_options(duration_ms, &split_line_coordinates[3..]) {
+                return (ParsedCommand::MouseMoveSmooth{x, y, movement}, ParseResult::Success);
+            }
+        }
 
         return (ParsedCommand::Wait(1), ParseResult::Fail);
     }
 
+    fn parse_mouse_movement_options(duration_ms: u64, options: &[&str]) -> Option<MouseMovement> {
+        let mut movement = MouseMovement{duration_ms,
+                                         path_type: MousePathType::Linear,
+                                         jitter: 0,
+                                         overshoot: 0,
+                                         step_interval_ms: DEFAULT_MOUSE_STEP_INTERVAL_MS};
+
+        for option in options {
+            if *option == "linear" {
+                movement.path_type = MousePathType::Linear;
+            }
+            else if *option == "eased" {
+                movement.path_type = MousePathType::Eased;
+            }
+            else if *option == "bezier" {
+                movement.path_type = MousePathType::Bezier;
+            }
+            else if let Some(jitter) = option.strip_prefix("jitter=") {
+                movement.jitter = jitter.parse::<i32>().ok()?;
+            }
+            else if let Some(overshoot) = option.strip_prefix("overshoot=") {
+                movement.overshoot = overshoot.parse::<i32>().ok()?;
+            }
+            else if let Some(rate) = option.strip_prefix("rate=") {
+                movement.step_interval_ms = rate.parse::<u64>().ok()?;
+            }
+            else {
+                return None;
+            }
+        }
+
+        return Some(movement);
+    }
+
     fn parse_mouse_move_relative(cmd_string: &str) -> (ParsedCommand, ParseResult) {
         let split_line_coordinates: Vec<&str> = cmd_string.split(" ").collect();
 
         if split_line_coordinates.len() == 2 {
-            let x = split_line_coordinates[0].parse::<i32>().expect("");
-            let y = split_line_coordinates[1].parse::<i32>().expect("");
+            let x = parse_number!(split_line_coordinates[0], i32);
+            let y = parse_number!(split_line_coordinates[1], i32);
 
             return (ParsedCommand::MouseMoveRelative{x, y}, ParseResult::Success);
         }
@@ -195,15 +368,77 @@ impl ParsedCommand {
         return (ParsedCommand::Wait(1), ParseResult::Fail);
     }
 
+    fn parse_mouse_drag(cmd_string: &str) -> (ParsedCommand, ParseResult) {
+        let split_line: Vec<&str> = cmd_string.split(" ").collect();
+
+        if split_line.len() == 6 {
+            let mouse_button = match split_line[0] {
+                "left" => MouseButton::Left,
+                "right" => MouseButton::Right,
+                "middle" => MouseButton::Middle,
+                _ => { return (ParsedCommand::Wait(1), ParseResult::Fail); }
+            };
+
+            let start_x = parse_number!(split_line[1], i32);
+            let start_y = parse_number!(split_line[2], i32);
+            let end_x = parse_number!(split_line[3], i32);
+            let end_y = parse_number!(split_line[4], i32);
+            let duration_ms = parse_number!(split_line[5], u64);
+
+            return (ParsedCommand::MouseDrag{mouse_button, start_x, start_y, end_x, end_y, duration_ms}, ParseResult::Success);
+        }
+
+        return (ParsedCommand::Wait(1), ParseResult::Fail);
+    }
+
+    fn parse_label(cmd_string: &str) -> (ParsedCommand, ParseResult) {
+        let label = cmd_string.trim();
+
+        if !label.is_empty() && !label.contains(" ") {
+            return (ParsedCommand::Label(String::from(label)), ParseResult::Success);
+        }
+
+        return (ParsedCommand::Wait(1), ParseResult::Fail);
+    }
+
+    fn parse_every(cmd_string: &str) -> (ParsedCommand, ParseResult) {
+        let split_line: Vec<&str> = cmd_string.split(" ").collect();
+
+        if split_line.len() <= 2 {
+            let interval_ms = parse_number!(split_line[0], u64);
+            let times = match split_line.get(1) {
+                Some(times) => Some(parse_number!(times, u64)),
+                None => None
+            };
+
+            if interval_ms > 0 {
+                return (ParsedCommand::Every{interval_ms, times}, ParseResult::Success);
+            }
+        }
+
+        return (ParsedCommand::Wait(1), ParseResult::Fail);
+    }
+
+    fn parse_exit(cmd_string: &str) -> (ParsedCommand, ParseResult) {
+        if let Ok(exit_code) = cmd_string.trim().parse::<i32>() {
+            if is_valid_exit_code(exit_code) {
+                return (ParsedCommand::Exit(exit_code), ParseResult::Success);
+            }
+            error!(target: "commands_debug", "Exit code {} is reserved or out of range, use 4 to 9 or 16 to 255 other than 101 and 130", exit_code);
+        }
+
+        return (ParsedCommand::Wait(1), ParseResult::Fail);
+    }
+
     fn parse_screen_compare_key_click(cmd_string: &str) -> (ParsedCommand, ParseResult) {
         let split_line: Vec<&str> = cmd_string.split(" ").collect();
 
         info!(target: "commands_debug", "parse_screen_compare_key_click: Number of Tokens = {}", split_line.len());
 
-        if split_line.len() >= 4 {
-            let start_x = split_line[1].parse::<i32>().expect("start_x read failure");
-            let start_y = split_line[2].parse::<i32>().expect("start_y read failure");
-            let match_threshold = split_line[3].parse::<f64>().expect("match_threshold read failure");
+        if split_line.len() >= 5 {
+            let start_x = parse_number!(split_line[1], i32);
+            let start_y = parse_number!(split_line[2], i32);
+            let match_threshold = parse_number!(split_line[3], f64);
 
             // Handle in case of an input path which includes spaces
             let mut input_file_path: String = split_line[4].to_string();
@@ -221,9 +456,9 @@ impl ParsedCommand {
                                                                     start_y,
                                                                     match_threshold}, ParseResult::Success);
             }
-            else if let Some(function_key) = STR_TO_ENIGO_KEY_MAP.get(split_line[0]) {
+            else if let Some(function_key) = lookup_function_key(split_line[0]) {
 
-                return (ParsedCommand::ScreenCompareFunctionKeyClick{function_key: *function_key, 
+                return (ParsedCommand::ScreenCompareFunctionKeyClick{function_key, 
                                                                         input_file_path, 
                                                                         start_x, 
                                                                         start_y,
@@ -235,6 +470,21 @@ impl ParsedCommand {
     }
 
     fn parse_command_substring(line: &String) -> (ParsedCommand, ParseResult) {
+        // Lines inside blocks are indented
+        let line = line.trim_start();
+
+        // breakpoint and end_every have no parameters, so there is nothing after them to split off
+        if line.trim_end() == "breakpoint:" {
+            return (ParsedCommand::Breakpoint, ParseResult::Success);
+        }
+        if line.trim_end() == "end_every:" {
+            return (ParsedCommand::EndEvery, ParseResult::Success);
+        }
+        // Typing nothing is written without the space after the colon
+        if line.trim_end() == "key_sequence:" {
+            return (ParsedCommand::KeySequence(String::new()), ParseResult::Success);
+        }
+
         let mut parse_fn: fn(&str) -> (ParsedCommand, ParseResult) = ParsedCommand::parse_wait;
         let mut beginning_sequence: &str = "";
         
@@ -242,10 +492,26 @@ impl ParsedCommand {
             parse_fn = ParsedCommand::parse_key_sequence;
             beginning_sequence = "key_sequence: ";
         }
+        else if line.starts_with("key_sequence_delay: ") {
+            parse_fn = ParsedCommand::parse_key_sequence_delay;
+            beginning_sequence = "key_sequence_delay: ";
+        }
+        else if line.starts_with("key_sequence_speed: ") {
+            parse_fn = ParsedCommand::parse_key_sequence_speed;
+            beginning_sequence = "key_sequence_speed: ";
+        }
         else if line.starts_with("key: ") {
             parse_fn = ParsedCommand::parse_key;
             beginning_sequence = "key: ";
         }
+        else if line.starts_with("key_hold: ") {
+            parse_fn = ParsedCommand::parse_key_hold;
+            beginning_sequence = "key_hold: ";
+        }
+        else if line.starts_with("mouse_hold: ") {
+            parse_fn = ParsedCommand::parse_mouse_hold;
+            beginning_sequence = "mouse_hold: ";
+        }
         else if line.starts_with("wait: ") {
             parse_fn = ParsedCommand::parse_wait;
             beginning_sequence = "wait: ";
@@ -270,6 +536,22 @@ impl ParsedCommand {
             parse_fn = ParsedCommand::parse_mouse_move_relative;
             beginning_sequence = "mouse_move_relative: ";
         }
+        else if line.starts_with("mouse_drag: ") {
+            parse_fn = ParsedCommand::parse_mouse_drag;
+            beginning_sequence = "mouse_drag: ";
+        }
+        else if line.starts_with("label: ") {
+            parse_fn = ParsedCommand::parse_label;
+            beginning_sequence = "label: ";
+        }
+        else if line.starts_with("every: ") {
+            parse_fn = ParsedCommand::parse_every;
+            beginning_sequence = "every: ";
+        }
+        else if line.starts_with("exit: ") {
+            parse_fn = ParsedCommand::parse_exit;
+            beginning_sequence = "exit: ";
+        }
         else if line.starts_with("screen_compare_key_click: ") {
             parse_fn = ParsedCommand::parse_screen_compare_key_click;
             beginning_sequence = "screen_compare_key_click: ";
@@ -293,22 +575,359 @@ impl ParsedCommand {
 }
 
 pub fn parse_command_from_line(line: &String) -> ParsedCommand{
-    let (return_parse, parse_result) = ParsedCommand::parse_command_substring(line);
-
-    match parse_result{
-        ParseResult::Fail => {
+    match try_parse_command_from_line(line) {
+        None => {
             error!(target: "commands_debug", "The line is not formatted properly, not using it as a command");
             return ParsedCommand::Wait(1);
         },
-        _ => {
-            return return_parse;
+        Some(command) => {
+            return command;
         }
     }
 }
 
+// The name a script uses for the key, which is the character itself for layout keys
+pub fn key_name(key: &enigo::Key) -> String {
+    // Whitespace cannot be written as itself, since parameters are split on it
+    return match key {
+        Key::Layout(' ') => key_name(&Key::Space),
+        Key::Layout('\t') => key_name(&Key::Tab),
+        Key::Layout('\n') | Key::Layout('\r') => key_name(&Key::Return),
+        Key::Layout(layout_key) => layout_key.to_string(),
+        _ => function_key_name(key)
+    };
+}
+
+fn function_key_name(key: &enigo::Key) -> String {
+    return match STR_TO_ENIGO_KEY_MAP.entries().find(|(_, mapped_key)| *mapped_key == key) {
+        Some((name, _)) => name.to_string(),
+        None => format!("{:?}", key).to_lowercase()
+    };
+}
+
+pub fn mouse_button_name(mouse_button: &MouseButton) -> String {
+    return format!("{:?}", mouse_button).to_lowercase();
+}
+
+// The key a script name stands for, a single character being a layout key
+pub fn lookup_key(name: &str) -> Option<enigo::Key> {
+    let mut characters = name.chars();
+    if let (Some(layout_key), None) = (characters.next(), characters.next()) {
+        return Some(Key::Layout(layout_key));
+    }
+
+    return lookup_function_key(name);
+}
+
+// The key: command for the key, which is a layout key command for a character
+pub fn key_use_command(key: enigo::Key, button_action: ButtonAction) -> ParsedCommand {
+    if let Key::Layout(layout_key) = key {
+        return ParsedCommand::LayoutKeyUse(layout_key, button_action);
+    }
+
+    return ParsedCommand::FunctionKeyUse(key, button_action);
+}
+
+pub fn lookup_mouse_button(name: &str) -> Option<MouseButton> {
+    return match name.to_lowercase().as_str() {
+        "left" => Some(MouseButton::Left),
+        "right" => Some(MouseButton::Right),
+        "middle" => Some(MouseButton::Middle),
+        _ => None
+    };
+}
+
+mod key_serde {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(key: &enigo::Key, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_str(&super::key_name(key));
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<enigo::Key, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        return super::lookup_key(&name).ok_or_else(|| D::Error::custom(format!("unknown key {}", name)));
+    }
+}
+
+mod mouse_button_serde {
+    use enigo::MouseButton;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(mouse_button: &MouseButton, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_str(&super::mouse_button_name(mouse_button));
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MouseButton, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        return super::lookup_mouse_button(&name).ok_or_else(|| D::Error::custom(format!("unknown mouse button {}", name)));
+    }
+}
+
+impl fmt::Display for ButtonAction {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            ButtonAction::Press => write!(formatter, "press"),
+            ButtonAction::Release => write!(formatter, "release"),
+            ButtonAction::Click => write!(formatter, "click"),
+            ButtonAction::None => write!(formatter, "none")
+        };
+    }
+}
+
+// Writes the command as a script line that parses back to the same command. Options that
+// are left at their default are left out. Whitespace layout keys are the one exception, since
+// they are written by the name of the function key that types them, and read back as it
+impl fmt::Display for ParsedCommand {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            ParsedCommand::LayoutKeyUse(key, button_action) => write!(formatter, "key: {} {}", key_name(&Key::Layout(*key)), button_action),
+            ParsedCommand::FunctionKeyUse(key, button_action) => write!(formatter, "key: {} {}", key_name(key), button_action),
+            ParsedCommand::KeySequence(sequence) if sequence.is_empty() => write!(formatter, "key_sequence:"),
+            ParsedCommand::KeySequence(sequence) => write!(formatter, "key_sequence: {}", sequence),
+            ParsedCommand::KeySequenceWithDelay{sequence, delay_ms} if sequence.is_empty() => write!(formatter, "key_sequence_delay: delay={}", delay_ms),
+            ParsedCommand::KeySequenceWithDelay{sequence, delay_ms} => write!(formatter, "key_sequence_delay: delay={} {}", delay_ms, sequence),
+            ParsedCommand::SetKeySequenceDelay(delay_ms) => write!(formatter, "key_sequence_speed: delay={}", delay_ms),
+            ParsedCommand::MouseClick(mouse_button) => write!(formatter, "mouse_click: {}", mouse_button_name(mouse_button)),
+            ParsedCommand::MouseDown(mouse_button) => write!(formatter, "mouse_down: {}", mouse_button_name(mouse_button)),
+            ParsedCommand::MouseRelease(mouse_button) => write!(formatter, "mouse_release: {}", mouse_button_name(mouse_button)),
+            ParsedCommand::MouseMove{x, y} => write!(formatter, "mouse_move: {} {}", x, y),
+            ParsedCommand::MouseMoveSmooth{x, y, movement} => {
+                write!(formatter, "mouse_move: {} {} {}", x, y, movement.duration_ms)?;
+                if movement.path_type != MousePathType::Linear {
+                    write!(formatter, " {}", movement.path_type)?;
+                }
+                if movement.jitter != 0 {
+                    write!(formatter, " jitter={}", movement.jitter)?;
+                }
+                if movement.overshoot != 0 {
+                    write!(formatter, " overshoot={}", movement.overshoot)?;
+                }
+                if movement.step_interval_ms != DEFAULT_MOUSE_STEP_INTERVAL_MS {
+                    write!(formatter, " rate={}", movement.step_interval_ms)?;
+                }
+                Ok(())
+            },
+            ParsedCommand::MouseMoveRelative{x, y} => write!(formatter, "mouse_move_relative: {} {}", x, y),
+            ParsedCommand::KeyHold{key, duration_ms} => write!(formatter, "key_hold: {} {}", key_name(key), duration_ms),
+            ParsedCommand::MouseHold{mouse_button, duration_ms} => write!(formatter, "mouse_hold: {} {}", mouse_button_name(mouse_button), duration_ms),
+            ParsedCommand::MouseDrag{mouse_button, start_x, start_y, end_x, end_y, duration_ms} => {
+                write!(formatter, "mouse_drag: {} {} {} {} {} {}", mouse_button_name(mouse_button), start_x, start_y, end_x, end_y, duration_ms)
+            },
+            ParsedCommand::Wait(wait_time_ms) => write!(formatter, "wait: {}", wait_time_ms),
+            ParsedCommand::WaitRandom{min_ms, max_ms, distribution: WaitDistribution::Uniform} => write!(formatter, "wait: {}..{}", min_ms, max_ms),
+            ParsedCommand::WaitRandom{min_ms, max_ms, distribution} => write!(formatter, "wait: {}..{} {}", min_ms, max_ms, distribution),
+            ParsedCommand::Label(label) => write!(formatter, "label: {}", label),
+            ParsedCommand::Breakpoint => write!(formatter, "breakpoint:"),
+            ParsedCommand::Exit(exit_code) => write!(formatter, "exit: {}", exit_code),
+            ParsedCommand::Every{interval_ms, times: Some(times)} => write!(formatter, "every: {} {}", interval_ms, times),
+            ParsedCommand::Every{interval_ms, times: None} => write!(formatter, "every: {}", interval_ms),
+            ParsedCommand::EndEvery => write!(formatter, "end_every:"),
+            ParsedCommand::ScreenCompareLayoutKeyClick{layout_key, input_file_path, start_x, start_y, match_threshold} => {
+                write!(formatter, "screen_compare_key_click: {} {} {} {} {}", key_name(&Key::Layout(*layout_key)), start_x, start_y, match_threshold, input_file_path)
+            },
+            ParsedCommand::ScreenCompareFunctionKeyClick{function_key, input_file_path, start_x, start_y, match_threshold} => {
+                write!(formatter, "screen_compare_key_click: {} {} {} {} {}", key_name(function_key), start_x, start_y, match_threshold, input_file_path)
+            }
+        };
+    }
+}
+
+// Gives None for a line that is not formatted properly, instead of replacing it with a short wait
+pub fn try_parse_command_from_line(line: &String) -> Option<ParsedCommand> {
+    let (return_parse, parse_result) = ParsedCommand::parse_command_substring(line);
+
+    return match parse_result {
+        ParseResult::Fail => None,
+        ParseResult::Success => Some(return_parse)
+    };
+}
+
+// Whether the command's text line is a single line that parses back to the same command. Commands made
+// in code or read from YAML, JSON or TOML can hold values the text format would not accept
+pub fn has_text_line(command: &ParsedCommand) -> bool {
+    let line = command.to_string();
+
+    return !line.contains('\n') && !line.contains('\r') && try_parse_command_from_line(&line).as_ref() == Some(command);
+}
+
 #[cfg(test)]
 mod tests{
     use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+
+    const COMMAND_KIND_COUNT: usize = 23;
+
+    fn random_word<R: Rng>(rng: &mut R) -> String {
+        let length = rng.gen_range(1..8);
+        return (0..length).map(|_| rng.gen_range(b'a'..=b'z') as char).collect();
+    }
+
+    fn random_text<R: Rng>(rng: &mut R) -> String {
+        let word_count = rng.gen_range(1..4);
+        return (0..word_count).map(|_| random_word(rng)).collect::<Vec<String>>().join(" ");
+    }
+
+    // Text to type, with punctuation, runs of spaces, leading typing speed options and nothing at all
+    fn random_typed_text<R: Rng>(rng: &mut R) -> String {
+        let word_count = rng.gen_range(0..5);
+        let mut text = String::new();
+        for word_index in 0..word_count {
+            if word_index > 0 {
+                text += [" ", "  "].choose(rng).unwrap();
+            }
+            text += &match rng.gen_range(0..4) {
+                0 => format!("delay={}", rng.gen_range(0..100)),
+                1 => format!("cpm={}", rng.gen_range(0..1000)),
+                2 => (0..rng.gen_range(1..4)).map(|_| *b"#:=.,!?-_()'\"\\/".choose(rng).unwrap() as char).collect(),
+                _ => random_word(rng)
+            };
+        }
+
+        return text;
+    }
+
+    fn random_layout_key<R: Rng>(rng: &mut R) -> char {
+        return *b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789#:=.,!?-_()'\"\\/ \t".choose(rng).unwrap() as char;
+    }
+
+    fn random_function_key<R: Rng>(rng: &mut R) -> enigo::Key {
+        let function_keys: Vec<enigo::Key> = STR_TO_ENIGO_KEY_MAP.values().copied().collect();
+        return *function_keys.choose(rng).unwrap();
+    }
+
+    fn random_key<R: Rng>(rng: &mut R) -> enigo::Key {
+        if rng.gen_bool(0.5) {
+            return Key::Layout(random_layout_key(rng));
+        }
+        return random_function_key(rng);
+    }
+
+    fn random_button_action<R: Rng>(rng: &mut R) -> ButtonAction {
+        return *[ButtonAction::Press, ButtonAction::Release, ButtonAction::Click].choose(rng).unwrap();
+    }
+
+    fn random_mouse_button<R: Rng>(rng: &mut R) -> MouseButton {
+        return *[MouseButton::Left, MouseButton::Right, MouseButton::Middle].choose(rng).unwrap();
+    }
+
+    fn random_coordinate<R: Rng>(rng: &mut R) -> i32 {
+        return rng.gen_range(-4000..4000);
+    }
+
+    fn random_command<R: Rng>(rng: &mut R, command_kind: usize) -> ParsedCommand {
+        return match command_kind {
+            0 => ParsedCommand::LayoutKeyUse(random_layout_key(rng), random_button_action(rng)),
+            1 => ParsedCommand::FunctionKeyUse(random_function_key(rng), random_button_action(rng)),
+            2 => ParsedCommand::KeySequence(random_typed_text(rng)),
+            3 => ParsedCommand::KeySequenceWithDelay{sequence: random_typed_text(rng), delay_ms: rng.gen_range(0..1000)},
+            4 => ParsedCommand::SetKeySequenceDelay(rng.gen_range(0..1000)),
+            5 => ParsedCommand::MouseClick(random_mouse_button(rng)),
+            6 => ParsedCommand::MouseDown(random_mouse_button(rng)),
+            7 => ParsedCommand::MouseRelease(random_mouse_button(rng)),
+            8 => ParsedCommand::MouseMove{x: random_coordinate(rng), y: random_coordinate(rng)},
+            9 => ParsedCommand::MouseMoveSmooth{x: random_coordinate(rng), y: random_coordinate(rng), movement: MouseMovement {
+                duration_ms: rng.gen_range(0..5000),
+                path_type: *[MousePathType::Linear, MousePathType::Eased, MousePathType::Bezier].choose(rng).unwrap(),
+                jitter: rng.gen_range(-5..20),
+                overshoot: rng.gen_range(-5..50),
+                step_interval_ms: rng.gen_range(1..50)
+            }},
+            10 => ParsedCommand::MouseMoveRelative{x: random_coordinate(rng), y: random_coordinate(rng)},
+            11 => ParsedCommand::KeyHold{key: random_key(rng), duration_ms: rng.gen_range(0..5000)},
+            12 => ParsedCommand::MouseHold{mouse_button: random_mouse_button(rng), duration_ms: rng.gen_range(0..5000)},
+            13 => ParsedCommand::MouseDrag{mouse_button: random_mouse_button(rng),
+                                           start_x: random_coordinate(rng),
+                                           start_y: random_coordinate(rng),
+                                           end_x: random_coordinate(rng),
+                                           end_y: random_coordinate(rng),
+                                           duration_ms: rng.gen_range(0..5000)},
+            14 => ParsedCommand::Wait(rng.gen_range(0..100000)),
+            15 => {
+                let min_ms = rng.gen_range(0..1000);
+                ParsedCommand::WaitRandom{min_ms,
+                                          max_ms: min_ms + rng.gen_range(0..1000),
+                                          distribution: *[WaitDistribution::Uniform, WaitDistribution::Normal].choose(rng).unwrap()}
+            },
+            16 => ParsedCommand::Label(random_word(rng)),
+            17 => ParsedCommand::Breakpoint,
+            18 => ParsedCommand::Exit(*(1..256).filter(|exit_code| is_valid_exit_code(*exit_code)).collect::<Vec<i32>>().choose(rng).unwrap()),
+            19 => ParsedCommand::Every{interval_ms: rng.gen_range(1..10000), times: if rng.gen_bool(0.5) { Some(rng.gen_range(0..100)) } else { None }},
+            20 => ParsedCommand::EndEvery,
+            21 => ParsedCommand::ScreenCompareLayoutKeyClick{layout_key: random_layout_key(rng),
+                                                             input_file_path: random_text(rng) + ".png",
+                                                             start_x: random_coordinate(rng),
+                                                             start_y: random_coordinate(rng),
+                                                             match_threshold: rng.gen_range(0..=1000) as f64 / 10.0},
+            22 => ParsedCommand::ScreenCompareFunctionKeyClick{function_key: random_function_key(rng),
+                                                               input_file_path: random_text(rng) + ".png",
+                                                               start_x: random_coordinate(rng),
+                                                               start_y: random_coordinate(rng),
+                                                               match_threshold: rng.gen_range(0..=1000) as f64 / 10.0},
+            _ => panic!("There are only {} command kinds", COMMAND_KIND_COUNT)
+        };
+    }
+
+    // A whitespace layout key is written as the function key that types it, so it reads back as that
+    fn as_written(command: ParsedCommand) -> ParsedCommand {
+        let whitespace_key = |layout_key: char| match key_name(&Key::Layout(layout_key)).as_str() {
+            "space" | "tab" | "return" => lookup_function_key(&key_name(&Key::Layout(layout_key))),
+            _ => None
+        };
+
+        return match command {
+            ParsedCommand::LayoutKeyUse(layout_key, button_action) => match whitespace_key(layout_key) {
+                Some(function_key) => ParsedCommand::FunctionKeyUse(function_key, button_action),
+                None => command
+            },
+            ParsedCommand::KeyHold{key: Key::Layout(layout_key), duration_ms} => match whitespace_key(layout_key) {
+                Some(function_key) => ParsedCommand::KeyHold{key: function_key, duration_ms},
+                None => command
+            },
+            ParsedCommand::ScreenCompareLayoutKeyClick{layout_key, input_file_path, start_x, start_y, match_threshold} => match whitespace_key(layout_key) {
+                Some(function_key) => ParsedCommand::ScreenCompareFunctionKeyClick{function_key, input_file_path, start_x, start_y, match_threshold},
+                None => ParsedCommand::ScreenCompareLayoutKeyClick{layout_key, input_file_path, start_x, start_y, match_threshold}
+            },
+            _ => command
+        };
+    }
+
+    // parse(serialize(command)) == command, for many random commands of every kind
+    #[test]
+    fn test_display_round_trips_through_the_parser() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _i in 0..500 {
+            for command_kind in 0..COMMAND_KIND_COUNT {
+                let command = random_command(&mut rng, command_kind);
+                let line = command.to_string();
+
+                assert_eq!(try_parse_command_from_line(&line), Some(as_written(command)), "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn test_display_edge_cases() {
+        let commands = [
+            (ParsedCommand::KeySequence(String::from("delay=5 hi")), "key_sequence: delay=5 hi"),
+            (ParsedCommand::KeySequence(String::from("cpm=60")), "key_sequence: cpm=60"),
+            (ParsedCommand::KeySequence(String::new()), "key_sequence:"),
+            (ParsedCommand::KeySequenceWithDelay{sequence: String::from(" two  spaces "), delay_ms: 5}, "key_sequence_delay: delay=5  two  spaces "),
+            (ParsedCommand::LayoutKeyUse(' ', ButtonAction::Click), "key: space click"),
+            (ParsedCommand::LayoutKeyUse('#', ButtonAction::Press), "key: # press"),
+            (ParsedCommand::LayoutKeyUse(':', ButtonAction::Release), "key: : release")
+        ];
+
+        for (command, line) in commands {
+            assert_eq!(command.to_string(), line);
+            assert_eq!(try_parse_command_from_line(&command.to_string()), Some(as_written(command)));
+        }
+    }
 
     #[test]
     fn test_parse_key_sequence() {
@@ -323,6 +942,42 @@ mod tests{
         }
     }
     
+    #[test]
+    fn test_parse_key_sequence_with_delay() {
+        let line: String = String::from("key_sequence_delay: cpm=600 hello world");
+        let command: ParsedCommand = parse_command_from_line(&line);
+        
+        if let ParsedCommand::KeySequenceWithDelay{sequence, delay_ms} = command {
+            assert_eq!(sequence, "hello world");
+            assert_eq!(delay_ms, 100);
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+    }
+
+    #[test]
+    fn test_parse_key_sequence_types_options_as_written() {
+        let line: String = String::from("key_sequence: delay=5 seconds");
+
+        assert_eq!(try_parse_command_from_line(&line), Some(ParsedCommand::KeySequence(String::from("delay=5 seconds"))));
+        assert_eq!(try_parse_command_from_line(&String::from("key_sequence_delay: 5 seconds")), None);
+        assert_eq!(try_parse_command_from_line(&String::from("key_sequence_delay: delay=5")), Some(ParsedCommand::KeySequenceWithDelay{sequence: String::new(), delay_ms: 5}));
+    }
+
+    #[test]
+    fn test_parse_key_sequence_speed() {
+        let line: String = String::from("key_sequence_speed: delay=35");
+        let command: ParsedCommand = parse_command_from_line(&line);
+        
+        if let ParsedCommand::SetKeySequenceDelay(delay_ms) = command {
+            assert_eq!(delay_ms, 35);
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+    }
+
     #[test]
     fn test_parse_key_layout() {
         let line: String = String::from("key: d click");
@@ -351,6 +1006,34 @@ mod tests{
         }
     }
 
+    #[test]
+    fn test_parse_key_hold() {
+        let line: String = String::from("key_hold: shift 1500");
+        let command: ParsedCommand = parse_command_from_line(&line);
+        
+        if let ParsedCommand::KeyHold{key, duration_ms} = command {
+            assert_eq!(key, enigo::Key::Shift);
+            assert_eq!(duration_ms, 1500);
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+    }
+
+    #[test]
+    fn test_parse_mouse_hold() {
+        let line: String = String::from("mouse_hold: middle 200");
+        let command: ParsedCommand = parse_command_from_line(&line);
+        
+        if let ParsedCommand::MouseHold{mouse_button, duration_ms} = command {
+            assert_eq!(mouse_button, MouseButton::Middle);
+            assert_eq!(duration_ms, 200);
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+    }
+
     #[test]
     fn test_parse_wait() {
         let line: String = String::from("wait: 9");
@@ -364,6 +1047,21 @@ mod tests{
         }
     }
 
+    #[test]
+    fn test_parse_wait_random() {
+        let line: String = String::from("wait: 100..250 normal");
+        let command: ParsedCommand = parse_command_from_line(&line);
+        
+        if let ParsedCommand::WaitRandom{min_ms, max_ms, distribution} = command {
+            assert_eq!(min_ms, 100);
+            assert_eq!(max_ms, 250);
+            assert_eq!(distribution, WaitDistribution::Normal);
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+    }
+
     #[test]
     fn test_parse_mouse_click() {
         let line: String = String::from("mouse_click: left");
@@ -417,6 +1115,25 @@ mod tests{
         }
     }
 
+    #[test]
+    fn test_parse_mouse_move_smooth() {
+        let line: String = String::from("mouse_move: 500 200 300 bezier jitter=2 overshoot=15 rate=5");
+        let command: ParsedCommand = parse_command_from_line(&line);
+        
+        if let ParsedCommand::MouseMoveSmooth{x, y, movement} = command {
+            assert_eq!(x, 500);
+            assert_eq!(y, 200);
+            assert_eq!(movement, MouseMovement{duration_ms: 300,
+                                               path_type: MousePathType::Bezier,
+                                               jitter: 2,
+                                               overshoot: 15,
+                                               step_interval_ms: 5});
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+    }
+
     #[test]
     fn test_parse_mouse_move_relative() {
         let line: String = String::from("mouse_move_relative: 300 100");
@@ -431,6 +1148,78 @@ mod tests{
         }
     }
 
+    #[test]
+    fn test_parse_mouse_drag() {
+        let line: String = String::from("mouse_drag: right 100 200 -300 400 250");
+        let command: ParsedCommand = parse_command_from_line(&line);
+        
+        if let ParsedCommand::MouseDrag{mouse_button, start_x, start_y, end_x, end_y, duration_ms} = command {
+            assert_eq!(mouse_button, MouseButton::Right);
+            assert_eq!(start_x, 100);
+            assert_eq!(start_y, 200);
+            assert_eq!(end_x, -300);
+            assert_eq!(end_y, 400);
+            assert_eq!(duration_ms, 250);
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+    }
+
+    #[test]
+    fn test_parse_label() {
+        let line: String = String::from("label: fishing_loop");
+        let command: ParsedCommand = parse_command_from_line(&line);
+        
+        if let ParsedCommand::Label(label) = command {
+            assert_eq!(label, "fishing_loop");
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+    }
+
+    #[test]
+    fn test_parse_exit() {
+        let line: String = String::from("exit: 4");
+        let command: ParsedCommand = parse_command_from_line(&line);
+
+        if let ParsedCommand::Exit(exit_code) = command {
+            assert_eq!(exit_code, 4);
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+
+        for reserved_line in ["exit: 0", "exit: 2", "exit: 12", "exit: 130", "exit: 256", "exit: -1"] {
+            assert_eq!(try_parse_command_from_line(&reserved_line.to_string()), None);
+        }
+    }
+
+    #[test]
+    fn test_parse_every() {
+        let line: String = String::from("every: 1000 5");
+        let command: ParsedCommand = parse_command_from_line(&line);
+
+        if let ParsedCommand::Every{interval_ms, times} = command {
+            assert_eq!(interval_ms, 1000);
+            assert_eq!(times, Some(5));
+        }
+        else {
+            panic!("The returned command was the wrong type!");
+        }
+
+        assert!(matches!(parse_command_from_line(&String::from("end_every:")), ParsedCommand::EndEvery));
+    }
+
+    #[test]
+    fn test_parse_breakpoint() {
+        let line: String = String::from("breakpoint:");
+        let command: ParsedCommand = parse_command_from_line(&line);
+        
+        assert!(matches!(command, ParsedCommand::Breakpoint), "The returned command was the wrong type!");
+    }
+
     #[test]
     fn test_parse_screen_compare_key_click_layout() {
         let line: String = String::from("screen_compare_key_click: g 400 100 40 D:\\the space folder\\input.png");
@@ -0,0 +1,624 @@
+// A local HTTP server with a JSON API for starting, watching, pausing and stopping runs from
+// another process. Runs happen one at a time on a worker thread, which owns the executor and takes
+// jobs over a channel. Every request has to carry the token as "Authorization: Bearer <token>"
+use std::io::Read;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use log::{error, info};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::command_executor::*;
+use crate::command_types::*;
+use crate::emergency_stop::*;
+use crate::error::{panic_message, Error};
+use crate::held_inputs::HeldInputs;
+use crate::input_backend::*;
+use crate::pause_control::PauseControl;
+use crate::rhai_script::*;
+use crate::script::Script;
+use crate::stop_conditions::StopConditions;
+use crate::structured_script::*;
+use crate::timing::check_jitter_percentage;
+
+pub const DEFAULT_CONTROL_SERVER_ADDRESS: &str = "127.0.0.1:7878";
+// Read for the token when neither --token nor --token_file is given, so it does not show up in the process list
+pub const TOKEN_ENV_VAR: &str = "SIS_CONTROL_TOKEN";
+// Made of this many random bytes, written as hex
+const GENERATED_TOKEN_BYTES: usize = 16;
+// Larger request bodies get a 413 without being read any further
+const MAX_BODY_BYTES: u64 = 1024 * 1024;
+// Requests are handled on this many threads, so a slow client cannot hold up a /stop
+const REQUEST_THREADS: usize = 4;
+
+pub struct ControlServerOptions {
+    pub address: SocketAddr,
+    pub token: String,
+    // Other machines can only connect when this is set, since anyone who can connect can press keys
+    pub allow_remote: bool,
+    pub dry_run: bool,
+    pub dry_run_match: f64,
+    pub stop_hotkey: Option<Hotkey>,
+    pub corner_failsafe: bool,
+    // Every run presses into this, so a stop signal handler can release whatever a run left held
+    pub held_inputs: HeldInputs,
+    // Where the actions of a dry run and pause notices go. Without one they are logged
+    pub output: Option<OutputSink>
+}
+
+pub fn generate_token() -> String {
+    let mut rng = rand::thread_rng();
+    return (0..GENERATED_TOKEN_BYTES).map(|_| format!("{:02x}", rng.gen::<u8>())).collect();
+}
+
+// The token is the first line of the file, without surrounding whitespace
+pub fn read_token_file(token_file_path: &str) -> Result<String, String> {
+    let text = std::fs::read_to_string(token_file_path).map_err(|error_reason| format!("Could not read the token file {}: {}", token_file_path, error_reason))?;
+    let token = text.lines().next().unwrap_or("").trim();
+    if token.is_empty() {
+        return Err(format!("The token file {} is empty", token_file_path));
+    }
+
+    return Ok(token.to_string());
+}
+
+// Takes as long for a wrong token as for a right one, so the token cannot be guessed a character at a time
+fn tokens_match(expected_token: &str, given_token: &str) -> bool {
+    if expected_token.len() != given_token.len() {
+        return false;
+    }
+
+    return expected_token.bytes().zip(given_token.bytes()).fold(0, |difference, (expected, given)| difference | (expected ^ given)) == 0;
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerState {
+    Idle,
+    Running,
+    Paused
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct RunOutcome {
+    // finished, stopped or failed
+    pub result: &'static str,
+    pub stop_reason: Option<String>,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct ServerStatus {
+    pub state: ServerState,
+    pub iteration: u64,
+    pub line: Option<usize>,
+    pub text: Option<String>,
+    pub last_run: Option<RunOutcome>
+}
+
+#[derive(Deserialize)]
+struct RunRequest {
+    // Either the script itself or the path of a script file
+    script: Option<String>,
+    file: Option<String>,
+    // The format of script, text unless given
+    format: Option<String>,
+    times: Option<u64>,
+    duration_ms: Option<u64>,
+    seed: Option<u64>,
+    #[serde(default)]
+    jitter: f64
+}
+
+#[derive(Deserialize)]
+struct CommandRequest {
+    command: String
+}
+
+enum ScriptSource {
+    Text(String, ScriptFileFormat),
+    File(String),
+    Command(String)
+}
+
+struct Job {
+    source: ScriptSource,
+    repeat: Repeat,
+    seed: Option<u64>,
+    wait_jitter_percentage: f64,
+    // The worker says here whether the script could be loaded, before it starts running it
+    started: Sender<Result<(), String>>
+}
+
+// A .rhai file is code for the embedded engine, anything else is a list of commands
+enum LoadedScript {
+    Commands(Script),
+    Rhai(RhaiScript)
+}
+
+fn load_script(source: &ScriptSource) -> Result<LoadedScript, Error> {
+    return match source {
+        ScriptSource::Text(text, ScriptFileFormat::Text) => Script::parse(text).map(LoadedScript::Commands),
+        ScriptSource::Text(text, format) => Script::parse_structured(text, *format).map(LoadedScript::Commands),
+        ScriptSource::File(path) if is_rhai_file(path) => RhaiScript::from_file(path).map(LoadedScript::Rhai),
+        ScriptSource::File(path) => Script::from_file(path).map(LoadedScript::Commands),
+        ScriptSource::Command(line) => match try_parse_command_from_line(line) {
+            Some(command) => Ok(LoadedScript::Commands(Script::from_commands(vec![command]))),
+            None => Err(Error::Parse{line_number: 1, text: line.clone()})
+        }
+    };
+}
+
+fn read_format(format: Option<&str>) -> Result<ScriptFileFormat, String> {
+    return match format.map(str::to_lowercase).as_deref() {
+        None | Some("text") | Some("txt") => Ok(ScriptFileFormat::Text),
+        Some("yaml") | Some("yml") => Ok(ScriptFileFormat::Yaml),
+        Some("json") => Ok(ScriptFileFormat::Json),
+        Some("toml") => Ok(ScriptFileFormat::Toml),
+        Some(format) => Err(format!("{} is not a script format", format))
+    };
+}
+
+// What the worker thread shares with the request handlers
+struct SharedState {
+    status: Mutex<ServerStatus>,
+    stop_requested: Arc<AtomicBool>,
+    // What the last /pause or /resume asked for. The state in the status changes once the executor has done it
+    pause_requested: Arc<AtomicBool>
+}
+
+impl SharedState {
+    fn update_status<F: FnOnce(&mut ServerStatus)>(&self, update: F) {
+        // A panicking run may have held the lock, which should not stop the status from being reported
+        update(&mut self.status.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+    }
+}
+
+struct Worker {
+    shared_state: Arc<SharedState>,
+    dry_run: bool,
+    dry_run_match: f64,
+    stop_hotkey: Option<Hotkey>,
+    corner_failsafe: bool,
+    held_inputs: HeldInputs,
+    output: Option<OutputSink>
+}
+
+impl Worker {
+    fn create_backend(&self) -> Box<dyn InputBackend> {
+        if self.dry_run {
+            return match &self.output {
+                Some(output) => Box::new(DryRunBackend::with_output(None, self.dry_run_match, Arc::clone(output))),
+                None => Box::new(DryRunBackend::new(None, self.dry_run_match))
+            };
+        }
+        return Box::new(EnigoBackend::new());
+    }
+
+    fn create_executor(&self) -> CommandExecutor {
+        let backend = self.create_backend();
+        let shared_state = Arc::clone(&self.shared_state);
+        let progress_backend = ProgressBackend::new(backend, Box::new(move |iteration, script_line: &ScriptLine| {
+            shared_state.update_status(|status| {
+                status.iteration = iteration;
+                status.line = Some(script_line.line_number);
+                status.text = Some(script_line.text.clone());
+            });
+        }));
+
+        let mut executor = CommandExecutor::with_held_inputs(Box::new(progress_backend), self.held_inputs.clone());
+        if let Some(output) = &self.output {
+            executor.set_output(Arc::clone(output));
+        }
+        return executor;
+    }
+
+    fn run_job(&self, job: Job) -> RunOutcome {
+        let loaded_script = match load_script(&job.source) {
+            Ok(loaded_script) => loaded_script,
+            Err(error_reason) => {
+                let _ = job.started.send(Err(error_reason.to_string()));
+                return RunOutcome{result: "failed", stop_reason: None, exit_code: None, error: Some(error_reason.to_string())};
+            }
+        };
+        // A run does not start without the emergency stop that was asked for
+        let mut emergency_stop = None;
+        if !self.dry_run {
+            match EmergencyStop::new(self.stop_hotkey.clone(), self.corner_failsafe) {
+                Ok(created_emergency_stop) => { emergency_stop = Some(created_emergency_stop); },
+                Err(error_reason) => {
+                    let _ = job.started.send(Err(error_reason.clone()));
+                    return RunOutcome{result: "failed", stop_reason: None, exit_code: None, error: Some(error_reason)};
+                }
+            }
+        }
+
+        self.shared_state.stop_requested.store(false, Ordering::Relaxed);
+        self.shared_state.pause_requested.store(false, Ordering::Relaxed);
+        self.shared_state.update_status(|status| {
+            *status = ServerStatus{state: ServerState::Running, iteration: 0, line: None, text: None, last_run: status.last_run.clone()};
+        });
+        let _ = job.started.send(Ok(()));
+
+        let mut stop_conditions = StopConditions::new();
+        stop_conditions.stop_requested = Some(Arc::clone(&self.shared_state.stop_requested));
+        let shared_state = Arc::clone(&self.shared_state);
+        let pause_control = PauseControl::with_pause_flag(Arc::clone(&self.shared_state.pause_requested), Box::new(move |paused| {
+            shared_state.update_status(|status| status.state = if paused { ServerState::Paused } else { ServerState::Running });
+        }));
+        let run_options = RunOptions {
+            repeat: job.repeat,
+            seed: job.seed,
+            wait_jitter_percentage: job.wait_jitter_percentage,
+            emergency_stop,
+            pause_control: Some(pause_control),
+            stop_conditions,
+            ..Default::default()
+        };
+
+        let mut executor = self.create_executor();
+        let run_result = match &loaded_script {
+            LoadedScript::Commands(script) => Ok(executor.run(script, run_options)),
+            LoadedScript::Rhai(rhai_script) => rhai_script.run(&mut executor, run_options)
+        };
+
+        return match run_result {
+            Ok(ExecutionResult::Finished) => RunOutcome{result: "finished", stop_reason: None, exit_code: Some(0), error: None},
+            Ok(ExecutionResult::Stopped) => RunOutcome {
+                result: "stopped",
+                stop_reason: executor.stop_reason().map(|stop_reason| stop_reason.to_string()),
+                exit_code: executor.stop_reason().map(|stop_reason| stop_reason.exit_code()),
+                error: None
+            },
+            Err(error_reason) => RunOutcome{result: "failed", stop_reason: None, exit_code: None, error: Some(error_reason.to_string())}
+        };
+    }
+
+    fn run(self, jobs: Receiver<Job>) {
+        for job in jobs {
+            // A panic ends only the run, so the server goes back to idle and can take the next one
+            let run_outcome = panic::catch_unwind(AssertUnwindSafe(|| self.run_job(job))).unwrap_or_else(|panic_payload| {
+                // The executor did not get to release what the script was holding down
+                self.held_inputs.release_all(self.create_backend().as_mut());
+                RunOutcome{result: "failed", stop_reason: None, exit_code: None, error: Some(format!("Panicked: {}", panic_message(panic_payload.as_ref())))}
+            });
+            info!(target: "commands_debug", "Control server run ended: {:?}", run_outcome);
+
+            self.shared_state.update_status(|status| {
+                status.state = ServerState::Idle;
+                status.last_run = Some(run_outcome);
+            });
+        }
+    }
+}
+
+pub struct ControlServer {
+    token: String,
+    shared_state: Arc<SharedState>,
+    // Locked while a run starts, since requests are handled on several threads and two of them could both see the server idle
+    jobs: Mutex<Sender<Job>>
+}
+
+impl ControlServer {
+    // Starts the worker thread. Requests are handled by serve, or by handle_request directly
+    pub fn new(options: &ControlServerOptions) -> ControlServer {
+        let shared_state = Arc::new(SharedState {
+            status: Mutex::new(ServerStatus{state: ServerState::Idle, iteration: 0, line: None, text: None, last_run: None}),
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            pause_requested: Arc::new(AtomicBool::new(false))
+        });
+        let (jobs, job_receiver) = mpsc::channel();
+
+        let worker = Worker {
+            shared_state: Arc::clone(&shared_state),
+            dry_run: options.dry_run,
+            dry_run_match: options.dry_run_match,
+            stop_hotkey: options.stop_hotkey.clone(),
+            corner_failsafe: options.corner_failsafe,
+            held_inputs: options.held_inputs.clone(),
+            output: options.output.clone()
+        };
+        thread::spawn(move || worker.run(job_receiver));
+
+        return ControlServer {
+            token: options.token.clone(),
+            shared_state,
+            jobs: Mutex::new(jobs)
+        };
+    }
+
+    pub fn status(&self) -> ServerStatus {
+        return self.shared_state.status.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    }
+
+    fn start_job(&self, source: ScriptSource, repeat: Repeat, seed: Option<u64>, wait_jitter_percentage: f64) -> (u16, Value) {
+        let jobs = self.jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if self.status().state != ServerState::Idle {
+            return (409, json!({"error": "a run is already in progress"}));
+        }
+
+        let (started, started_receiver) = mpsc::channel();
+        let job = Job{source, repeat, seed, wait_jitter_percentage, started};
+        if jobs.send(job).is_err() {
+            return (500, json!({"error": "the worker thread has stopped"}));
+        }
+
+        return match started_receiver.recv() {
+            Ok(Ok(())) => (202, json!({"started": true})),
+            Ok(Err(message)) => (400, json!({"error": message})),
+            // The job panicked before it could say whether it started
+            Err(_) => (500, json!({"error": "the run failed to start"}))
+        };
+    }
+
+    fn handle_run(&self, body: &str) -> (u16, Value) {
+        let run_request: RunRequest = match serde_json::from_str(body) {
+            Ok(run_request) => run_request,
+            Err(error_reason) => { return (400, json!({"error": error_reason.to_string()})); }
+        };
+
+        let source = match (run_request.script, run_request.file) {
+            (Some(text), None) => match read_format(run_request.format.as_deref()) {
+                Ok(format) => ScriptSource::Text(text, format),
+                Err(message) => { return (400, json!({"error": message})); }
+            },
+            (None, Some(path)) => ScriptSource::File(path),
+            _ => { return (400, json!({"error": "give either script or file"})); }
+        };
+        let repeat = match (run_request.times, run_request.duration_ms) {
+            (Some(times), _) => Repeat::Times(times),
+            (None, Some(duration_ms)) => Repeat::For(Duration::from_millis(duration_ms)),
+            (None, None) => Repeat::Once
+        };
+
+        let wait_jitter_percentage = match check_jitter_percentage(run_request.jitter) {
+            Ok(wait_jitter_percentage) => wait_jitter_percentage,
+            Err(message) => { return (400, json!({"error": message})); }
+        };
+
+        return self.start_job(source, repeat, run_request.seed, wait_jitter_percentage);
+    }
+
+    fn handle_command(&self, body: &str) -> (u16, Value) {
+        return match serde_json::from_str::<CommandRequest>(body) {
+            Ok(command_request) => self.start_job(ScriptSource::Command(command_request.command), Repeat::Once, None, 0.0),
+            Err(error_reason) => (400, json!({"error": error_reason.to_string()}))
+        };
+    }
+
+    // Pausing and resuming set the state the executor should be in, which it moves to at its next check
+    fn handle_pause(&self, paused: bool) -> (u16, Value) {
+        if self.status().state == ServerState::Idle {
+            return (409, json!({"error": "nothing is running"}));
+        }
+
+        self.shared_state.pause_requested.store(paused, Ordering::Relaxed);
+        return (200, json!({"pausing": paused, "resuming": !paused}));
+    }
+
+    fn handle_stop(&self) -> (u16, Value) {
+        if self.status().state == ServerState::Idle {
+            return (409, json!({"error": "nothing is running"}));
+        }
+
+        self.shared_state.stop_requested.store(true, Ordering::Relaxed);
+        return (200, json!({"stopping": true}));
+    }
+
+    // authorization is the value of the Authorization header, if the request had one
+    fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        let given_token = authorization.and_then(|authorization| authorization.strip_prefix("Bearer "));
+        return given_token.map(|given_token| tokens_match(&self.token, given_token)).unwrap_or(false);
+    }
+
+    pub fn handle_request(&self, method: &Method, url: &str, authorization: Option<&str>, body: &str) -> (u16, Value) {
+        if !self.is_authorized(authorization) {
+            return (401, json!({"error": "a valid token is needed"}));
+        }
+
+        return match (method, url) {
+            (Method::Get, "/status") => (200, serde_json::to_value(self.status()).unwrap()),
+            (Method::Post, "/run") => self.handle_run(body),
+            (Method::Post, "/command") => self.handle_command(body),
+            (Method::Post, "/pause") => self.handle_pause(true),
+            (Method::Post, "/resume") => self.handle_pause(false),
+            (Method::Post, "/stop") => self.handle_stop(),
+            _ => (404, json!({"error": format!("{} {} is not part of the API", method, url)}))
+        };
+    }
+
+    // Reads at most MAX_BODY_BYTES of the body, and only once the token has been checked
+    fn read_body(&self, request: &mut Request, authorization: Option<&str>) -> Result<String, (u16, Value)> {
+        if !self.is_authorized(authorization) {
+            return Err((401, json!({"error": "a valid token is needed"})));
+        }
+        if request.body_length().map(|body_length| body_length as u64 > MAX_BODY_BYTES).unwrap_or(false) {
+            return Err((413, json!({"error": format!("the body is larger than {} bytes", MAX_BODY_BYTES)})));
+        }
+
+        let mut body = String::new();
+        if let Err(error_reason) = request.as_reader().take(MAX_BODY_BYTES + 1).read_to_string(&mut body) {
+            return Err((400, json!({"error": error_reason.to_string()})));
+        }
+        if body.len() as u64 > MAX_BODY_BYTES {
+            return Err((413, json!({"error": format!("the body is larger than {} bytes", MAX_BODY_BYTES)})));
+        }
+
+        return Ok(body);
+    }
+
+    fn respond(&self, mut request: Request) {
+        let authorization = request.headers().iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .map(|header| header.value.as_str().to_string());
+        let (status_code, response_body) = match self.read_body(&mut request, authorization.as_deref()) {
+            Ok(body) => self.handle_request(request.method(), request.url(), authorization.as_deref(), &body),
+            Err(error_response) => error_response
+        };
+
+        let content_type = Header::from_bytes("Content-Type", "application/json").expect("The header is valid");
+        let response = Response::from_string(response_body.to_string()).with_status_code(status_code).with_header(content_type);
+        if let Err(error_reason) = request.respond(response) {
+            error!(target: "commands_debug", "Failed to respond to a control server request: {}", error_reason);
+        }
+    }
+}
+
+// Handles requests until the process is stopped
+pub fn serve(options: &ControlServerOptions) -> Result<(), String> {
+    if !options.address.ip().is_loopback() && !options.allow_remote {
+        return Err(format!("{} can be reached from other machines, which needs --allow_remote", options.address));
+    }
+
+    let server = Arc::new(Server::http(options.address).map_err(|error_reason| format!("Could not listen on {}: {}", options.address, error_reason))?);
+    let control_server = Arc::new(ControlServer::new(options));
+    info!(target: "commands_debug", "Control server listening on {}", options.address);
+
+    let request_threads: Vec<_> = (0..REQUEST_THREADS).map(|_| {
+        let server = Arc::clone(&server);
+        let control_server = Arc::clone(&control_server);
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                control_server.respond(request);
+            }
+        })
+    }).collect();
+    for request_thread in request_threads {
+        let _ = request_thread.join();
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const TOKEN: &str = "secret";
+
+    fn dry_run_server() -> ControlServer {
+        return ControlServer::new(&ControlServerOptions {
+            address: DEFAULT_CONTROL_SERVER_ADDRESS.parse().unwrap(),
+            token: String::from(TOKEN),
+            allow_remote: false,
+            dry_run: true,
+            dry_run_match: 100.0,
+            stop_hotkey: None,
+            corner_failsafe: false,
+            held_inputs: HeldInputs::new(),
+            output: None
+        });
+    }
+
+    fn wait_until_idle(control_server: &ControlServer) -> ServerStatus {
+        loop {
+            let status = control_server.status();
+            if status.state == ServerState::Idle {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_requests_need_the_token() {
+        let control_server = dry_run_server();
+
+        assert_eq!(control_server.handle_request(&Method::Get, "/status", None, "").0, 401);
+        assert_eq!(control_server.handle_request(&Method::Get, "/status", Some("Bearer wrong!"), "").0, 401);
+        assert_eq!(control_server.handle_request(&Method::Get, "/status", Some("Bearer secret"), "").0, 200);
+        assert_eq!(control_server.handle_request(&Method::Get, "/nothing", Some("Bearer secret"), "").0, 404);
+    }
+
+    #[test]
+    fn test_read_token_file() {
+        let token_file_path = std::env::temp_dir().join(format!("scriptable_input_simulator_token_{}.txt", std::process::id()));
+        let token_file_path = token_file_path.to_str().unwrap();
+
+        std::fs::write(token_file_path, "  secret \nignored\n").unwrap();
+        assert_eq!(read_token_file(token_file_path), Ok(String::from("secret")));
+        std::fs::write(token_file_path, "\n").unwrap();
+        assert!(read_token_file(token_file_path).is_err());
+        std::fs::remove_file(token_file_path).unwrap();
+        assert!(read_token_file(token_file_path).is_err());
+    }
+
+    #[test]
+    fn test_run_script() {
+        let control_server = dry_run_server();
+        let authorization = Some("Bearer secret");
+
+        let (status_code, _) = control_server.handle_request(&Method::Post, "/run", authorization, r#"{"script": "wait: 100\nexit: 4", "times": 2}"#);
+        assert_eq!(status_code, 202);
+
+        let status = wait_until_idle(&control_server);
+        assert_eq!(status.line, Some(2));
+        assert_eq!(status.text.as_deref(), Some("exit: 4"));
+        assert_eq!(status.last_run.unwrap().exit_code, Some(4));
+
+        let (status_code, _) = control_server.handle_request(&Method::Post, "/command", authorization, r#"{"command": "mouse_move: 10 20"}"#);
+        assert_eq!(status_code, 202);
+        assert_eq!(wait_until_idle(&control_server).last_run.unwrap().result, "finished");
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        let control_server = dry_run_server();
+        let authorization = Some("Bearer secret");
+
+        assert_eq!(control_server.handle_request(&Method::Post, "/run", authorization, r#"{"script": "wait: soon"}"#).0, 400);
+        assert_eq!(control_server.handle_request(&Method::Post, "/run", authorization, r#"{"script": "wait: 1", "format": "xml"}"#).0, 400);
+        assert_eq!(control_server.handle_request(&Method::Post, "/run", authorization, "{}").0, 400);
+        assert_eq!(control_server.handle_request(&Method::Post, "/run", authorization, r#"{"script": "wait: 1", "jitter": -5}"#).0, 400);
+        assert_eq!(control_server.handle_request(&Method::Post, "/stop", authorization, "").0, 409);
+        assert_eq!(control_server.handle_request(&Method::Post, "/resume", authorization, "").0, 409);
+    }
+
+    fn wait_for_state(control_server: &ControlServer, state: ServerState) {
+        while control_server.status().state != state {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let control_server = dry_run_server();
+        let authorization = Some("Bearer secret");
+
+        assert_eq!(control_server.handle_request(&Method::Post, "/run", authorization, r#"{"script": "wait: 100", "times": 1000000000}"#).0, 202);
+        assert_eq!(control_server.handle_request(&Method::Post, "/pause", authorization, "").0, 200);
+        wait_for_state(&control_server, ServerState::Paused);
+
+        // A pause straight after a resume cancels it rather than leaving the two out of step
+        assert_eq!(control_server.handle_request(&Method::Post, "/resume", authorization, "").0, 200);
+        assert_eq!(control_server.handle_request(&Method::Post, "/pause", authorization, "").0, 200);
+        assert_eq!(control_server.handle_request(&Method::Post, "/resume", authorization, "").0, 200);
+        wait_for_state(&control_server, ServerState::Running);
+
+        assert_eq!(control_server.handle_request(&Method::Post, "/pause", authorization, "").0, 200);
+        assert_eq!(control_server.handle_request(&Method::Post, "/resume", authorization, "").0, 200);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(control_server.status().state, ServerState::Running);
+
+        assert_eq!(control_server.handle_request(&Method::Post, "/pause", authorization, "").0, 200);
+        wait_for_state(&control_server, ServerState::Paused);
+        assert_eq!(control_server.handle_request(&Method::Post, "/stop", authorization, "").0, 200);
+        assert_eq!(wait_until_idle(&control_server).last_run.unwrap().result, "stopped");
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret2"));
+        assert_eq!(generate_token().len(), GENERATED_TOKEN_BYTES * 2);
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+
+use crate::command_types::*;
+
+pub const DEBUGGER_HELP: &str = "Debugger commands:
+    next (n)                 Run the next command and pause again
+    continue (c)             Run until a breakpoint
+    run_to <line> (r)        Run until <line>, or until a breakpoint
+    break <line> (b)         Pause before <line>
+    break <label>            Pause at label: <label>
+    score (s)                Print the last screen comparison score
+    screenshot <file>        Save a screenshot of the main screen to <file>
+    state                    Print the current line and state again
+    help (h)                 Print this help";
+
+#[derive(PartialEq, Debug)]
+pub enum DebuggerCommand {
+    Next,
+    Continue,
+    RunToLine(usize),
+    BreakOnLine(usize),
+    BreakOnLabel(String),
+    PrintScore,
+    Screenshot(String),
+    PrintState,
+    Help
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum DebuggerMode {
+    Stepping,
+    Continuing,
+    RunningToLine(usize)
+}
+
+pub struct Debugger {
+    mode: DebuggerMode,
+    breakpoint_lines: HashSet<usize>,
+    breakpoint_labels: HashSet<String>
+}
+
+impl Debugger {
+    // Starts out stepping, so the script pauses before its first command
+    pub fn new() -> Debugger {
+        return Debugger {
+            mode: DebuggerMode::Stepping,
+            breakpoint_lines: HashSet::new(),
+            breakpoint_labels: HashSet::new()
+        };
+    }
+
+    pub fn should_pause(&self, script_line: &ScriptLine) -> bool {
+        let at_breakpoint = match &script_line.command {
+            ParsedCommand::Breakpoint => true,
+            ParsedCommand::Label(label) => self.breakpoint_labels.contains(label),
+            _ => self.breakpoint_lines.contains(&script_line.line_number)
+        };
+
+        return match self.mode {
+            DebuggerMode::Stepping => true,
+            DebuggerMode::Continuing => at_breakpoint,
+            DebuggerMode::RunningToLine(line_number) => at_breakpoint || script_line.line_number == line_number
+        };
+    }
+
+    // Handles the commands that only change the debugger. Returns true for the commands that resume the script
+    pub fn handle_command(&mut self, debugger_command: &DebuggerCommand) -> bool {
+        match debugger_command {
+            DebuggerCommand::Next => {
+                self.mode = DebuggerMode::Stepping;
+                return true;
+            },
+            DebuggerCommand::Continue => {
+                self.mode = DebuggerMode::Continuing;
+                return true;
+            },
+            DebuggerCommand::RunToLine(line_number) => {
+                self.mode = DebuggerMode::RunningToLine(*line_number);
+                return true;
+            },
+            DebuggerCommand::BreakOnLine(line_number) => {
+                self.breakpoint_lines.insert(*line_number);
+                println!("Breakpoint set on line {}", line_number);
+            },
+            DebuggerCommand::BreakOnLabel(label) => {
+                self.breakpoint_labels.insert(label.clone());
+                println!("Breakpoint set on label {}", label);
+            },
+            _ => {}
+        }
+
+        return false;
+    }
+}
+
+pub fn parse_debugger_command(input: &str) -> Option<DebuggerCommand> {
+    let split_input: Vec<&str> = input.split_whitespace().collect();
+
+    return match split_input.as_slice() {
+        ["next"] | ["n"] | [] => Some(DebuggerCommand::Next),
+        ["continue"] | ["c"] => Some(DebuggerCommand::Continue),
+        ["run_to", line] | ["r", line] => line.parse::<usize>().ok().map(DebuggerCommand::RunToLine),
+        ["break", target] | ["b", target] => {
+            match target.parse::<usize>() {
+                Ok(line_number) => Some(DebuggerCommand::BreakOnLine(line_number)),
+                Err(_) => Some(DebuggerCommand::BreakOnLabel(target.to_string()))
+            }
+        },
+        ["score"] | ["s"] => Some(DebuggerCommand::PrintScore),
+        ["screenshot", file_path] => Some(DebuggerCommand::Screenshot(file_path.to_string())),
+        ["state"] => Some(DebuggerCommand::PrintState),
+        ["help"] | ["h"] => Some(DebuggerCommand::Help),
+        _ => None
+    };
+}
+
+pub fn read_debugger_command() -> DebuggerCommand {
+    loop {
+        print!("(debug) ");
+        let _ = io::stdout().flush();
+
+        let mut input = String::new();
+        match io::stdin().lock().read_line(&mut input) {
+            // Nothing more can be read, so let the script run to the end
+            Ok(0) | Err(_) => { return DebuggerCommand::Continue; },
+            Ok(_) => {}
+        }
+
+        match parse_debugger_command(&input) {
+            Some(debugger_command) => { return debugger_command; },
+            None => { println!("Unknown debugger command. Type help for the list of commands"); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_parse_debugger_command() {
+        assert_eq!(parse_debugger_command("\n"), Some(DebuggerCommand::Next));
+        assert_eq!(parse_debugger_command("run_to 12\n"), Some(DebuggerCommand::RunToLine(12)));
+        assert_eq!(parse_debugger_command("b 7"), Some(DebuggerCommand::BreakOnLine(7)));
+        assert_eq!(parse_debugger_command("break fishing_loop"), Some(DebuggerCommand::BreakOnLabel(String::from("fishing_loop"))));
+        assert_eq!(parse_debugger_command("screenshot capture.png"), Some(DebuggerCommand::Screenshot(String::from("capture.png"))));
+        assert_eq!(parse_debugger_command("jump 3"), None);
+    }
+
+    #[test]
+    fn test_debugger_stops_at_breakpoints_when_continuing() {
+        let mut debugger = Debugger::new();
+        let plain_line = ScriptLine{line_number: 1, text: String::from("wait: 1"), command: ParsedCommand::Wait(1)};
+        let breakpoint_line = ScriptLine{line_number: 2, text: String::from("breakpoint:"), command: ParsedCommand::Breakpoint};
+        let label_line = ScriptLine{line_number: 3, text: String::from("label: start"), command: ParsedCommand::Label(String::from("start"))};
+
+        assert!(debugger.should_pause(&plain_line));
+
+        debugger.handle_command(&DebuggerCommand::BreakOnLabel(String::from("start")));
+        debugger.handle_command(&DebuggerCommand::Continue);
+        assert!(!debugger.should_pause(&plain_line));
+        assert!(debugger.should_pause(&breakpoint_line));
+        assert!(debugger.should_pause(&label_line));
+    }
+}
\ No newline at end of file
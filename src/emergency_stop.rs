@@ -0,0 +1,153 @@
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use phf::phf_map;
+use screenshots::Screen;
+
+use crate::stop_conditions::StopReason;
+
+// How close to a screen corner, in pixels, the cursor has to be to trigger the failsafe
+const CORNER_FAILSAFE_MARGIN_PX: i32 = 1;
+
+static STR_TO_STOP_KEYCODES_MAP: phf::Map<&str, &[Keycode]> = phf_map! {
+    "alt" => &[Keycode::LAlt, Keycode::RAlt],
+    "control" => &[Keycode::LControl, Keycode::RControl],
+    "shift" => &[Keycode::LShift, Keycode::RShift],
+    "back_space" => &[Keycode::Backspace],
+    "delete" => &[Keycode::Delete],
+    "end" => &[Keycode::End],
+    "escape" => &[Keycode::Escape],
+    "home" => &[Keycode::Home],
+    "page_down" => &[Keycode::PageDown],
+    "page_up" => &[Keycode::PageUp],
+    "return" => &[Keycode::Enter],
+    "space" => &[Keycode::Space],
+    "tab" => &[Keycode::Tab],
+    "f1" => &[Keycode::F1],
+    "f2" => &[Keycode::F2],
+    "f3" => &[Keycode::F3],
+    "f4" => &[Keycode::F4],
+    "f5" => &[Keycode::F5],
+    "f6" => &[Keycode::F6],
+    "f7" => &[Keycode::F7],
+    "f8" => &[Keycode::F8],
+    "f9" => &[Keycode::F9],
+    "f10" => &[Keycode::F10],
+    "f11" => &[Keycode::F11],
+    "f12" => &[Keycode::F12],
+    "a" => &[Keycode::A],
+    "b" => &[Keycode::B],
+    "c" => &[Keycode::C],
+    "d" => &[Keycode::D],
+    "e" => &[Keycode::E],
+    "f" => &[Keycode::F],
+    "g" => &[Keycode::G],
+    "h" => &[Keycode::H],
+    "i" => &[Keycode::I],
+    "j" => &[Keycode::J],
+    "k" => &[Keycode::K],
+    "l" => &[Keycode::L],
+    "m" => &[Keycode::M],
+    "n" => &[Keycode::N],
+    "o" => &[Keycode::O],
+    "p" => &[Keycode::P],
+    "q" => &[Keycode::Q],
+    "r" => &[Keycode::R],
+    "s" => &[Keycode::S],
+    "t" => &[Keycode::T],
+    "u" => &[Keycode::U],
+    "v" => &[Keycode::V],
+    "w" => &[Keycode::W],
+    "x" => &[Keycode::X],
+    "y" => &[Keycode::Y],
+    "z" => &[Keycode::Z]
+};
+
+// A hotkey is a list of keys that all have to be down at once. Each key is a list of
+// keycodes because, for example, either control key counts as control
+pub type Hotkey = Vec<&'static [Keycode]>;
+
+pub fn parse_hotkey(hotkey_string: &str) -> Option<Hotkey> {
+    let mut hotkey: Hotkey = vec![];
+
+    for key_name in hotkey_string.split("+") {
+        let keycodes = STR_TO_STOP_KEYCODES_MAP.get(key_name.trim())?;
+        hotkey.push(*keycodes);
+    }
+
+    return Some(hotkey);
+}
+
+pub fn is_hotkey_pressed(device_state: &DeviceState, hotkey: &Hotkey) -> bool {
+    let pressed_keys = device_state.get_keys();
+
+    return !hotkey.is_empty() && hotkey.iter().all(|keycodes| keycodes.iter().any(|keycode| pressed_keys.contains(keycode)));
+}
+
+pub struct EmergencyStop {
+    device_state: DeviceState,
+    hotkey: Option<Hotkey>,
+    // The corners of the main screen, only set when the failsafe is enabled
+    screen_corners: Option<[(i32, i32); 4]>
+}
+
+impl EmergencyStop {
+    // Fails when the corner failsafe is asked for but the main screen cannot be found, rather than running without it
+    pub fn new(hotkey: Option<Hotkey>, corner_failsafe: bool) -> Result<EmergencyStop, String> {
+        let mut screen_corners = None;
+
+        if corner_failsafe {
+            let screens = Screen::all().map_err(|error_reason| format!("The screen for the corner failsafe could not be found: {}", error_reason))?;
+            let display_info = screens.first().ok_or_else(|| String::from("The corner failsafe needs a screen, but there is none"))?.display_info;
+            let left = display_info.x;
+            let top = display_info.y;
+            let right = display_info.x + display_info.width as i32 - 1;
+            let bottom = display_info.y + display_info.height as i32 - 1;
+
+            screen_corners = Some([(left, top), (right, top), (left, bottom), (right, bottom)]);
+        }
+
+        return Ok(EmergencyStop {
+            device_state: DeviceState::new(),
+            hotkey,
+            screen_corners
+        });
+    }
+
+    pub fn check(&self) -> Option<StopReason> {
+        if let Some(hotkey) = &self.hotkey {
+            if is_hotkey_pressed(&self.device_state, hotkey) {
+                return Some(StopReason::Hotkey);
+            }
+        }
+
+        if let Some(screen_corners) = &self.screen_corners {
+            let (mouse_x, mouse_y) = self.device_state.get_mouse().coords;
+
+            for (corner_x, corner_y) in screen_corners {
+                if (mouse_x - corner_x).abs() <= CORNER_FAILSAFE_MARGIN_PX && (mouse_y - corner_y).abs() <= CORNER_FAILSAFE_MARGIN_PX {
+                    return Some(StopReason::MouseCorner);
+                }
+            }
+        }
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_parse_hotkey() {
+        let hotkey = parse_hotkey("control+alt+escape").unwrap();
+
+        assert_eq!(hotkey.len(), 3);
+        assert_eq!(hotkey[0], &[Keycode::LControl, Keycode::RControl]);
+        assert_eq!(hotkey[2], &[Keycode::Escape]);
+    }
+
+    #[test]
+    fn test_parse_hotkey_unknown_key() {
+        assert!(parse_hotkey("control+not_a_key").is_none());
+    }
+}
\ No newline at end of file
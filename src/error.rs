@@ -0,0 +1,45 @@
+use std::any::Any;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    // The script file could not be read
+    Io{path: String, source: io::Error},
+    // A line of the script is not formatted properly
+    Parse{line_number: usize, text: String},
+    // An every: without an end_every:, or the other way around
+    UnmatchedBlock{line_number: usize, text: String},
+    // A YAML, JSON or TOML script could not be read as commands, or commands could not be written in it
+    Structured{format: &'static str, message: String},
+    // A Rhai script did not compile, or failed while running
+    Rhai{message: String}
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Error::Io{path, source} => write!(formatter, "The script file {} could not be read: {}", path, source),
+            Error::Parse{line_number, text} => write!(formatter, "Line {} is not formatted properly: {}", line_number, text),
+            Error::UnmatchedBlock{line_number, text} => write!(formatter, "Line {} is not part of a complete every: ... end_every: block: {}", line_number, text),
+            Error::Structured{format, message} => write!(formatter, "The {} script is not valid: {}", format, message),
+            Error::Rhai{message} => write!(formatter, "The Rhai script failed: {}", message)
+        };
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            Error::Io{source, ..} => Some(source),
+            _ => None
+        };
+    }
+}
+
+// The message a panic was started with, for callers that catch panics and report them as errors
+pub fn panic_message(panic_payload: &(dyn Any + Send)) -> String {
+    return panic_payload.downcast_ref::<String>().cloned()
+        .or_else(|| panic_payload.downcast_ref::<&str>().map(|message| message.to_string()))
+        .unwrap_or_else(|| String::from("unknown panic"));
+}
@@ -0,0 +1,193 @@
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::command_types::*;
+
+// One line of the trace. Runs of the same script can be diffed line by line
+#[derive(Serialize)]
+pub struct TraceEvent<'a> {
+    // Milliseconds since the start of the run, which is simulated in a dry run
+    pub timestamp_ms: f64,
+    pub source_file: &'a str,
+    pub line: usize,
+    pub iteration: u64,
+    pub command: &'static str,
+    pub arguments: Value,
+    pub score: Option<f64>,
+    pub outcome: &'static str
+}
+
+pub struct EventTrace {
+    writer: Box<dyn Write>,
+    source_file: String
+}
+
+impl EventTrace {
+    pub fn new(writer: Box<dyn Write>, source_file: &str) -> EventTrace {
+        return EventTrace {
+            writer,
+            source_file: source_file.to_string()
+        };
+    }
+
+    // Each event is flushed as soon as its line is written, so the trace is complete up to a crash
+    pub fn create_file(trace_file_name: &str, source_file: &str) -> io::Result<EventTrace> {
+        let trace_file = File::create(trace_file_name)?;
+        return Ok(EventTrace::new(Box::new(LineWriter::new(trace_file)), source_file));
+    }
+
+    pub fn source_file(&self) -> &str {
+        return &self.source_file;
+    }
+
+    pub fn write_event(&mut self, trace_event: &TraceEvent) -> io::Result<()> {
+        let event_line = serde_json::to_string(trace_event)?;
+        return writeln!(self.writer, "{}", event_line);
+    }
+}
+
+pub fn command_kind(command: &ParsedCommand) -> &'static str {
+    return match command {
+        ParsedCommand::LayoutKeyUse(_, _) => "key",
+        ParsedCommand::FunctionKeyUse(_, _) => "key",
+        ParsedCommand::KeySequence(_) => "key_sequence",
+        ParsedCommand::KeySequenceWithDelay{..} => "key_sequence_delay",
+        ParsedCommand::SetKeySequenceDelay(_) => "key_sequence_speed",
+        ParsedCommand::MouseClick(_) => "mouse_click",
+        ParsedCommand::MouseDown(_) => "mouse_down",
+        ParsedCommand::MouseRelease(_) => "mouse_release",
+        ParsedCommand::MouseMove{..} => "mouse_move",
+        ParsedCommand::MouseMoveSmooth{..} => "mouse_move",
+        ParsedCommand::MouseMoveRelative{..} => "mouse_move_relative",
+        ParsedCommand::KeyHold{..} => "key_hold",
+        ParsedCommand::MouseHold{..} => "mouse_hold",
+        ParsedCommand::MouseDrag{..} => "mouse_drag",
+        ParsedCommand::Wait(_) => "wait",
+        ParsedCommand::WaitRandom{..} => "wait",
+        ParsedCommand::Label(_) => "label",
+        ParsedCommand::Breakpoint => "breakpoint",
+        ParsedCommand::Exit(_) => "exit",
+        ParsedCommand::Every{..} => "every",
+        ParsedCommand::EndEvery => "end_every",
+        ParsedCommand::ScreenCompareLayoutKeyClick{..} => "screen_compare_key_click",
+        ParsedCommand::ScreenCompareFunctionKeyClick{..} => "screen_compare_key_click"
+    };
+}
+
+// The arguments as the script gave them. Values only known while running, like the
+// jittered length of a wait, are added by the executor
+pub fn command_arguments(command: &ParsedCommand) -> Value {
+    return match command {
+        ParsedCommand::LayoutKeyUse(key, button_action) => json!({"key": key.to_string(), "action": button_action.to_string()}),
+        ParsedCommand::FunctionKeyUse(key, button_action) => json!({"key": key_name(key), "action": button_action.to_string()}),
+        ParsedCommand::KeySequence(sequence) => json!({"sequence": sequence}),
+        ParsedCommand::KeySequenceWithDelay{sequence, delay_ms} => json!({"sequence": sequence, "delay_ms": delay_ms}),
+        ParsedCommand::SetKeySequenceDelay(delay_ms) => json!({"delay_ms": delay_ms}),
+        ParsedCommand::MouseClick(mouse_button) |
+        ParsedCommand::MouseDown(mouse_button) |
+        ParsedCommand::MouseRelease(mouse_button) => json!({"button": mouse_button_name(mouse_button)}),
+        ParsedCommand::MouseMove{x, y} => json!({"x": x, "y": y}),
+        ParsedCommand::MouseMoveSmooth{x, y, movement} => json!({
+            "x": x,
+            "y": y,
+            "duration_ms": movement.duration_ms,
+            "path": movement.path_type.to_string(),
+            "jitter": movement.jitter,
+            "overshoot": movement.overshoot,
+            "rate_ms": movement.step_interval_ms
+        }),
+        ParsedCommand::MouseMoveRelative{x, y} => json!({"x": x, "y": y}),
+        ParsedCommand::KeyHold{key, duration_ms} => json!({"key": key_name(key), "duration_ms": duration_ms}),
+        ParsedCommand::MouseHold{mouse_button, duration_ms} => json!({"button": mouse_button_name(mouse_button), "duration_ms": duration_ms}),
+        ParsedCommand::MouseDrag{mouse_button, start_x, start_y, end_x, end_y, duration_ms} => json!({
+            "button": mouse_button_name(mouse_button),
+            "start_x": start_x,
+            "start_y": start_y,
+            "end_x": end_x,
+            "end_y": end_y,
+            "duration_ms": duration_ms
+        }),
+        ParsedCommand::Wait(wait_time_ms) => json!({"duration_ms": wait_time_ms}),
+        ParsedCommand::WaitRandom{min_ms, max_ms, distribution} => json!({
+            "min_ms": min_ms,
+            "max_ms": max_ms,
+            "distribution": distribution.to_string()
+        }),
+        ParsedCommand::Label(label) => json!({"name": label}),
+        ParsedCommand::Breakpoint => json!({}),
+        ParsedCommand::Exit(exit_code) => json!({"code": exit_code}),
+        ParsedCommand::Every{interval_ms, times} => json!({"interval_ms": interval_ms, "times": times}),
+        ParsedCommand::EndEvery => json!({}),
+        ParsedCommand::ScreenCompareLayoutKeyClick{layout_key, input_file_path, start_x, start_y, match_threshold} => json!({
+            "key": layout_key.to_string(),
+            "image_file": input_file_path,
+            "start_x": start_x,
+            "start_y": start_y,
+            "match_threshold": match_threshold
+        }),
+        ParsedCommand::ScreenCompareFunctionKeyClick{function_key, input_file_path, start_x, start_y, match_threshold} => json!({
+            "key": key_name(function_key),
+            "image_file": input_file_path,
+            "start_x": start_x,
+            "start_y": start_y,
+            "match_threshold": match_threshold
+        })
+    };
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Keeps what is written where the test can still read it after the trace takes the writer
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            return self.0.borrow_mut().write(buffer);
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_trace_event_is_one_json_line() {
+        let command = parse_command_from_line(&String::from("wait: 100..200 normal"));
+        let mut arguments = command_arguments(&command);
+        arguments["waited_ms"] = json!(150);
+
+        let trace_event = TraceEvent {
+            timestamp_ms: 12.5,
+            source_file: "fishing.txt",
+            line: 3,
+            iteration: 2,
+            command: command_kind(&command),
+            arguments,
+            score: None,
+            outcome: "done"
+        };
+
+        let written = SharedBuffer::default();
+        let mut event_trace = EventTrace::new(Box::new(written.clone()), "fishing.txt");
+        assert!(event_trace.write_event(&trace_event).is_ok());
+        assert_eq!(String::from_utf8(written.0.borrow().clone()).unwrap(),
+                   concat!(r#"{"timestamp_ms":12.5,"source_file":"fishing.txt","line":3,"iteration":2,"command":"wait","arguments":{"distribution":"normal","max_ms":200,"min_ms":100,"waited_ms":150},"score":null,"outcome":"done"}"#, "\n"));
+    }
+
+    #[test]
+    fn test_command_kind_is_the_script_command() {
+        for line in ["key: a click", "key: escape press", "key_sequence: hi", "key_sequence_delay: delay=5 hi", "key_sequence_speed: delay=5",
+                     "mouse_move: 1 2", "mouse_move: 1 2 300", "screen_compare_key_click: g 1 2 90 fish.png",
+                     "screen_compare_key_click: return 1 2 90 fish.png", "wait: 100..200", "exit: 4"] {
+            let command = parse_command_from_line(&line.to_string());
+            assert_eq!(command_kind(&command), line.split(':').next().unwrap(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use enigo::*;
+use log::info;
+
+use crate::input_backend::*;
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum HeldInput {
+    Key(enigo::Key),
+    MouseButton(MouseButton)
+}
+
+fn press_input(backend: &mut dyn InputBackend, input: HeldInput) {
+    match input {
+        HeldInput::Key(key) => { backend.key_down(key); },
+        HeldInput::MouseButton(mouse_button) => { backend.mouse_down(mouse_button); }
+    }
+}
+
+fn release_input(backend: &mut dyn InputBackend, input: HeldInput) {
+    match input {
+        HeldInput::Key(key) => { backend.key_up(key); },
+        HeldInput::MouseButton(mouse_button) => { backend.mouse_up(mouse_button); }
+    }
+}
+
+// Every key and mouse button that is currently pressed. Clones share the same set, so a
+// signal handler on another thread can release whatever the executor left pressed
+#[derive(Clone, Default)]
+pub struct HeldInputs {
+    inputs: Arc<Mutex<HashSet<HeldInput>>>
+}
+
+impl HeldInputs {
+    pub fn new() -> HeldInputs {
+        return HeldInputs::default();
+    }
+
+    fn lock_inputs(&self) -> MutexGuard<HashSet<HeldInput>> {
+        // A panic while the set was locked does not make the set itself wrong
+        return self.inputs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+
+    pub fn held(&self) -> Vec<HeldInput> {
+        return self.lock_inputs().iter().copied().collect();
+    }
+
+    pub fn press(&self, backend: &mut dyn InputBackend, input: HeldInput) {
+        press_input(backend, input);
+        self.lock_inputs().insert(input);
+    }
+
+    pub fn release(&self, backend: &mut dyn InputBackend, input: HeldInput) {
+        release_input(backend, input);
+        self.lock_inputs().remove(&input);
+    }
+
+    pub fn release_all(&self, backend: &mut dyn InputBackend) -> Vec<HeldInput> {
+        let released_inputs: Vec<HeldInput> = self.lock_inputs().drain().collect();
+
+        for input in &released_inputs {
+            info!(target: "commands_debug", "Releasing held input {:?}", input);
+            release_input(backend, *input);
+        }
+
+        return released_inputs;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,339 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use enigo::*;
+use image::RgbaImage;
+use log::info;
+
+use crate::command_types::*;
+use crate::screen_compare::*;
+
+// Where text meant for the person running a script goes, like the actions of a dry run or a pause notice.
+// The program prints it, and without one it is logged
+pub type OutputSink = Arc<dyn Fn(&str) + Send + Sync>;
+
+pub fn write_output(output: Option<&OutputSink>, message: &str) {
+    match output {
+        Some(output) => output(message),
+        None => info!(target: "commands_debug", "{}", message)
+    }
+}
+
+// Everything the executor does to the machine goes through a backend, so a script can
+// run against the real keyboard, mouse and screen or against a simulation of them
+pub trait InputBackend {
+    fn key_down(&mut self, key: enigo::Key);
+    fn key_up(&mut self, key: enigo::Key);
+    fn key_click(&mut self, key: enigo::Key);
+    fn key_sequence(&mut self, sequence: &str);
+    fn mouse_down(&mut self, mouse_button: MouseButton);
+    fn mouse_up(&mut self, mouse_button: MouseButton);
+    fn mouse_click(&mut self, mouse_button: MouseButton);
+    fn mouse_move_to(&mut self, x: i32, y: i32);
+    fn mouse_move_relative(&mut self, x: i32, y: i32);
+    fn mouse_location(&self) -> (i32, i32);
+    // From 0 to 1. An image that does not all fit on the screen scores 0
+    fn compare_screen_to_image_file(&mut self, input_file_path: &String, start_x: i32, start_y: i32) -> Result<f64, CaptureError>;
+    // The top left corner of the first place the image is found on the screen, if it is found
+    fn find_image_on_screen(&mut self, input_file_path: &String) -> Result<Option<(i32, i32)>, CaptureError>;
+    // Fails if there is no screen to capture, or the area is not all on it
+    fn capture_screen_area(&mut self, start_x: i32, start_y: i32, width: u32, height: u32) -> Result<RgbaImage, CaptureError>;
+    fn sleep(&mut self, duration: Duration);
+    // Time since the backend was created
+    fn elapsed(&self) -> Duration;
+
+    fn command_started(&mut self, _iteration: u64, _script_line: &ScriptLine) {}
+
+    // Simulated time only passes in sleep, so waiting by watching the clock would never end
+    fn is_simulated(&self) -> bool {
+        return false;
+    }
+}
+
+pub struct EnigoBackend {
+    enigo: Enigo,
+    start_time: Instant
+}
+
+impl EnigoBackend {
+    pub fn new() -> EnigoBackend {
+        return EnigoBackend {
+            enigo: Enigo::new(),
+            start_time: Instant::now()
+        };
+    }
+}
+
+impl InputBackend for EnigoBackend {
+    fn key_down(&mut self, key: enigo::Key) {
+        self.enigo.key_down(key);
+    }
+
+    fn key_up(&mut self, key: enigo::Key) {
+        self.enigo.key_up(key);
+    }
+
+    fn key_click(&mut self, key: enigo::Key) {
+        self.enigo.key_click(key);
+    }
+
+    fn key_sequence(&mut self, sequence: &str) {
+        self.enigo.key_sequence(sequence);
+    }
+
+    fn mouse_down(&mut self, mouse_button: MouseButton) {
+        self.enigo.mouse_down(mouse_button);
+    }
+
+    fn mouse_up(&mut self, mouse_button: MouseButton) {
+        self.enigo.mouse_up(mouse_button);
+    }
+
+    fn mouse_click(&mut self, mouse_button: MouseButton) {
+        self.enigo.mouse_click(mouse_button);
+    }
+
+    fn mouse_move_to(&mut self, x: i32, y: i32) {
+        self.enigo.mouse_move_to(x, y);
+    }
+
+    fn mouse_move_relative(&mut self, x: i32, y: i32) {
+        self.enigo.mouse_move_relative(x, y);
+    }
+
+    fn mouse_location(&self) -> (i32, i32) {
+        return self.enigo.mouse_location();
+    }
+
+    fn compare_screen_to_image_file(&mut self, input_file_path: &String, start_x: i32, start_y: i32) -> Result<f64, CaptureError> {
+        return compare_screen_to_image_file(input_file_path, start_x, start_y);
+    }
+
+    fn find_image_on_screen(&mut self, input_file_path: &String) -> Result<Option<(i32, i32)>, CaptureError> {
+        return find_image_on_screen(input_file_path);
+    }
+
+    fn capture_screen_area(&mut self, start_x: i32, start_y: i32, width: u32, height: u32) -> Result<RgbaImage, CaptureError> {
+        return capture_screen_area(start_x, start_y, width, height);
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+
+    fn elapsed(&self) -> Duration {
+        return self.start_time.elapsed();
+    }
+}
+
+// Passes everything to the wrapped backend, and tells the callback about each command as it starts
+pub struct ProgressBackend {
+    backend: Box<dyn InputBackend>,
+    on_command_started: Box<dyn FnMut(u64, &ScriptLine)>
+}
+
+impl ProgressBackend {
+    pub fn new(backend: Box<dyn InputBackend>, on_command_started: Box<dyn FnMut(u64, &ScriptLine)>) -> ProgressBackend {
+        return ProgressBackend {
+            backend,
+            on_command_started
+        };
+    }
+}
+
+impl InputBackend for ProgressBackend {
+    fn key_down(&mut self, key: enigo::Key) {
+        self.backend.key_down(key);
+    }
+
+    fn key_up(&mut self, key: enigo::Key) {
+        self.backend.key_up(key);
+    }
+
+    fn key_click(&mut self, key: enigo::Key) {
+        self.backend.key_click(key);
+    }
+
+    fn key_sequence(&mut self, sequence: &str) {
+        self.backend.key_sequence(sequence);
+    }
+
+    fn mouse_down(&mut self, mouse_button: MouseButton) {
+        self.backend.mouse_down(mouse_button);
+    }
+
+    fn mouse_up(&mut self, mouse_button: MouseButton) {
+        self.backend.mouse_up(mouse_button);
+    }
+
+    fn mouse_click(&mut self, mouse_button: MouseButton) {
+        self.backend.mouse_click(mouse_button);
+    }
+
+    fn mouse_move_to(&mut self, x: i32, y: i32) {
+        self.backend.mouse_move_to(x, y);
+    }
+
+    fn mouse_move_relative(&mut self, x: i32, y: i32) {
+        self.backend.mouse_move_relative(x, y);
+    }
+
+    fn mouse_location(&self) -> (i32, i32) {
+        return self.backend.mouse_location();
+    }
+
+    fn compare_screen_to_image_file(&mut self, input_file_path: &String, start_x: i32, start_y: i32) -> Result<f64, CaptureError> {
+        return self.backend.compare_screen_to_image_file(input_file_path, start_x, start_y);
+    }
+
+    fn find_image_on_screen(&mut self, input_file_path: &String) -> Result<Option<(i32, i32)>, CaptureError> {
+        return self.backend.find_image_on_screen(input_file_path);
+    }
+
+    fn capture_screen_area(&mut self, start_x: i32, start_y: i32, width: u32, height: u32) -> Result<RgbaImage, CaptureError> {
+        return self.backend.capture_screen_area(start_x, start_y, width, height);
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.backend.sleep(duration);
+    }
+
+    fn elapsed(&self) -> Duration {
+        return self.backend.elapsed();
+    }
+
+    fn command_started(&mut self, iteration: u64, script_line: &ScriptLine) {
+        self.backend.command_started(iteration, script_line);
+        (self.on_command_started)(iteration, script_line);
+    }
+
+    fn is_simulated(&self) -> bool {
+        return self.backend.is_simulated();
+    }
+}
+
+// Writes out what would be done instead of doing it. Waits only move a simulated clock forward,
+// so a long script finishes straight away
+pub struct DryRunBackend {
+    simulated_time: Duration,
+    mouse_location: (i32, i32),
+    // Screen comparisons are made against this image if there is one
+    fake_screen: Option<RgbaImage>,
+    // Otherwise every screen comparison gives this result
+    assumed_match_percentage: f64,
+    output: Option<OutputSink>
+}
+
+impl DryRunBackend {
+    // The actions are logged
+    pub fn new(fake_screen: Option<RgbaImage>, assumed_match_percentage: f64) -> DryRunBackend {
+        return DryRunBackend {
+            simulated_time: Duration::ZERO,
+            mouse_location: (0, 0),
+            fake_screen,
+            assumed_match_percentage,
+            output: None
+        };
+    }
+
+    // The actions go to output, one line each
+    pub fn with_output(fake_screen: Option<RgbaImage>, assumed_match_percentage: f64, output: OutputSink) -> DryRunBackend {
+        let mut dry_run_backend = DryRunBackend::new(fake_screen, assumed_match_percentage);
+        dry_run_backend.output = Some(output);
+        return dry_run_backend;
+    }
+
+    fn print_action(&self, action: String) {
+        write_output(self.output.as_ref(), &format!("{:>12.3}s        {}", self.simulated_time.as_secs_f64(), action));
+    }
+}
+
+impl InputBackend for DryRunBackend {
+    fn key_down(&mut self, key: enigo::Key) {
+        self.print_action(format!("key_down {:?}", key));
+    }
+
+    fn key_up(&mut self, key: enigo::Key) {
+        self.print_action(format!("key_up {:?}", key));
+    }
+
+    fn key_click(&mut self, key: enigo::Key) {
+        self.print_action(format!("key_click {:?}", key));
+    }
+
+    fn key_sequence(&mut self, sequence: &str) {
+        self.print_action(format!("key_sequence {:?}", sequence));
+    }
+
+    fn mouse_down(&mut self, mouse_button: MouseButton) {
+        self.print_action(format!("mouse_down {:?}", mouse_button));
+    }
+
+    fn mouse_up(&mut self, mouse_button: MouseButton) {
+        self.print_action(format!("mouse_up {:?}", mouse_button));
+    }
+
+    fn mouse_click(&mut self, mouse_button: MouseButton) {
+        self.print_action(format!("mouse_click {:?}", mouse_button));
+    }
+
+    fn mouse_move_to(&mut self, x: i32, y: i32) {
+        self.mouse_location = (x, y);
+        self.print_action(format!("mouse_move_to {} {}", x, y));
+    }
+
+    fn mouse_move_relative(&mut self, x: i32, y: i32) {
+        self.mouse_location = (self.mouse_location.0 + x, self.mouse_location.1 + y);
+        self.print_action(format!("mouse_move_relative {} {}", x, y));
+    }
+
+    fn mouse_location(&self) -> (i32, i32) {
+        return self.mouse_location;
+    }
+
+    fn compare_screen_to_image_file(&mut self, input_file_path: &String, start_x: i32, start_y: i32) -> Result<f64, CaptureError> {
+        let match_percentage = match &self.fake_screen {
+            Some(fake_screen) => compare_fake_screen_to_image_file(fake_screen, input_file_path, start_x, start_y)?,
+            None => self.assumed_match_percentage / 100.0
+        };
+
+        self.print_action(format!("screen comparison with {} scored {}%", input_file_path, match_percentage * 100.0));
+        return Ok(match_percentage);
+    }
+
+    // Without a fake screen there is nowhere to look, so the image is never found
+    fn find_image_on_screen(&mut self, input_file_path: &String) -> Result<Option<(i32, i32)>, CaptureError> {
+        let found_location = match &self.fake_screen {
+            Some(fake_screen) => find_image_on_fake_screen(fake_screen, input_file_path)?,
+            None => None
+        };
+
+        self.print_action(format!("search for {} found {:?}", input_file_path, found_location));
+        return Ok(found_location);
+    }
+
+    fn capture_screen_area(&mut self, start_x: i32, start_y: i32, width: u32, height: u32) -> Result<RgbaImage, CaptureError> {
+        let fake_screen = self.fake_screen.as_ref().ok_or(CaptureError::NoScreen)?;
+        if !is_area_on_screen(fake_screen.width(), fake_screen.height(), start_x, start_y, width, height) {
+            return Err(CaptureError::OutsideScreen);
+        }
+
+        return Ok(capture_fake_screen_area(fake_screen, start_x, start_y, width, height));
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.print_action(format!("wait {} ms", duration.as_millis()));
+        self.simulated_time += duration;
+    }
+
+    fn elapsed(&self) -> Duration {
+        return self.simulated_time;
+    }
+
+    fn is_simulated(&self) -> bool {
+        return true;
+    }
+
+    fn command_started(&mut self, iteration: u64, script_line: &ScriptLine) {
+        write_output(self.output.as_ref(), &format!("{:>12.3}s  iteration {}, line {}: {}", self.simulated_time.as_secs_f64(), iteration, script_line.line_number, script_line.text));
+    }
+}
\ No newline at end of file
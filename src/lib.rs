@@ -0,0 +1,47 @@
+// The engine behind the scriptable_input_simulator program, for embedding in other tools.
+//
+//     let script = Script::from_file("fishing_automation.txt")?;
+//     let mut executor = Executor::new(Box::new(EnigoBackend::new()));
+//     executor.run(&script, RunOptions{repeat: Repeat::Times(10), ..Default::default()});
+
+pub mod command_types;
+pub mod command_executor;
+pub mod screen_compare;
+pub mod mouse_path;
+pub mod timing;
+pub mod held_inputs;
+pub mod emergency_stop;
+pub mod stop_conditions;
+pub mod pause_control;
+pub mod input_backend;
+pub mod debugger;
+pub mod repl;
+pub mod recorder;
+pub mod run_statistics;
+pub mod event_trace;
+pub mod script;
+pub mod script_builder;
+pub mod script_format;
+pub mod structured_script;
+pub mod rhai_script;
+pub mod control_server;
+#[cfg(unix)]
+pub mod command_socket;
+pub mod error;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "c_api")]
+pub mod c_api;
+
+// The key and mouse button types used by scripts
+pub use enigo::{Key, MouseButton};
+
+pub use crate::command_executor::{CommandExecutor as Executor, ExecutionResult, Repeat, RunOptions};
+pub use crate::command_types::{ParsedCommand, ScriptLine};
+pub use crate::error::Error;
+pub use crate::input_backend::{InputBackend, EnigoBackend, DryRunBackend, OutputSink};
+pub use crate::rhai_script::RhaiScript;
+pub use crate::script::{BadLines, Script};
+pub use crate::script_builder::ScriptBuilder;
+pub use crate::structured_script::ScriptFileFormat;
+pub use crate::stop_conditions::{StopConditions, StopReason};
@@ -1,29 +1,45 @@
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 use std::env;
-use std::fs::File;
-use std::io::*;
-use std::path::Path;
-use clap::{Parser, ArgGroup};
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use clap::{Parser, Subcommand, ArgGroup};
 use log::{error, info};
 use log4rs;
 
-use crate::command_types::*;
-use crate::command_executor::*;
-
-mod command_types;
-mod command_executor;
-mod screen_compare;
+use scriptable_input_simulator::*;
+use scriptable_input_simulator::emergency_stop::*;
+use scriptable_input_simulator::held_inputs::HeldInputs;
+use scriptable_input_simulator::stop_conditions::*;
+use scriptable_input_simulator::timing::check_jitter_percentage;
+use scriptable_input_simulator::pause_control::*;
+use scriptable_input_simulator::debugger::*;
+use scriptable_input_simulator::recorder::*;
+use scriptable_input_simulator::run_statistics::*;
+use scriptable_input_simulator::event_trace::*;
+use scriptable_input_simulator::script_format::*;
+use scriptable_input_simulator::structured_script::*;
+use scriptable_input_simulator::rhai_script::*;
+use scriptable_input_simulator::control_server::*;
+use scriptable_input_simulator::repl::*;
+#[cfg(unix)]
+use scriptable_input_simulator::command_socket::*;
 
 #[derive(Parser)]
-#[command(author, version, about = "", long_about = None)]
+#[command(author, version, about = "", long_about = None, subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 #[clap(group(
         ArgGroup::new("repeat-commands")
             .required(false)
             .args(&["arg_times_to_execute_commands", "arg_duration_to_execute_commands"])
     ))]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
     #[arg(short='f', long="file_name", value_name = "file", required = true)]
-    arg_file_name: String,
+    #[arg(long_help="The script to run. Files ending in .yaml, .yml, .json, .toml or .rhai are read in that format, and anything else as a text script. - reads a text script from stdin")]
+    arg_file_name: Option<String>,
 
     #[arg(short='s', long="start_delay", value_name = "start_delay_ms", required = false, default_value_t=0)]
     #[arg(long_help="The program will wait this long before executing the commands. This is in milliseconds")]
@@ -33,7 +49,258 @@ struct Cli {
     arg_times_to_execute_commands: Option<u64>,
 
     #[arg(short='d', long="duration_to_execute_commands", value_name = "duration_to_execute_commands_ms", required = false)]
-    arg_duration_to_execute_commands: Option<u64>
+    arg_duration_to_execute_commands: Option<u64>,
+
+    #[arg(short='j', long="jitter", value_name = "jitter_percentage", required = false, default_value_t=0.0)]
+    #[arg(long_help="Every wait is randomly made up to this many percent shorter or longer, from 0 to 1000")]
+    arg_jitter: f64,
+
+    #[arg(long="seed", value_name = "seed", required = false)]
+    #[arg(long_help="The seed for all random behaviour. Runs with the same seed and script make the same random choices. A random seed is used if this is not given")]
+    arg_seed: Option<u64>,
+
+    #[arg(long="stop_hotkey", value_name = "keys", required = false, default_value = "control+alt+escape")]
+    #[arg(long_help="Pressing these keys together stops the program and releases any held keys and mouse buttons. Key names are joined with +. Use none to turn the hotkey off")]
+    arg_stop_hotkey: String,
+
+    #[arg(long="pause_hotkey", value_name = "keys", required = false, default_value = "control+alt+p")]
+    #[arg(long_help="Pressing these keys together pauses the program, releasing any held keys and mouse buttons. Pressing them again presses the held keys and mouse buttons again and resumes. Key names are joined with +. Use none to turn the hotkey off")]
+    arg_pause_hotkey: String,
+
+    #[arg(long="pause_counts_toward_time", required = false)]
+    #[arg(long_help="Time spent paused uses up the current wait and the --duration_to_execute_commands time. By default, both continue where they left off after resuming")]
+    arg_pause_counts_toward_time: bool,
+
+    #[arg(long="corner_failsafe", required = false)]
+    #[arg(long_help="Moving the mouse cursor into a corner of the main screen stops the program and releases any held keys and mouse buttons")]
+    arg_corner_failsafe: bool,
+
+    #[arg(long="dry_run", alias="dry-run", required = false)]
+    #[arg(long_help="Prints what the script would do, with the line and iteration of each command and the simulated time, instead of doing it. Waits are not actually waited, so the whole run finishes straight away")]
+    arg_dry_run: bool,
+
+    #[arg(long="dry_run_screen", value_name = "image_file", required = false)]
+    #[arg(long_help="In a dry run, screen comparisons compare against this image instead of the screen")]
+    arg_dry_run_screen: Option<String>,
+
+    #[arg(long="dry_run_match", value_name = "match_percentage", required = false, default_value_t=100.0)]
+    #[arg(long_help="In a dry run without --dry_run_screen, every screen comparison matches this many percent of pixels")]
+    arg_dry_run_match: f64,
+
+    #[arg(long="step", required = false)]
+    #[arg(long_help="Runs the script in the debugger, which pauses before every command and at every breakpoint: line. Type help at the debugger prompt for its commands")]
+    arg_step: bool,
+
+    #[arg(long="statistics", required = false)]
+    #[arg(long_help="Prints a summary of the run when the program finishes: iterations completed, how often and how long each command ran, and how often each screen comparison matched")]
+    arg_statistics: bool,
+
+    #[arg(long="report", value_name = "report_file", required = false)]
+    #[arg(long_help="Writes the run statistics to <report_file> as JSON when the program finishes")]
+    arg_report: Option<String>,
+
+    #[arg(long="trace", value_name = "trace_file", required = false)]
+    #[arg(long_help="Writes every executed command to <trace_file> as one JSON object per line, with its time, line, arguments, screen comparison score and outcome")]
+    arg_trace: Option<String>,
+
+    #[arg(long="stop_when_image_appears", value_names = ["start_x", "start_y", "match_threshold", "image_file"], num_args = 4, required = false)]
+    #[arg(long_help="Stops the program once the screen area at <start_x> <start_y> matches <image_file> by at least <match_threshold>%, compared the same way as screen_compare_key_click")]
+    arg_stop_when_image_appears: Option<Vec<String>>,
+
+    #[arg(long="stop_when_image_disappears", value_names = ["start_x", "start_y", "match_threshold", "image_file"], num_args = 4, required = false)]
+    #[arg(long_help="Stops the program once the screen area at <start_x> <start_y> no longer matches <image_file> by at least <match_threshold>%")]
+    arg_stop_when_image_disappears: Option<Vec<String>>,
+
+    #[arg(long="until", value_name = "HH:MM", required = false)]
+    #[arg(long_help="Stops the program the next time the clock reads HH:MM, or HH:MM:SS")]
+    arg_until: Option<String>,
+
+    #[arg(long="max_failed_comparisons", value_name = "count", required = false)]
+    #[arg(long_help="Stops the program after this many screen comparisons in a row did not match")]
+    arg_max_failed_comparisons: Option<u64>,
+
+    #[arg(long="stop_file", value_name = "file", required = false)]
+    #[arg(long_help="Stops the program once <file> exists")]
+    arg_stop_file: Option<String>,
+
+    #[arg(long="busy_wait", required = false)]
+    #[arg(long_help="Spends the last millisecond of every wait checking the time instead of sleeping, for more precise timing at the cost of CPU use")]
+    arg_busy_wait: bool,
+
+    #[arg(long="strict", required = false)]
+    #[arg(long_help="Stops before executing anything if a line is badly formatted, and says which line it is. By default, badly formatted lines are left out")]
+    arg_strict: bool
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Records keyboard and mouse activity and writes it out as a script
+    Record {
+        #[arg(short='o', long="output_file", value_name = "file", required = true)]
+        arg_output_file: String,
+
+        #[arg(long="stop_hotkey", value_name = "keys", required = false, default_value = "control+f12")]
+        #[arg(long_help="Pressing these keys together stops the recording. Key names are joined with +")]
+        arg_stop_hotkey: String,
+
+        #[arg(long="coalesce_mouse", required = false)]
+        #[arg(long_help="Only keep the position the mouse cursor stopped at, instead of every position along the way")]
+        arg_coalesce_mouse: bool,
+
+        #[arg(long="round_waits", value_name = "round_waits_ms", required = false, default_value_t=0)]
+        #[arg(long_help="Rounds every wait to the nearest multiple of this many milliseconds. Waits that round to 0 are left out")]
+        arg_round_waits: u64
+    },
+    /// Rewrites script files in their canonical form
+    Fmt {
+        #[arg(value_name = "files", required = true)]
+        arg_files: Vec<String>,
+
+        #[arg(long="check", required = false)]
+        #[arg(long_help="Lists the files that are not in their canonical form instead of rewriting them, and exits with 1 if there are any")]
+        arg_check: bool
+    },
+    /// Translates a script between the text, YAML, JSON and TOML formats, chosen by the file extensions
+    Convert {
+        #[arg(value_name = "input_file")]
+        arg_input_file: String,
+
+        #[arg(value_name = "output_file")]
+        arg_output_file: String
+    },
+    /// Runs a local HTTP server with a JSON API for starting, watching, pausing and stopping scripts
+    Serve {
+        #[arg(long="address", value_name = "address:port", required = false, default_value = DEFAULT_CONTROL_SERVER_ADDRESS)]
+        arg_address: String,
+
+        #[arg(long="token", value_name = "token", required = false)]
+        #[arg(long_help="Every request has to send this as \"Authorization: Bearer <token>\". Without this or --token_file, the SIS_CONTROL_TOKEN environment variable is used, and a random token is made and printed if that is not set either")]
+        arg_token: Option<String>,
+
+        #[arg(long="token_file", value_name = "file", required = false, conflicts_with = "arg_token")]
+        #[arg(long_help="Reads the token from the first line of this file, which keeps it out of the process list")]
+        arg_token_file: Option<String>,
+
+        #[arg(long="allow_remote", required = false)]
+        #[arg(long_help="Allows an --address that other machines can connect to. Anyone with the token can then press keys on this machine")]
+        arg_allow_remote: bool,
+
+        #[arg(long="stop_hotkey", value_name = "keys", required = false, default_value = "control+alt+escape")]
+        #[arg(long_help="Pressing these keys together stops the running script and releases any held keys and mouse buttons. Key names are joined with +. Use none to turn the hotkey off")]
+        arg_stop_hotkey: String,
+
+        #[arg(long="corner_failsafe", required = false)]
+        #[arg(long_help="Moving the mouse cursor into a corner of the main screen stops the running script and releases any held keys and mouse buttons")]
+        arg_corner_failsafe: bool,
+
+        #[arg(long="dry_run", alias="dry-run", required = false)]
+        #[arg(long_help="Prints what each script would do instead of doing it")]
+        arg_dry_run: bool,
+
+        #[arg(long="dry_run_match", value_name = "match_percentage", required = false, default_value_t=100.0)]
+        #[arg(long_help="In a dry run, every screen comparison matches this many percent of pixels")]
+        arg_dry_run_match: f64
+    },
+    /// Runs commands one at a time as they are typed, with line editing and history
+    Repl {
+        #[arg(long="stop_hotkey", value_name = "keys", required = false, default_value = "control+alt+escape")]
+        #[arg(long_help="Pressing these keys together stops the running command, releases any held keys and mouse buttons and leaves the prompt. Key names are joined with +. Use none to turn the hotkey off")]
+        arg_stop_hotkey: String,
+
+        #[arg(long="corner_failsafe", required = false)]
+        #[arg(long_help="Moving the mouse cursor into a corner of the main screen stops the running command, releases any held keys and mouse buttons and leaves the prompt")]
+        arg_corner_failsafe: bool,
+
+        #[arg(long="dry_run", alias="dry-run", required = false)]
+        #[arg(long_help="Prints what each command would do instead of doing it")]
+        arg_dry_run: bool,
+
+        #[arg(long="dry_run_screen", value_name = "image_file", required = false)]
+        #[arg(long_help="In a dry run, screen comparisons, pixel and capture look at this image instead of the screen")]
+        arg_dry_run_screen: Option<String>,
+
+        #[arg(long="dry_run_match", value_name = "match_percentage", required = false, default_value_t=100.0)]
+        #[arg(long_help="In a dry run without --dry_run_screen, every screen comparison matches this many percent of pixels")]
+        arg_dry_run_match: f64
+    },
+    /// Listens on a Unix domain socket and runs each line sent to it as a command straight away
+    #[cfg(unix)]
+    Listen {
+        #[arg(long="socket", value_name = "path", required = true)]
+        arg_socket: String,
+
+        #[arg(long="stop_hotkey", value_name = "keys", required = false, default_value = "control+alt+escape")]
+        #[arg(long_help="Pressing these keys together stops the connected client's commands and releases any held keys and mouse buttons. Key names are joined with +. Use none to turn the hotkey off")]
+        arg_stop_hotkey: String,
+
+        #[arg(long="corner_failsafe", required = false)]
+        #[arg(long_help="Moving the mouse cursor into a corner of the main screen stops the connected client's commands and releases any held keys and mouse buttons")]
+        arg_corner_failsafe: bool,
+
+        #[arg(long="dry_run", alias="dry-run", required = false)]
+        #[arg(long_help="Prints what each command would do instead of doing it")]
+        arg_dry_run: bool,
+
+        #[arg(long="dry_run_match", value_name = "match_percentage", required = false, default_value_t=100.0)]
+        #[arg(long_help="In a dry run, every screen comparison matches this many percent of pixels")]
+        arg_dry_run_match: f64
+    }
+}
+
+// A .rhai file is code for the embedded engine, anything else is a list of commands
+enum LoadedScript {
+    Commands(Script),
+    Rhai(RhaiScript)
+}
+
+// --file_name - reads a text script from stdin, so scripts can be piped in from other programs
+const STDIN_FILE_NAME: &str = "-";
+// Kept next to the executable, like the logs
+const REPL_HISTORY_FILE: &str = "repl_history.txt";
+
+fn read_script_from_stdin(bad_lines: BadLines) -> Result<Script, Error> {
+    let mut text = String::new();
+    std::io::stdin().read_to_string(&mut text).map_err(|error_reason| Error::Io{path: String::from("stdin"), source: error_reason})?;
+
+    return Script::parse_with(&text, bad_lines);
+}
+
+// The library leaves it to the program to say where its messages go, and here they are printed
+fn print_output() -> OutputSink {
+    return Arc::new(|message: &str| println!("{}", message));
+}
+
+// Ctrl+C and termination signals skip the executor's cleanup, so release held inputs here before exiting
+fn release_held_inputs_on_stop_signal(held_inputs: HeldInputs) {
+    let signal_handler_result = ctrlc::set_handler(move || {
+        info!(target: "commands_debug", "Received a stop signal, releasing held inputs");
+        held_inputs.release_all(&mut EnigoBackend::new());
+        std::process::exit(130);
+    });
+    if let Err(reason) = signal_handler_result {
+        error!(target: "commands_debug", "Failed to set the stop signal handler: {}", reason);
+    }
+}
+
+// For a script run, the first stop signal stops the run like a stop condition, so held inputs are released
+// and the statistics and report are still written. A second one exits straight away, for a run that is
+// stuck somewhere the stop is not checked
+fn stop_run_on_stop_signal(held_inputs: HeldInputs) -> Arc<AtomicBool> {
+    let stop_signal = Arc::new(AtomicBool::new(false));
+    let handler_stop_signal = Arc::clone(&stop_signal);
+    let signal_handler_result = ctrlc::set_handler(move || {
+        if handler_stop_signal.swap(true, Ordering::Relaxed) {
+            info!(target: "commands_debug", "Received a second stop signal, releasing held inputs");
+            held_inputs.release_all(&mut EnigoBackend::new());
+            std::process::exit(130);
+        }
+        info!(target: "commands_debug", "Received a stop signal, stopping the run");
+    });
+    if let Err(reason) = signal_handler_result {
+        error!(target: "commands_debug", "Failed to set the stop signal handler: {}", reason);
+    }
+
+    return stop_signal;
 }
 
 fn main() {
@@ -52,54 +319,388 @@ fn main() {
     info!(target: "commands_debug", "Reading arguments now");
 
     let args = Cli::parse();
-    let file_name = args.arg_file_name;
 
-    if args.arg_start_delay > 0 {
+    match &args.command {
+        Some(CliCommand::Record{arg_output_file, arg_stop_hotkey, arg_coalesce_mouse, arg_round_waits}) => {
+            record_script(arg_output_file, arg_stop_hotkey, RecordingOptions{coalesce_mouse_moves: *arg_coalesce_mouse,
+                                                                             wait_rounding_ms: *arg_round_waits});
+            return;
+        },
+        Some(CliCommand::Fmt{arg_files, arg_check}) => {
+            std::process::exit(format_script_files(arg_files, *arg_check));
+        },
+        Some(CliCommand::Convert{arg_input_file, arg_output_file}) => {
+            convert_script(arg_input_file, arg_output_file);
+            return;
+        },
+        Some(CliCommand::Serve{arg_address, arg_token, arg_token_file, arg_allow_remote, arg_stop_hotkey, arg_corner_failsafe, arg_dry_run, arg_dry_run_match}) => {
+            let address = match arg_address.parse() {
+                Ok(address) => address,
+                Err(_) => {
+                    error!(target: "commands_debug", "The address {} could not be read", arg_address);
+                    panic!("The address {} could not be read", arg_address);
+                }
+            };
+            let token = match (arg_token, arg_token_file, std::env::var(TOKEN_ENV_VAR).ok().filter(|token| !token.is_empty())) {
+                (Some(token), _, _) => token.clone(),
+                (None, Some(token_file_path), _) => match read_token_file(token_file_path) {
+                    Ok(token) => token,
+                    Err(error_reason) => {
+                        error!(target: "commands_debug", "{}", error_reason);
+                        panic!("{}", error_reason);
+                    }
+                },
+                (None, None, Some(token)) => token,
+                (None, None, None) => {
+                    let token = generate_token();
+                    println!("Token: {}", token);
+                    token
+                }
+            };
+
+            let server_options = ControlServerOptions {
+                address,
+                token,
+                allow_remote: *arg_allow_remote,
+                dry_run: *arg_dry_run,
+                dry_run_match: *arg_dry_run_match,
+                stop_hotkey: read_hotkey_argument(arg_stop_hotkey),
+                corner_failsafe: *arg_corner_failsafe,
+                held_inputs: HeldInputs::new(),
+                output: Some(print_output())
+            };
+            if !*arg_dry_run {
+                release_held_inputs_on_stop_signal(server_options.held_inputs.clone());
+            }
+            println!("Listening on http://{}", address);
+            if let Err(error_reason) = serve(&server_options) {
+                error!(target: "commands_debug", "{}", error_reason);
+                panic!("{}", error_reason);
+            }
+            return;
+        },
+        Some(CliCommand::Repl{arg_stop_hotkey, arg_corner_failsafe, arg_dry_run, arg_dry_run_screen, arg_dry_run_match}) => {
+            let mut command_executor = if *arg_dry_run {
+                let fake_screen = arg_dry_run_screen.as_ref().map(|fake_screen_path| image::open(fake_screen_path).expect("Dry run screen file not found").to_rgba8());
+                Executor::new(Box::new(DryRunBackend::with_output(fake_screen, *arg_dry_run_match, print_output())))
+            }
+            else {
+                let mut command_executor = Executor::new(Box::new(EnigoBackend::new()));
+                command_executor.start_run(RunOptions {
+                    emergency_stop: Some(create_emergency_stop(arg_stop_hotkey, *arg_corner_failsafe)),
+                    ..Default::default()
+                });
+                command_executor
+            };
+            command_executor.set_output(print_output());
+
+            let mut repl = Repl::new(command_executor);
+            if !*arg_dry_run {
+                release_held_inputs_on_stop_signal(repl.held_inputs());
+            }
+            if let Err(error_reason) = run_repl(&mut repl, &PathBuf::from(REPL_HISTORY_FILE)) {
+                error!(target: "commands_debug", "{}", error_reason);
+                panic!("{}", error_reason);
+            }
+            return;
+        },
+        #[cfg(unix)]
+        Some(CliCommand::Listen{arg_socket, arg_stop_hotkey, arg_corner_failsafe, arg_dry_run, arg_dry_run_match}) => {
+            let socket_options = CommandSocketOptions {
+                path: PathBuf::from(arg_socket),
+                dry_run: *arg_dry_run,
+                dry_run_match: *arg_dry_run_match,
+                stop_hotkey: read_hotkey_argument(arg_stop_hotkey),
+                corner_failsafe: *arg_corner_failsafe,
+                held_inputs: HeldInputs::new(),
+                output: Some(print_output())
+            };
+            if !*arg_dry_run {
+                release_held_inputs_on_stop_signal(socket_options.held_inputs.clone());
+            }
+            println!("Listening on {}", arg_socket);
+            if let Err(error_reason) = listen(&socket_options) {
+                error!(target: "commands_debug", "{}", error_reason);
+                panic!("{}", error_reason);
+            }
+            return;
+        },
+        None => {}
+    }
+
+    let file_name = args.arg_file_name.clone().expect("The file name option is required");
+    let bad_lines = if args.arg_strict { BadLines::Reject } else { BadLines::Skip };
+    let loaded_script = if file_name == STDIN_FILE_NAME {
+        read_script_from_stdin(bad_lines).map(LoadedScript::Commands)
+    }
+    else if is_rhai_file(&file_name) {
+        RhaiScript::from_file(&file_name).map(LoadedScript::Rhai)
+    }
+    else {
+        Script::from_file_with(&file_name, bad_lines).map(LoadedScript::Commands)
+    };
+    let script = match loaded_script {
+        Ok(script) => script,
+        Err(error_reason) => {
+            error!(target: "commands_debug", "{}", error_reason);
+            panic!("{}", error_reason);
+        }
+    };
+
+    if args.arg_start_delay > 0 && !args.arg_dry_run {
         let start_delay: Duration = Duration::from_millis(args.arg_start_delay);
         std::thread::sleep(start_delay);
     }
 
-    let mut command_sequence: Vec<ParsedCommand> = vec![];
-    read_input_file(&file_name, &mut command_sequence);
+    let mut run_options = RunOptions {
+        repeat: read_repeat_arguments(&args),
+        seed: args.arg_seed,
+        wait_jitter_percentage: read_jitter_argument(args.arg_jitter),
+        pause_counts_toward_time: args.arg_pause_counts_toward_time,
+        ..Default::default()
+    };
+
+    let mut command_executor: Executor;
+    let mut stop_signal = None;
+    if args.arg_dry_run {
+        let fake_screen = args.arg_dry_run_screen.as_ref().map(|fake_screen_path| image::open(fake_screen_path).expect("Dry run screen file not found").to_rgba8());
+        let dry_run_backend = DryRunBackend::with_output(fake_screen, args.arg_dry_run_match, print_output());
+
+        // Nothing real is pressed in a dry run, so there is nothing to stop, pause or release
+        command_executor = Executor::new(Box::new(dry_run_backend));
+    }
+    else {
+        run_options.emergency_stop = Some(create_emergency_stop(&args.arg_stop_hotkey, args.arg_corner_failsafe));
+        run_options.pause_control = Some(PauseControl::new(read_hotkey_argument(&args.arg_pause_hotkey)));
+        run_options.busy_wait = args.arg_busy_wait;
+
+        command_executor = Executor::new(Box::new(EnigoBackend::new()));
+        stop_signal = Some(stop_run_on_stop_signal(command_executor.held_inputs()));
+    }
+    command_executor.set_output(print_output());
+
+    // On Linux and macOS, SIGUSR2 prints the statistics so far without stopping the run
+    #[cfg(unix)]
+    if let Err(reason) = signal_hook::flag::register(signal_hook::consts::SIGUSR2, command_executor.statistics_requested()) {
+        error!(target: "commands_debug", "Failed to set the statistics signal handler: {}", reason);
+    }
+
+    run_options.stop_conditions = read_stop_conditions(&args, command_executor.elapsed());
+    run_options.stop_conditions.stop_signal = stop_signal;
 
-    if let Some(times_to_execute_commands) = args.arg_times_to_execute_commands {  
-        for _i in 0.. times_to_execute_commands {
-            execute_commands(&command_sequence);
+    if let Some(trace_file_name) = &args.arg_trace {
+        match EventTrace::create_file(trace_file_name, &file_name) {
+            Ok(event_trace) => { run_options.event_trace = Some(event_trace); },
+            Err(error_reason) => {
+                error!(target: "commands_debug", "The trace file {} could not be created: {}", trace_file_name, error_reason);
+                panic!("The trace file {} could not be created: {}", trace_file_name, error_reason);
+            }
         }
     }
+
+    if args.arg_step {
+        println!("{}", DEBUGGER_HELP);
+        run_options.debugger = Some(Debugger::new());
+    }
+
+    let run_result = match &script {
+        LoadedScript::Commands(script) => Ok(command_executor.run(script, run_options)),
+        LoadedScript::Rhai(rhai_script) => rhai_script.run(&mut command_executor, run_options)
+    };
+    if let Err(error_reason) = run_result {
+        error!(target: "commands_debug", "{}", error_reason);
+        panic!("{}", error_reason);
+    }
+
+    let run_statistics = command_executor.statistics();
+    if args.arg_statistics {
+        println!("{}", run_statistics.summary());
+    }
+    if let Some(report_file_name) = args.arg_report {
+        write_report(&report_file_name, &run_statistics);
+    }
+
+    if let Some(stop_reason) = command_executor.stop_reason() {
+        info!(target: "commands_debug", "Stopped because {}", stop_reason);
+        println!("Stopped because {}", stop_reason);
+
+        // Exiting skips destructors, so release held inputs first
+        drop(command_executor);
+        std::process::exit(stop_reason.exit_code());
+    }
+}
+
+fn read_repeat_arguments(args: &Cli) -> Repeat {
+    if let Some(times_to_execute_commands) = args.arg_times_to_execute_commands {
+        return Repeat::Times(times_to_execute_commands);
+    }
     else if let Some(duration_to_execute_commands_ms) = args.arg_duration_to_execute_commands {
-        let execute_commands_duration: Duration = Duration::from_millis(duration_to_execute_commands_ms);
-        let now = SystemTime::now();
-        let execution_end_time = now + execute_commands_duration;
+        return Repeat::For(Duration::from_millis(duration_to_execute_commands_ms));
+    }
 
-        while SystemTime::now() <= execution_end_time {
-            execute_commands(&command_sequence);
+    return Repeat::Once;
+}
+
+fn read_stop_conditions(args: &Cli, start_time: Duration) -> StopConditions {
+    let mut stop_conditions = StopConditions::new();
+
+    stop_conditions.image_appears = args.arg_stop_when_image_appears.as_deref().map(read_image_condition_argument);
+    stop_conditions.image_disappears = args.arg_stop_when_image_disappears.as_deref().map(read_image_condition_argument);
+    stop_conditions.max_consecutive_failed_comparisons = args.arg_max_failed_comparisons;
+    stop_conditions.sentinel_file = args.arg_stop_file.as_ref().map(PathBuf::from);
+
+    if let Some(until) = &args.arg_until {
+        match parse_duration_until(until) {
+            Some(duration_until) => { stop_conditions.stop_time = Some(start_time + duration_until); },
+            None => {
+                error!(target: "commands_debug", "The stop time {} could not be read", until);
+                panic!("The stop time {} could not be read", until);
+            }
         }
     }
-    else {
-        execute_commands(&command_sequence);
+
+    return stop_conditions;
+}
+
+fn read_image_condition_argument(values: &[String]) -> ImageCondition {
+    match parse_image_condition(values) {
+        Some(image_condition) => { return image_condition; },
+        None => {
+            error!(target: "commands_debug", "The image stop condition {} could not be read", values.join(" "));
+            panic!("The image stop condition {} could not be read", values.join(" "));
+        }
     }
 }
 
-fn read_input_file(file_name: &String, command_vector: &mut Vec<ParsedCommand>) {
-    let full_name: &Path = Path::new(file_name);
-    let file: Result<File> = File::open(full_name);
+fn write_report(report_file_name: &str, run_statistics: &RunStatistics) {
+    let write_result = std::fs::write(report_file_name, run_statistics.to_json());
+    match write_result {
+        Ok(_) => { info!(target: "commands_debug", "Wrote the run report to {}", report_file_name); },
+        Err(error_reason) => {
+            error!(target: "commands_debug", "The report file {} could not be written: {}", report_file_name, error_reason);
+            println!("The report file {} could not be written: {}", report_file_name, error_reason);
+        }
+    }
+}
 
-    match file
-    {
-        Ok(file) => {
-            let buf_reader = BufReader::new(file).lines();
-            for line in buf_reader {
-                if let Ok(command) = line {
-                    if !command.is_empty() {
-                        command_vector.push(parse_command_from_line(&command));
-                    }
+// Returns the exit code, which is 1 if a file could not be formatted or, with check, is not formatted
+fn format_script_files(file_names: &[String], check: bool) -> i32 {
+    let mut exit_code = 0;
+
+    for file_name in file_names {
+        let format_result = std::fs::read_to_string(file_name)
+            .map_err(|source| Error::Io{path: file_name.clone(), source})
+            .and_then(|text| format_script(&text).map(|formatted_text| (text, formatted_text)));
+
+        match format_result {
+            Ok((text, formatted_text)) => {
+                if text == formatted_text {
+                    continue;
                 }
+
+                if check {
+                    println!("{} is not formatted", file_name);
+                    exit_code = 1;
+                }
+                else if let Err(error_reason) = std::fs::write(file_name, formatted_text) {
+                    println!("{} could not be written: {}", file_name, error_reason);
+                    exit_code = 1;
+                }
+                else {
+                    println!("Formatted {}", file_name);
+                }
+            },
+            Err(error_reason) => {
+                println!("{}: {}", file_name, error_reason);
+                exit_code = 1;
             }
-        },
+        }
+    }
+
+    return exit_code;
+}
+
+// Comments in a text script are not commands, so they are not carried over
+fn convert_script(input_file_name: &str, output_file_name: &str) {
+    let converted_text = Script::from_file(input_file_name)
+        .and_then(|script| write_commands(&script.commands(), ScriptFileFormat::from_path(output_file_name)));
+
+    let converted_text = match converted_text {
+        Ok(converted_text) => converted_text,
+        Err(error_reason) => {
+            error!(target: "commands_debug", "{}", error_reason);
+            panic!("{}", error_reason);
+        }
+    };
+
+    if let Err(error_reason) = std::fs::write(output_file_name, converted_text) {
+        error!(target: "commands_debug", "The converted script could not be written to {}: {}", output_file_name, error_reason);
+        panic!("The converted script could not be written to {}: {}", output_file_name, error_reason);
+    }
+
+    println!("Converted {} to {}", input_file_name, output_file_name);
+}
+
+fn record_script(output_file_name: &str, stop_hotkey_argument: &str, recording_options: RecordingOptions) {
+    let stop_hotkey = match parse_record_stop_hotkey(stop_hotkey_argument) {
+        Some(stop_hotkey) => stop_hotkey,
+        None => {
+            error!(target: "commands_debug", "The hotkey {} could not be read", stop_hotkey_argument);
+            panic!("The hotkey {} could not be read", stop_hotkey_argument);
+        }
+    };
+
+    println!("Recording. Press {} to stop", stop_hotkey_argument);
+    // Nothing is written when recording fails, so an earlier recording at the same path is kept
+    let recorded_events = match record_events(&stop_hotkey) {
+        Ok(recorded_events) => recorded_events,
         Err(error_reason) => {
-            error!(target: "commands_debug", "The input file {} could not be read: {}", full_name.display(), error_reason);
-            panic!("The input file {} could not be read: {}", full_name.display(), error_reason);
-        } 
+            error!(target: "commands_debug", "{}", error_reason);
+            panic!("{}", error_reason);
+        }
+    };
+    let script_lines = generate_script(&recorded_events, &recording_options);
+
+    let write_result = std::fs::write(output_file_name, script_lines.join("\n"));
+    match write_result {
+        Ok(_) => { println!("Wrote {} lines to {}", script_lines.len(), output_file_name); },
+        Err(error_reason) => {
+            error!(target: "commands_debug", "The output file {} could not be written: {}", output_file_name, error_reason);
+            panic!("The output file {} could not be written: {}", output_file_name, error_reason);
+        }
+    }
+}
+
+fn create_emergency_stop(stop_hotkey_argument: &str, corner_failsafe: bool) -> EmergencyStop {
+    return match EmergencyStop::new(read_hotkey_argument(stop_hotkey_argument), corner_failsafe) {
+        Ok(emergency_stop) => emergency_stop,
+        Err(error_reason) => {
+            error!(target: "commands_debug", "{}", error_reason);
+            panic!("{}", error_reason);
+        }
+    };
+}
+
+fn read_jitter_argument(jitter_argument: f64) -> f64 {
+    return match check_jitter_percentage(jitter_argument) {
+        Ok(jitter_percentage) => jitter_percentage,
+        Err(error_reason) => {
+            error!(target: "commands_debug", "{}", error_reason);
+            panic!("{}", error_reason);
+        }
+    };
+}
+
+fn read_hotkey_argument(hotkey_argument: &str) -> Option<Hotkey> {
+    if hotkey_argument == "none" {
+        return None;
+    }
+
+    let hotkey = parse_hotkey(hotkey_argument);
+    if hotkey.is_none() {
+        error!(target: "commands_debug", "The hotkey {} could not be read", hotkey_argument);
+        panic!("The hotkey {} could not be read", hotkey_argument);
     }
-}
\ No newline at end of file
+
+    return hotkey;
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+pub const DEFAULT_MOUSE_STEP_INTERVAL_MS: u64 = 10;
+
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MousePathType {
+    #[default]
+    Linear,
+    Eased,
+    Bezier
+}
+
+// The name a script uses for the path type
+impl fmt::Display for MousePathType {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            MousePathType::Linear => write!(formatter, "linear"),
+            MousePathType::Eased => write!(formatter, "eased"),
+            MousePathType::Bezier => write!(formatter, "bezier")
+        };
+    }
+}
+
+// Structured scripts can leave out the options that have a default, as text scripts can
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MouseMovement {
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub path_type: MousePathType,
+    #[serde(default)]
+    pub jitter: i32,
+    #[serde(default)]
+    pub overshoot: i32,
+    #[serde(default = "default_step_interval_ms")]
+    pub step_interval_ms: u64
+}
+
+fn default_step_interval_ms() -> u64 {
+    return DEFAULT_MOUSE_STEP_INTERVAL_MS;
+}
+
+pub fn interpolate_linear_path(start_x: i32, start_y: i32, end_x: i32, end_y: i32, steps: u64) -> Vec<(i32, i32)> {
+    let mut path: Vec<(i32, i32)> = vec![];
+
+    // Always produce at least the end point, so the cursor finishes where it was asked to
+    let steps = steps.max(1);
+    for step in 1..= steps {
+        let progress = step as f64 / steps as f64;
+        let x = start_x as f64 + (end_x - start_x) as f64 * progress;
+        let y = start_y as f64 + (end_y - start_y) as f64 * progress;
+
+        path.push((x.round() as i32, y.round() as i32));
+    }
+
+    return path;
+}
+
+pub fn generate_mouse_path<R: Rng>(start_x: i32, start_y: i32, end_x: i32, end_y: i32, movement: &MouseMovement, rng: &mut R) -> Vec<(i32, i32)> {
+    let steps = (movement.duration_ms / movement.step_interval_ms.max(1)).max(1);
+
+    let start = (start_x as f64, start_y as f64);
+    let end = (end_x as f64, end_y as f64);
+    let distance = ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt();
+
+    // When overshooting, travel past the end point first and spend the last few steps coming back to it
+    let mut target = end;
+    let mut return_steps = 0;
+    if movement.overshoot > 0 && distance > 0.0 && steps > 1 {
+        let overshoot = movement.overshoot as f64;
+        target = (end.0 + (end.0 - start.0) / distance * overshoot, end.1 + (end.1 - start.1) / distance * overshoot);
+        return_steps = (steps / 6).max(1);
+    }
+    let travel_steps = steps - return_steps;
+
+    // The bezier control points are pushed off to either side of the straight line so the path curves
+    let perpendicular = if distance > 0.0 {
+        (-(end.1 - start.1) / distance, (end.0 - start.0) / distance)
+    }
+    else {
+        (0.0, 0.0)
+    };
+    let first_offset = rng.gen_range(-0.3..=0.3) * distance;
+    let second_offset = rng.gen_range(-0.3..=0.3) * distance;
+    let first_control = (start.0 + (target.0 - start.0) * 0.3 + perpendicular.0 * first_offset,
+                         start.1 + (target.1 - start.1) * 0.3 + perpendicular.1 * first_offset);
+    let second_control = (start.0 + (target.0 - start.0) * 0.7 + perpendicular.0 * second_offset,
+                          start.1 + (target.1 - start.1) * 0.7 + perpendicular.1 * second_offset);
+
+    let mut path: Vec<(i32, i32)> = vec![];
+    for step in 1..= travel_steps {
+        let progress = step as f64 / travel_steps as f64;
+
+        let (x, y) = match movement.path_type {
+            MousePathType::Linear => interpolate(start, target, progress),
+            MousePathType::Eased => interpolate(start, target, ease_in_out(progress)),
+            MousePathType::Bezier => cubic_bezier(start, first_control, second_control, target, ease_in_out(progress))
+        };
+
+        path.push((x.round() as i32, y.round() as i32));
+    }
+
+    if return_steps > 0 {
+        path.extend(interpolate_linear_path(target.0.round() as i32, target.1.round() as i32, end_x, end_y, return_steps));
+    }
+
+    // Every point except the last one is jittered, so the cursor still ends exactly on the end point
+    if movement.jitter > 0 {
+        let last_point = path.len() - 1;
+        for point in &mut path[..last_point] {
+            point.0 += rng.gen_range(-movement.jitter..=movement.jitter);
+            point.1 += rng.gen_range(-movement.jitter..=movement.jitter);
+        }
+    }
+
+    return path;
+}
+
+fn interpolate(start: (f64, f64), end: (f64, f64), progress: f64) -> (f64, f64) {
+    return (start.0 + (end.0 - start.0) * progress, start.1 + (end.1 - start.1) * progress);
+}
+
+fn ease_in_out(progress: f64) -> f64 {
+    return progress * progress * (3.0 - 2.0 * progress);
+}
+
+fn cubic_bezier(start: (f64, f64), first_control: (f64, f64), second_control: (f64, f64), end: (f64, f64), progress: f64) -> (f64, f64) {
+    let remaining = 1.0 - progress;
+    let start_weight = remaining * remaining * remaining;
+    let first_control_weight = 3.0 * remaining * remaining * progress;
+    let second_control_weight = 3.0 * remaining * progress * progress;
+    let end_weight = progress * progress * progress;
+
+    return (start.0 * start_weight + first_control.0 * first_control_weight + second_control.0 * second_control_weight + end.0 * end_weight,
+            start.1 * start_weight + first_control.1 * first_control_weight + second_control.1 * second_control_weight + end.1 * end_weight);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_interpolate_linear_path() {
+        let path = interpolate_linear_path(0, 0, 100, -50, 4);
+
+        assert_eq!(path, vec![(25, -13), (50, -25), (75, -38), (100, -50)]);
+    }
+
+    #[test]
+    fn test_interpolate_linear_path_zero_steps() {
+        let path = interpolate_linear_path(10, 10, 20, 20, 0);
+
+        assert_eq!(path, vec![(20, 20)]);
+    }
+
+    #[test]
+    fn test_generate_mouse_path_linear() {
+        let movement = MouseMovement{duration_ms: 40, path_type: MousePathType::Linear, jitter: 0, overshoot: 0, step_interval_ms: 10};
+        let path = generate_mouse_path(0, 0, 100, -50, &movement, &mut StdRng::seed_from_u64(0));
+
+        assert_eq!(path, interpolate_linear_path(0, 0, 100, -50, 4));
+    }
+
+    #[test]
+    fn test_generate_mouse_path_ends_on_target() {
+        let movement = MouseMovement{duration_ms: 500, path_type: MousePathType::Bezier, jitter: 3, overshoot: 20, step_interval_ms: 10};
+        let path = generate_mouse_path(10, 700, 640, 80, &movement, &mut StdRng::seed_from_u64(0));
+
+        assert_eq!(path.len(), 50);
+        assert_eq!(path.last(), Some(&(640, 80)));
+    }
+}
\ No newline at end of file
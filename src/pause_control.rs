@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use device_query::DeviceState;
+
+use crate::emergency_stop::*;
+
+pub struct PauseControl {
+    // Only made when there is a hotkey to watch, since it needs a display
+    device_state: Option<DeviceState>,
+    hotkey: Option<Hotkey>,
+    // The hotkey toggles when it goes down, not for as long as it is held
+    hotkey_was_pressed: bool,
+    // Set by SIGUSR1 on platforms that have it
+    signal_toggle_requested: Arc<AtomicBool>,
+    // Whether whoever holds the flag wants the run paused. A pause and a resume that come before
+    // the executor looks cancel out, which toggle requests could not do
+    pause_requested: Option<Arc<AtomicBool>>,
+    // Tracked here so that the pause flag can be compared with it
+    paused: bool,
+    on_pause_changed: Option<Box<dyn FnMut(bool)>>
+}
+
+impl PauseControl {
+    pub fn new(hotkey: Option<Hotkey>) -> PauseControl {
+        let signal_toggle_requested = Arc::new(AtomicBool::new(false));
+
+        #[cfg(unix)]
+        {
+            let register_result = signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&signal_toggle_requested));
+            if let Err(reason) = register_result {
+                log::error!(target: "commands_debug", "Failed to set the pause signal handler: {}", reason);
+            }
+        }
+
+        return PauseControl {
+            device_state: hotkey.as_ref().map(|_| DeviceState::new()),
+            hotkey,
+            hotkey_was_pressed: false,
+            signal_toggle_requested,
+            pause_requested: None,
+            paused: false,
+            on_pause_changed: None
+        };
+    }
+
+    // Pauses while the flag is set and resumes once it is cleared, for callers with no keyboard to
+    // watch, like the control server. on_pause_changed is told each time the executor pauses or resumes
+    pub fn with_pause_flag(pause_requested: Arc<AtomicBool>, on_pause_changed: Box<dyn FnMut(bool)>) -> PauseControl {
+        return PauseControl {
+            device_state: None,
+            hotkey: None,
+            hotkey_was_pressed: false,
+            signal_toggle_requested: Arc::new(AtomicBool::new(false)),
+            pause_requested: Some(pause_requested),
+            paused: false,
+            on_pause_changed: Some(on_pause_changed)
+        };
+    }
+
+    // The executor pauses or resumes each time this is true
+    pub fn is_toggle_requested(&mut self) -> bool {
+        let mut toggle_requested = self.signal_toggle_requested.swap(false, Ordering::Relaxed);
+
+        if let Some(pause_requested) = &self.pause_requested {
+            if pause_requested.load(Ordering::Relaxed) != self.paused {
+                toggle_requested = true;
+            }
+        }
+
+        if let (Some(hotkey), Some(device_state)) = (&self.hotkey, &self.device_state) {
+            let hotkey_pressed = is_hotkey_pressed(device_state, hotkey);
+            if hotkey_pressed && !self.hotkey_was_pressed {
+                toggle_requested = true;
+            }
+            self.hotkey_was_pressed = hotkey_pressed;
+        }
+
+        if toggle_requested {
+            self.paused = !self.paused;
+            if let Some(on_pause_changed) = &mut self.on_pause_changed {
+                on_pause_changed(self.paused);
+            }
+        }
+
+        return toggle_requested;
+    }
+}
\ No newline at end of file
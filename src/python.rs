@@ -0,0 +1,297 @@
+// The scriptable_input_simulator Python module, built with maturin when the python feature is on.
+// Running scripts with Executor(dry_run=True) needs no real keyboard, mouse or screen
+use std::path::Path;
+use pyo3::prelude::*;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyFileNotFoundError, PyOSError, PyValueError};
+
+use crate::command_executor::*;
+use crate::command_types::*;
+use crate::error::Error;
+use crate::input_backend::*;
+use crate::mouse_path::*;
+use crate::screen_compare::{self, CaptureError};
+use crate::script::Script;
+use crate::structured_script::*;
+use crate::timing::check_jitter_percentage;
+
+create_exception!(scriptable_input_simulator, ScriptError, PyException);
+create_exception!(scriptable_input_simulator, ParseError, ScriptError);
+create_exception!(scriptable_input_simulator, BlockError, ScriptError);
+create_exception!(scriptable_input_simulator, RhaiError, ScriptError);
+create_exception!(scriptable_input_simulator, StoppedError, ScriptError);
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> PyErr {
+        let message = error.to_string();
+
+        return match error {
+            Error::Io{..} => PyOSError::new_err(message),
+            Error::Parse{..} | Error::Structured{..} => ParseError::new_err(message),
+            Error::UnmatchedBlock{..} => BlockError::new_err(message),
+            Error::Rhai{..} => RhaiError::new_err(message)
+        };
+    }
+}
+
+impl From<CaptureError> for PyErr {
+    fn from(error: CaptureError) -> PyErr {
+        let message = error.to_string();
+
+        return match error {
+            CaptureError::UnreadableImage(..) => PyValueError::new_err(message),
+            CaptureError::NoScreen | CaptureError::OutsideScreen | CaptureError::Failed(..) => PyOSError::new_err(message)
+        };
+    }
+}
+
+fn read_key(name: &str) -> PyResult<enigo::Key> {
+    return lookup_key(name).ok_or_else(|| PyValueError::new_err(format!("{} is not a key", name)));
+}
+
+fn read_mouse_button(name: &str) -> PyResult<enigo::MouseButton> {
+    return lookup_mouse_button(name).ok_or_else(|| PyValueError::new_err(format!("{} is not a mouse button", name)));
+}
+
+// A missing image is a FileNotFoundError, where a file that is not an image is a ValueError
+fn check_image_file_exists(input_file_path: &str) -> PyResult<()> {
+    if !Path::new(input_file_path).exists() {
+        return Err(PyFileNotFoundError::new_err(format!("The image file {} does not exist", input_file_path)));
+    }
+
+    return Ok(());
+}
+
+#[pyclass(name = "Script", unsendable)]
+pub struct PyScript {
+    script: Script
+}
+
+#[pymethods]
+impl PyScript {
+    #[staticmethod]
+    fn parse(text: &str) -> PyResult<PyScript> {
+        return Ok(PyScript{script: Script::parse(text)?});
+    }
+
+    // Picks the format by the file extension, the same as --file_name
+    #[staticmethod]
+    fn from_file(path: &str) -> PyResult<PyScript> {
+        return Ok(PyScript{script: Script::from_file(path)?});
+    }
+
+    fn __len__(&self) -> usize {
+        return self.script.lines().len();
+    }
+
+    // (line_number, text) for each command
+    fn lines(&self) -> Vec<(usize, String)> {
+        return self.script.lines().iter().map(|script_line| (script_line.line_number, script_line.text.clone())).collect();
+    }
+
+    fn to_text(&self) -> PyResult<String> {
+        return Ok(write_commands(&self.script.commands(), ScriptFileFormat::Text)?);
+    }
+}
+
+// Runs whole scripts or single actions. Every action goes through the same executor as a script
+// command, so held inputs are tracked and released the same way
+#[pyclass(name = "Executor", unsendable)]
+pub struct PyExecutor {
+    executor: CommandExecutor
+}
+
+// allow_threads only asks for Send to keep Python objects out of the closure, and the executor holds none.
+// The closure still runs on this thread, so an executor that is not Send can be used in it
+struct WithoutPythonObjects<T>(T);
+
+unsafe impl<T> Send for WithoutPythonObjects<T> {}
+
+impl<T> WithoutPythonObjects<T> {
+    fn into_inner(self) -> T {
+        return self.0;
+    }
+}
+
+impl PyExecutor {
+    // An action after the executor was stopped does nothing, which raises a StoppedError
+    fn run_command(&mut self, command: ParsedCommand) -> PyResult<()> {
+        let script_line = ScriptLine{line_number: 0, text: command.to_string(), command};
+        if self.executor.execute_command(&script_line) == ExecutionResult::Stopped {
+            let stop_reason = self.executor.stop_reason().map_or(String::from("unknown"), |stop_reason| stop_reason.to_string());
+            return Err(StoppedError::new_err(format!("The executor was stopped: {}", stop_reason)));
+        }
+
+        return Ok(());
+    }
+}
+
+#[pymethods]
+impl PyExecutor {
+    #[new]
+    #[pyo3(signature = (dry_run = false, dry_run_match = 100.0, dry_run_screen = None))]
+    fn new(dry_run: bool, dry_run_match: f64, dry_run_screen: Option<&str>) -> PyResult<PyExecutor> {
+        if !dry_run {
+            return Ok(PyExecutor{executor: CommandExecutor::new(Box::new(EnigoBackend::new()))});
+        }
+
+        let fake_screen = match dry_run_screen {
+            Some(fake_screen_path) => {
+                let fake_screen = image::open(fake_screen_path)
+                    .map_err(|error_reason| PyOSError::new_err(format!("The dry run screen {} could not be read: {}", fake_screen_path, error_reason)))?;
+                Some(fake_screen.to_rgba8())
+            },
+            None => None
+        };
+
+        return Ok(PyExecutor{executor: CommandExecutor::new(Box::new(DryRunBackend::new(fake_screen, dry_run_match)))});
+    }
+
+    // Returns True if the script ran to the end, or False if it was stopped. Other Python threads keep running meanwhile
+    #[pyo3(signature = (script, times = None, duration_ms = None, seed = None, jitter = 0.0))]
+    fn run(&mut self, py: Python<'_>, script: &PyScript, times: Option<u64>, duration_ms: Option<u64>, seed: Option<u64>, jitter: f64) -> PyResult<bool> {
+        let jitter = check_jitter_percentage(jitter).map_err(PyValueError::new_err)?;
+        let repeat = match (times, duration_ms) {
+            (Some(times), _) => Repeat::Times(times),
+            (None, Some(duration_ms)) => Repeat::For(std::time::Duration::from_millis(duration_ms)),
+            (None, None) => Repeat::Once
+        };
+        let run_options = RunOptions{repeat, seed, wait_jitter_percentage: jitter, ..Default::default()};
+
+        let run_arguments = WithoutPythonObjects((&mut self.executor, &script.script, run_options));
+        let execution_result = py.allow_threads(move || {
+            let (executor, script, run_options) = run_arguments.into_inner();
+            executor.run(script, run_options)
+        });
+        return Ok(execution_result == ExecutionResult::Finished);
+    }
+
+    #[getter]
+    fn stop_reason(&self) -> Option<String> {
+        return self.executor.stop_reason().map(|stop_reason| stop_reason.to_string());
+    }
+
+    #[getter]
+    fn exit_code(&self) -> Option<i32> {
+        return self.executor.stop_reason().map(|stop_reason| stop_reason.exit_code());
+    }
+
+    fn statistics_json(&self) -> String {
+        return self.executor.statistics().to_json();
+    }
+
+    fn key_click(&mut self, key: &str) -> PyResult<()> {
+        return self.run_command(key_use_command(read_key(key)?, ButtonAction::Click));
+    }
+
+    fn key_down(&mut self, key: &str) -> PyResult<()> {
+        return self.run_command(key_use_command(read_key(key)?, ButtonAction::Press));
+    }
+
+    fn key_up(&mut self, key: &str) -> PyResult<()> {
+        return self.run_command(key_use_command(read_key(key)?, ButtonAction::Release));
+    }
+
+    fn key_hold(&mut self, key: &str, duration_ms: u64) -> PyResult<()> {
+        return self.run_command(ParsedCommand::KeyHold{key: read_key(key)?, duration_ms});
+    }
+
+    fn type_text(&mut self, text: &str) -> PyResult<()> {
+        return self.run_command(ParsedCommand::KeySequence(text.to_string()));
+    }
+
+    #[pyo3(signature = (button = "left"))]
+    fn mouse_click(&mut self, button: &str) -> PyResult<()> {
+        return self.run_command(ParsedCommand::MouseClick(read_mouse_button(button)?));
+    }
+
+    #[pyo3(signature = (button = "left"))]
+    fn mouse_down(&mut self, button: &str) -> PyResult<()> {
+        return self.run_command(ParsedCommand::MouseDown(read_mouse_button(button)?));
+    }
+
+    #[pyo3(signature = (button = "left"))]
+    fn mouse_up(&mut self, button: &str) -> PyResult<()> {
+        return self.run_command(ParsedCommand::MouseRelease(read_mouse_button(button)?));
+    }
+
+    // Jumps straight there without a duration, or moves along a straight path over it
+    #[pyo3(signature = (x, y, duration_ms = None))]
+    fn mouse_move(&mut self, x: i32, y: i32, duration_ms: Option<u64>) -> PyResult<()> {
+        return match duration_ms {
+            Some(duration_ms) => {
+                let movement = MouseMovement{duration_ms,
+                                             path_type: MousePathType::Linear,
+                                             jitter: 0,
+                                             overshoot: 0,
+                                             step_interval_ms: DEFAULT_MOUSE_STEP_INTERVAL_MS};
+                self.run_command(ParsedCommand::MouseMoveSmooth{x, y, movement})
+            },
+            None => self.run_command(ParsedCommand::MouseMove{x, y})
+        };
+    }
+
+    fn mouse_move_relative(&mut self, x: i32, y: i32) -> PyResult<()> {
+        return self.run_command(ParsedCommand::MouseMoveRelative{x, y});
+    }
+
+    fn mouse_drag(&mut self, button: &str, start_x: i32, start_y: i32, end_x: i32, end_y: i32, duration_ms: u64) -> PyResult<()> {
+        return self.run_command(ParsedCommand::MouseDrag{mouse_button: read_mouse_button(button)?, start_x, start_y, end_x, end_y, duration_ms});
+    }
+
+    fn wait(&mut self, duration_ms: u64) -> PyResult<()> {
+        return self.run_command(ParsedCommand::Wait(duration_ms));
+    }
+
+    fn mouse_location(&self) -> (i32, i32) {
+        return self.executor.mouse_location();
+    }
+
+    // Simulated in a dry run
+    fn elapsed_ms(&self) -> f64 {
+        return self.executor.elapsed().as_secs_f64() * 1000.0;
+    }
+
+    // From 0 to 1, against the fake screen in a dry run
+    fn compare_screen_to_image_file(&mut self, input_file_path: &str, start_x: i32, start_y: i32) -> PyResult<f64> {
+        check_image_file_exists(input_file_path)?;
+        return Ok(self.executor.compare_screen_to_image_file(&input_file_path.to_string(), start_x, start_y)?);
+    }
+
+    fn find_image(&mut self, input_file_path: &str) -> PyResult<Option<(i32, i32)>> {
+        check_image_file_exists(input_file_path)?;
+        return Ok(self.executor.find_image_on_screen(&input_file_path.to_string())?);
+    }
+}
+
+// From 0 to 1, against the real screen
+#[pyfunction]
+#[pyo3(name = "compare_screen_to_image_file")]
+fn py_compare_screen_to_image_file(input_file_path: &str, start_x: i32, start_y: i32) -> PyResult<f64> {
+    check_image_file_exists(input_file_path)?;
+    return Ok(screen_compare::compare_screen_to_image_file(&input_file_path.to_string(), start_x, start_y)?);
+}
+
+// The top left corner of the first exact copy of the image on the real screen, or None
+#[pyfunction]
+#[pyo3(name = "find_image_on_screen")]
+fn py_find_image_on_screen(input_file_path: &str) -> PyResult<Option<(i32, i32)>> {
+    check_image_file_exists(input_file_path)?;
+    return Ok(screen_compare::find_image_on_screen(&input_file_path.to_string())?);
+}
+
+#[pymodule]
+fn scriptable_input_simulator(py: Python, module: &PyModule) -> PyResult<()> {
+    module.add_class::<PyScript>()?;
+    module.add_class::<PyExecutor>()?;
+    module.add_function(wrap_pyfunction!(py_compare_screen_to_image_file, module)?)?;
+    module.add_function(wrap_pyfunction!(py_find_image_on_screen, module)?)?;
+
+    module.add("ScriptError", py.get_type::<ScriptError>())?;
+    module.add("ParseError", py.get_type::<ParseError>())?;
+    module.add("BlockError", py.get_type::<BlockError>())?;
+    module.add("RhaiError", py.get_type::<RhaiError>())?;
+    module.add("StoppedError", py.get_type::<StoppedError>())?;
+
+    return Ok(());
+}
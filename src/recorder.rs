@@ -0,0 +1,408 @@
+use std::collections::HashSet;
+use std::sync::mpsc;
+use std::time::SystemTime;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum RecordedEventKind {
+    KeyPress{key_name: String, character: Option<char>},
+    KeyRelease{key_name: String},
+    ButtonPress(String),
+    ButtonRelease(String),
+    MouseMove{x: i32, y: i32}
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct RecordedEvent {
+    // Milliseconds since the recording started
+    pub time_ms: u64,
+    pub kind: RecordedEventKind
+}
+
+pub struct RecordingOptions {
+    // Only keep the last position of every run of mouse movements
+    pub coalesce_mouse_moves: bool,
+    // Waits are rounded to the nearest multiple of this. 0 keeps them as measured
+    pub wait_rounding_ms: u64
+}
+
+// Uses the same names as the key command, so the names can be written straight into a script
+static RDEV_KEY_NAMES: &[(rdev::Key, &str)] = &[
+    (rdev::Key::Alt, "alt"),
+    (rdev::Key::AltGr, "alt"),
+    (rdev::Key::Backspace, "back_space"),
+    (rdev::Key::CapsLock, "caps_lock"),
+    (rdev::Key::ControlLeft, "control"),
+    (rdev::Key::ControlRight, "control"),
+    (rdev::Key::Delete, "delete"),
+    (rdev::Key::DownArrow, "down_arrow"),
+    (rdev::Key::End, "end"),
+    (rdev::Key::Escape, "escape"),
+    (rdev::Key::F1, "f1"),
+    (rdev::Key::F2, "f2"),
+    (rdev::Key::F3, "f3"),
+    (rdev::Key::F4, "f4"),
+    (rdev::Key::F5, "f5"),
+    (rdev::Key::F6, "f6"),
+    (rdev::Key::F7, "f7"),
+    (rdev::Key::F8, "f8"),
+    (rdev::Key::F9, "f9"),
+    (rdev::Key::F10, "f10"),
+    (rdev::Key::F11, "f11"),
+    (rdev::Key::F12, "f12"),
+    (rdev::Key::Home, "home"),
+    (rdev::Key::LeftArrow, "left_arrow"),
+    (rdev::Key::MetaLeft, "meta"),
+    (rdev::Key::MetaRight, "meta"),
+    (rdev::Key::PageDown, "page_down"),
+    (rdev::Key::PageUp, "page_up"),
+    (rdev::Key::Return, "return"),
+    (rdev::Key::RightArrow, "right_arrow"),
+    (rdev::Key::ShiftLeft, "shift"),
+    (rdev::Key::ShiftRight, "shift"),
+    (rdev::Key::Space, "space"),
+    (rdev::Key::Tab, "tab"),
+    (rdev::Key::UpArrow, "up_arrow"),
+    (rdev::Key::KeyA, "a"),
+    (rdev::Key::KeyB, "b"),
+    (rdev::Key::KeyC, "c"),
+    (rdev::Key::KeyD, "d"),
+    (rdev::Key::KeyE, "e"),
+    (rdev::Key::KeyF, "f"),
+    (rdev::Key::KeyG, "g"),
+    (rdev::Key::KeyH, "h"),
+    (rdev::Key::KeyI, "i"),
+    (rdev::Key::KeyJ, "j"),
+    (rdev::Key::KeyK, "k"),
+    (rdev::Key::KeyL, "l"),
+    (rdev::Key::KeyM, "m"),
+    (rdev::Key::KeyN, "n"),
+    (rdev::Key::KeyO, "o"),
+    (rdev::Key::KeyP, "p"),
+    (rdev::Key::KeyQ, "q"),
+    (rdev::Key::KeyR, "r"),
+    (rdev::Key::KeyS, "s"),
+    (rdev::Key::KeyT, "t"),
+    (rdev::Key::KeyU, "u"),
+    (rdev::Key::KeyV, "v"),
+    (rdev::Key::KeyW, "w"),
+    (rdev::Key::KeyX, "x"),
+    (rdev::Key::KeyY, "y"),
+    (rdev::Key::KeyZ, "z"),
+    (rdev::Key::Num0, "0"),
+    (rdev::Key::Num1, "1"),
+    (rdev::Key::Num2, "2"),
+    (rdev::Key::Num3, "3"),
+    (rdev::Key::Num4, "4"),
+    (rdev::Key::Num5, "5"),
+    (rdev::Key::Num6, "6"),
+    (rdev::Key::Num7, "7"),
+    (rdev::Key::Num8, "8"),
+    (rdev::Key::Num9, "9")
+];
+
+fn rdev_key_name(key: rdev::Key) -> Option<String> {
+    return RDEV_KEY_NAMES.iter()
+        .find(|(named_key, _)| *named_key == key)
+        .map(|(_, key_name)| String::from(*key_name));
+}
+
+pub fn parse_record_stop_hotkey(hotkey_string: &str) -> Option<Vec<String>> {
+    let mut hotkey: Vec<String> = vec![];
+
+    for key_name in hotkey_string.split("+") {
+        let key_name = key_name.trim();
+        if !RDEV_KEY_NAMES.iter().any(|(_, recordable_key_name)| *recordable_key_name == key_name) {
+            return None;
+        }
+
+        hotkey.push(String::from(key_name));
+    }
+
+    return Some(hotkey);
+}
+
+fn rdev_button_name(button: rdev::Button) -> Option<String> {
+    let button_name = match button {
+        rdev::Button::Left => "left",
+        rdev::Button::Right => "right",
+        rdev::Button::Middle => "middle",
+        _ => { return None; }
+    };
+
+    return Some(String::from(button_name));
+}
+
+fn to_recorded_event_kind(event: &rdev::Event) -> Option<RecordedEventKind> {
+    return match &event.event_type {
+        rdev::EventType::KeyPress(key) => {
+            // The name is the text the key typed, which is only useful when it is a single printable character
+            let mut character = None;
+            if let Some(name) = &event.name {
+                let mut name_characters = name.chars();
+                if let (Some(typed_character), None) = (name_characters.next(), name_characters.next()) {
+                    if !typed_character.is_control() {
+                        character = Some(typed_character);
+                    }
+                }
+            }
+
+            rdev_key_name(*key).map(|key_name| RecordedEventKind::KeyPress{key_name, character})
+        },
+        rdev::EventType::KeyRelease(key) => rdev_key_name(*key).map(|key_name| RecordedEventKind::KeyRelease{key_name}),
+        rdev::EventType::ButtonPress(button) => rdev_button_name(*button).map(RecordedEventKind::ButtonPress),
+        rdev::EventType::ButtonRelease(button) => rdev_button_name(*button).map(RecordedEventKind::ButtonRelease),
+        rdev::EventType::MouseMove{x, y} => Some(RecordedEventKind::MouseMove{x: x.round() as i32, y: y.round() as i32}),
+        _ => None
+    };
+}
+
+// Records keyboard and mouse events until every key in the stop hotkey is held down at once.
+// The stop hotkey's own key presses are left out of the recording. Fails if events cannot be
+// listened for, or listening ends before the stop hotkey is pressed
+pub fn record_events(stop_hotkey: &[String]) -> Result<Vec<RecordedEvent>, String> {
+    let (event_sender, event_receiver) = mpsc::channel::<Result<rdev::Event, String>>();
+
+    // listen never returns while it works, so it gets a thread of its own that dies with the program
+    let error_sender = event_sender.clone();
+    std::thread::spawn(move || {
+        let listen_result = rdev::listen(move |event| {
+            let _ = event_sender.send(Ok(event));
+        });
+        if let Err(reason) = listen_result {
+            let _ = error_sender.send(Err(format!("Could not listen for keyboard and mouse events: {:?}", reason)));
+        }
+    });
+
+    let recording_start_time = SystemTime::now();
+    let mut recorded_events: Vec<RecordedEvent> = vec![];
+    let mut pressed_key_names: HashSet<String> = HashSet::new();
+    let mut is_stop_hotkey_pressed = false;
+
+    for event in event_receiver {
+        let event = event?;
+        let kind = match to_recorded_event_kind(&event) {
+            Some(kind) => kind,
+            None => { continue; }
+        };
+
+        match &kind {
+            RecordedEventKind::KeyPress{key_name, ..} => { pressed_key_names.insert(key_name.clone()); },
+            RecordedEventKind::KeyRelease{key_name} => { pressed_key_names.remove(key_name); },
+            _ => {}
+        }
+
+        let time_ms = event.time.duration_since(recording_start_time).unwrap_or_default().as_millis() as u64;
+        recorded_events.push(RecordedEvent{time_ms, kind});
+
+        if !stop_hotkey.is_empty() && stop_hotkey.iter().all(|key_name| pressed_key_names.contains(key_name)) {
+            is_stop_hotkey_pressed = true;
+            break;
+        }
+    }
+
+    if !is_stop_hotkey_pressed {
+        return Err(String::from("Listening for keyboard and mouse events ended before the stop hotkey was pressed"));
+    }
+
+    for key_name in stop_hotkey {
+        let last_press = recorded_events.iter().rposition(|recorded_event| {
+            matches!(&recorded_event.kind, RecordedEventKind::KeyPress{key_name: pressed_key_name, ..} if pressed_key_name == key_name)
+        });
+
+        if let Some(last_press_index) = last_press {
+            recorded_events.remove(last_press_index);
+        }
+    }
+
+    return Ok(recorded_events);
+}
+
+fn round_wait(wait_ms: u64, wait_rounding_ms: u64) -> u64 {
+    if wait_rounding_ms == 0 {
+        return wait_ms;
+    }
+
+    return (wait_ms + wait_rounding_ms / 2) / wait_rounding_ms * wait_rounding_ms;
+}
+
+fn is_release_of(recorded_event: Option<&RecordedEvent>, pressed_key_name: &str) -> bool {
+    return matches!(recorded_event.map(|event| &event.kind), Some(RecordedEventKind::KeyRelease{key_name}) if key_name == pressed_key_name);
+}
+
+// Turns the recorded events into script lines. Returns the line and how many events it covers
+fn next_script_line(events: &[RecordedEvent], options: &RecordingOptions) -> (String, usize) {
+    match &events[0].kind {
+        RecordedEventKind::MouseMove{x, y} => {
+            let mut last_position = (*x, *y);
+            let mut consumed = 1;
+
+            if options.coalesce_mouse_moves {
+                while let Some(RecordedEventKind::MouseMove{x, y}) = events.get(consumed).map(|event| &event.kind) {
+                    last_position = (*x, *y);
+                    consumed += 1;
+                }
+            }
+
+            return (format!("mouse_move: {} {}", last_position.0, last_position.1), consumed);
+        },
+        RecordedEventKind::ButtonPress(button_name) => {
+            if let Some(RecordedEventKind::ButtonRelease(released_button_name)) = events.get(1).map(|event| &event.kind) {
+                if released_button_name == button_name {
+                    return (format!("mouse_click: {}", button_name), 2);
+                }
+            }
+
+            return (format!("mouse_down: {}", button_name), 1);
+        },
+        RecordedEventKind::ButtonRelease(button_name) => {
+            return (format!("mouse_release: {}", button_name), 1);
+        },
+        RecordedEventKind::KeyPress{key_name, ..} => {
+            if !is_release_of(events.get(1), key_name) {
+                return (format!("key: {} press", key_name), 1);
+            }
+
+            // Runs of printable characters that were pressed and released one at a time are typed as a key_sequence
+            let mut typed_text = String::new();
+            let mut consumed = 0;
+            while let Some(RecordedEventKind::KeyPress{key_name: typed_key_name, character: Some(typed_character)}) = events.get(consumed).map(|event| &event.kind) {
+                if !is_release_of(events.get(consumed + 1), typed_key_name) {
+                    break;
+                }
+
+                typed_text.push(*typed_character);
+                consumed += 2;
+            }
+
+            if typed_text.chars().count() > 1 {
+                return (format!("key_sequence: {}", typed_text), consumed);
+            }
+
+            return (format!("key: {} click", key_name), 2);
+        },
+        RecordedEventKind::KeyRelease{key_name} => {
+            return (format!("key: {} release", key_name), 1);
+        }
+    }
+}
+
+pub fn generate_script(events: &[RecordedEvent], options: &RecordingOptions) -> Vec<String> {
+    let mut script_lines: Vec<String> = vec![];
+    let mut event_index = 0;
+    let mut last_event_time_ms: Option<u64> = None;
+
+    while event_index < events.len() {
+        let (script_line, consumed) = next_script_line(&events[event_index..], options);
+
+        if let Some(last_event_time_ms) = last_event_time_ms {
+            let wait_ms = round_wait(events[event_index].time_ms.saturating_sub(last_event_time_ms), options.wait_rounding_ms);
+            if wait_ms > 0 {
+                script_lines.push(format!("wait: {}", wait_ms));
+            }
+        }
+
+        script_lines.push(script_line);
+        event_index += consumed;
+        last_event_time_ms = Some(events[event_index - 1].time_ms);
+    }
+
+    return script_lines;
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn key_press(time_ms: u64, key_name: &str, character: Option<char>) -> RecordedEvent {
+        return RecordedEvent{time_ms, kind: RecordedEventKind::KeyPress{key_name: String::from(key_name), character}};
+    }
+
+    fn key_release(time_ms: u64, key_name: &str) -> RecordedEvent {
+        return RecordedEvent{time_ms, kind: RecordedEventKind::KeyRelease{key_name: String::from(key_name)}};
+    }
+
+    fn mouse_move(time_ms: u64, x: i32, y: i32) -> RecordedEvent {
+        return RecordedEvent{time_ms, kind: RecordedEventKind::MouseMove{x, y}};
+    }
+
+    #[test]
+    fn test_generate_script() {
+        let events = vec![
+            mouse_move(0, 10, 10),
+            mouse_move(5, 20, 20),
+            mouse_move(12, 30, 30),
+            RecordedEvent{time_ms: 260, kind: RecordedEventKind::ButtonPress(String::from("left"))},
+            RecordedEvent{time_ms: 330, kind: RecordedEventKind::ButtonRelease(String::from("left"))},
+            key_press(1000, "h", Some('h')),
+            key_release(1040, "h"),
+            key_press(1100, "i", Some('i')),
+            key_release(1150, "i"),
+            key_press(1500, "shift", None),
+            key_press(1600, "a", Some('A')),
+            key_release(1650, "a"),
+            key_release(1700, "shift"),
+            key_press(2000, "tab", None),
+            key_release(2030, "tab")
+        ];
+        let options = RecordingOptions{coalesce_mouse_moves: true, wait_rounding_ms: 50};
+
+        assert_eq!(generate_script(&events, &options), vec![
+            "mouse_move: 30 30",
+            "wait: 250",
+            "mouse_click: left",
+            "wait: 650",
+            "key_sequence: hi",
+            "wait: 350",
+            "key: shift press",
+            "wait: 100",
+            "key: a click",
+            "wait: 50",
+            "key: shift release",
+            "wait: 300",
+            "key: tab click"
+        ]);
+    }
+
+    #[test]
+    fn test_parse_record_stop_hotkey() {
+        assert_eq!(parse_record_stop_hotkey("control+f12"), Some(vec![String::from("control"), String::from("f12")]));
+        assert_eq!(parse_record_stop_hotkey("control+f20"), None);
+    }
+
+    #[test]
+    fn test_generate_script_keeps_every_mouse_move() {
+        let events = vec![mouse_move(0, 10, 10), mouse_move(8, 20, 20)];
+        let options = RecordingOptions{coalesce_mouse_moves: false, wait_rounding_ms: 0};
+
+        assert_eq!(generate_script(&events, &options), vec!["mouse_move: 10 10", "wait: 8", "mouse_move: 20 20"]);
+    }
+
+    // Needs a display to listen on and synthesize events into, for example Xvfb:
+    //     xvfb-run cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn test_record_synthesized_events() {
+        std::thread::spawn(|| {
+            let simulated_events = [
+                rdev::EventType::MouseMove{x: 40.0, y: 60.0},
+                rdev::EventType::KeyPress(rdev::Key::KeyO),
+                rdev::EventType::KeyRelease(rdev::Key::KeyO),
+                rdev::EventType::KeyPress(rdev::Key::KeyK),
+                rdev::EventType::KeyRelease(rdev::Key::KeyK),
+                rdev::EventType::KeyPress(rdev::Key::ControlLeft),
+                rdev::EventType::KeyPress(rdev::Key::F12)
+            ];
+
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            for simulated_event in &simulated_events {
+                rdev::simulate(simulated_event).expect("Could not synthesize an event");
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+        });
+
+        let events = record_events(&[String::from("control"), String::from("f12")]).unwrap();
+        let script_lines = generate_script(&events, &RecordingOptions{coalesce_mouse_moves: true, wait_rounding_ms: 1000});
+
+        assert_eq!(script_lines, vec!["mouse_move: 40 60", "key_sequence: ok"]);
+    }
+}
\ No newline at end of file
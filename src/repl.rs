@@ -0,0 +1,240 @@
+// An interactive prompt that runs each command as soon as it is entered, for trying commands
+// out while writing a script. Besides script commands, it takes a few commands of its own for
+// looking at the screen
+use std::path::Path;
+use log::error;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use crate::command_executor::*;
+use crate::command_types::*;
+use crate::held_inputs::HeldInputs;
+use crate::screen_compare::CaptureError;
+use crate::script::is_comment;
+
+pub const REPL_HELP: &str = "Enter any script command, like key: a click, to run it straight away. Other commands:
+    position                              Print the mouse cursor position
+    pixel <x> <y>                         Print the colour of the pixel at <x> <y>
+    capture <x> <y> <width> <height> <file>
+                                          Save that area of the screen to <file>, for use with screen comparisons
+    help                                  Print this help
+    quit                                  Leave, releasing any held keys and mouse buttons";
+
+#[derive(PartialEq, Debug)]
+pub enum ReplCommand {
+    Script(ParsedCommand),
+    Position,
+    Pixel{x: i32, y: i32},
+    Capture{x: i32, y: i32, width: u32, height: u32, file_path: String},
+    Help,
+    Quit
+}
+
+// None for an empty line or a comment
+pub fn parse_repl_command(input: &str) -> Result<Option<ReplCommand>, String> {
+    let input = input.trim();
+    if input.is_empty() || is_comment(input) {
+        return Ok(None);
+    }
+
+    let split_input: Vec<&str> = input.split_whitespace().collect();
+    let repl_command = match split_input.as_slice() {
+        ["position"] => Some(ReplCommand::Position),
+        ["pixel", x, y] => match (x.parse(), y.parse()) {
+            (Ok(x), Ok(y)) => Some(ReplCommand::Pixel{x, y}),
+            _ => None
+        },
+        ["capture", x, y, width, height, file_path] => match (x.parse(), y.parse(), width.parse(), height.parse()) {
+            (Ok(x), Ok(y), Ok(width), Ok(height)) if width > 0 && height > 0 => Some(ReplCommand::Capture{x, y, width, height, file_path: file_path.to_string()}),
+            _ => None
+        },
+        ["help"] => Some(ReplCommand::Help),
+        ["quit"] => Some(ReplCommand::Quit),
+        _ => try_parse_command_from_line(&input.to_string()).map(ReplCommand::Script)
+    };
+
+    return match repl_command {
+        Some(ReplCommand::Script(ParsedCommand::Every{..})) | Some(ReplCommand::Script(ParsedCommand::EndEvery)) => Err(String::from("every: blocks need a whole script")),
+        Some(repl_command) => Ok(Some(repl_command)),
+        None => Err(format!("{} is not a command. Type help for the list of commands", input))
+    };
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ReplStep {
+    // With anything to print
+    Continue(Option<String>),
+    Finish(Option<String>)
+}
+
+pub struct Repl {
+    executor: CommandExecutor,
+    // Each command entered counts as a line, for the log, trace and statistics
+    line_number: usize
+}
+
+impl Repl {
+    pub fn new(executor: CommandExecutor) -> Repl {
+        return Repl {
+            executor,
+            line_number: 0
+        };
+    }
+
+    // Keys and mouse buttons held by commands entered so far, for releasing them on a stop signal
+    pub fn held_inputs(&self) -> HeldInputs {
+        return self.executor.held_inputs();
+    }
+
+    fn no_screen_message() -> String {
+        return String::from("There is no screen to look at in a dry run without --dry_run_screen");
+    }
+
+    fn capture_error_message(capture_error: CaptureError, area: String) -> String {
+        return match capture_error {
+            CaptureError::NoScreen => Repl::no_screen_message(),
+            CaptureError::OutsideScreen => format!("{} is outside the screen", area),
+            CaptureError::Failed(reason) => format!("Could not capture {}: {}", area, reason)
+        };
+    }
+
+    pub fn handle_input(&mut self, input: &str) -> ReplStep {
+        let repl_command = match parse_repl_command(input) {
+            Ok(Some(repl_command)) => repl_command,
+            Ok(None) => { return ReplStep::Continue(None); },
+            Err(message) => { return ReplStep::Continue(Some(message)); }
+        };
+
+        return match repl_command {
+            ReplCommand::Script(command) => {
+                self.line_number += 1;
+                let script_line = ScriptLine{line_number: self.line_number, text: input.trim().to_string(), command};
+
+                match self.executor.execute_command(&script_line) {
+                    ExecutionResult::Finished => ReplStep::Continue(None),
+                    ExecutionResult::Stopped => {
+                        let stop_reason = self.executor.stop_reason().map_or(String::from("unknown"), |stop_reason| stop_reason.to_string());
+                        ReplStep::Finish(Some(format!("Stopped because {}", stop_reason)))
+                    }
+                }
+            },
+            ReplCommand::Position => {
+                let (x, y) = self.executor.mouse_location();
+                ReplStep::Continue(Some(format!("{} {}", x, y)))
+            },
+            ReplCommand::Pixel{x, y} => match self.executor.capture_screen_area(x, y, 1, 1) {
+                Ok(screen_area) => {
+                    let [red, green, blue, _] = screen_area.get_pixel(0, 0).0;
+                    ReplStep::Continue(Some(format!("{} {} {} (#{:02x}{:02x}{:02x})", red, green, blue, red, green, blue)))
+                },
+                Err(capture_error) => ReplStep::Continue(Some(Repl::capture_error_message(capture_error, format!("{} {}", x, y))))
+            },
+            ReplCommand::Capture{x, y, width, height, file_path} => match self.executor.capture_screen_area(x, y, width, height) {
+                Ok(screen_area) => match screen_area.save(&file_path) {
+                    Ok(_) => ReplStep::Continue(Some(format!("Saved {}x{} at {} {} to {}", width, height, x, y, file_path))),
+                    Err(error_reason) => ReplStep::Continue(Some(format!("Failed to save {}: {}", file_path, error_reason)))
+                },
+                Err(capture_error) => ReplStep::Continue(Some(Repl::capture_error_message(capture_error, format!("{}x{} at {} {}", width, height, x, y))))
+            },
+            ReplCommand::Help => ReplStep::Continue(Some(String::from(REPL_HELP))),
+            ReplCommand::Quit => ReplStep::Finish(None)
+        };
+    }
+}
+
+// Reads commands with line editing until quit, Ctrl+D or a stop. History is kept in history_path between sessions
+pub fn run_repl(repl: &mut Repl, history_path: &Path) -> Result<(), String> {
+    let mut editor = DefaultEditor::new().map_err(|error_reason| format!("Could not start the prompt: {}", error_reason))?;
+    // There is no history the first time
+    let _ = editor.load_history(history_path);
+    println!("Type help for the list of commands");
+
+    loop {
+        let input = match editor.readline("> ") {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => { continue; },
+            Err(ReadlineError::Eof) => { break; },
+            Err(error_reason) => { return Err(format!("Could not read the prompt: {}", error_reason)); }
+        };
+        if !input.trim().is_empty() {
+            let _ = editor.add_history_entry(input.as_str());
+        }
+
+        match repl.handle_input(&input) {
+            ReplStep::Continue(message) => {
+                if let Some(message) = message {
+                    println!("{}", message);
+                }
+            },
+            ReplStep::Finish(message) => {
+                if let Some(message) = message {
+                    println!("{}", message);
+                }
+                break;
+            }
+        }
+    }
+
+    if let Err(error_reason) = editor.save_history(history_path) {
+        error!(target: "commands_debug", "Failed to save the prompt history to {}: {}", history_path.display(), error_reason);
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use crate::input_backend::DryRunBackend;
+
+    fn dry_run_repl(fake_screen: Option<RgbaImage>) -> Repl {
+        return Repl::new(CommandExecutor::new(Box::new(DryRunBackend::new(fake_screen, 100.0))));
+    }
+
+    #[test]
+    fn test_parse_repl_command() {
+        assert_eq!(parse_repl_command("  # comment"), Ok(None));
+        assert_eq!(parse_repl_command("pixel 10 20\n"), Ok(Some(ReplCommand::Pixel{x: 10, y: 20})));
+        assert_eq!(parse_repl_command("capture 1 2 30 40 bobber.png"),
+                   Ok(Some(ReplCommand::Capture{x: 1, y: 2, width: 30, height: 40, file_path: String::from("bobber.png")})));
+        assert_eq!(parse_repl_command("mouse_move: 1 2"), Ok(Some(ReplCommand::Script(ParsedCommand::MouseMove{x: 1, y: 2}))));
+        assert!(parse_repl_command("capture 1 2 0 40 bobber.png").is_err());
+        assert!(parse_repl_command("every: 100").is_err());
+        assert!(parse_repl_command("wait: soon").is_err());
+    }
+
+    #[test]
+    fn test_handle_input() {
+        let mut fake_screen = RgbaImage::new(20, 20);
+        fake_screen.put_pixel(5, 6, Rgba([255, 128, 0, 255]));
+        let mut repl = dry_run_repl(Some(fake_screen));
+
+        assert_eq!(repl.handle_input("mouse_move: 5 6"), ReplStep::Continue(None));
+        assert_eq!(repl.handle_input("position"), ReplStep::Continue(Some(String::from("5 6"))));
+        assert_eq!(repl.handle_input("pixel 5 6"), ReplStep::Continue(Some(String::from("255 128 0 (#ff8000)"))));
+        assert_eq!(repl.handle_input("pixel 99999 5"), ReplStep::Continue(Some(String::from("99999 5 is outside the screen"))));
+        assert_eq!(repl.handle_input("pixel -5 -5"), ReplStep::Continue(Some(String::from("-5 -5 is outside the screen"))));
+        assert_eq!(repl.handle_input("capture 10 10 20 20 area.png"), ReplStep::Continue(Some(String::from("20x20 at 10 10 is outside the screen"))));
+        assert_eq!(repl.handle_input("exit: 5"), ReplStep::Finish(Some(String::from("Stopped because an exit command ran with code 5"))));
+
+        let mut repl = dry_run_repl(None);
+        assert_eq!(repl.handle_input("pixel 5 6"), ReplStep::Continue(Some(Repl::no_screen_message())));
+        assert_eq!(repl.handle_input("quit"), ReplStep::Finish(None));
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut fake_screen = RgbaImage::new(20, 20);
+        fake_screen.put_pixel(3, 4, Rgba([1, 2, 3, 255]));
+        let mut repl = dry_run_repl(Some(fake_screen));
+        let file_path = std::env::temp_dir().join(format!("scriptable_input_simulator_capture_{}.png", std::process::id()));
+
+        repl.handle_input(&format!("capture 2 2 4 4 {}", file_path.display()));
+        let captured_image = image::open(&file_path).unwrap().to_rgba8();
+        std::fs::remove_file(&file_path).unwrap();
+
+        assert_eq!(captured_image.dimensions(), (4, 4));
+        assert_eq!(*captured_image.get_pixel(1, 2), Rgba([1, 2, 3, 255]));
+    }
+}
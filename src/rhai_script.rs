@@ -0,0 +1,354 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, NativeCallContext, AST};
+
+use crate::command_executor::*;
+use crate::command_types::*;
+use crate::error::Error;
+use crate::input_backend::DryRunBackend;
+use crate::mouse_path::*;
+use crate::stop_conditions::is_valid_exit_code;
+use crate::timing::*;
+
+pub const RHAI_EXTENSION: &str = "rhai";
+// How many script operations run between checks of the emergency stop and the stop conditions,
+// so a loop that sends no commands can still be stopped
+const STOP_CHECK_OPERATIONS: u64 = 1000;
+
+type SharedExecutor = Rc<RefCell<CommandExecutor>>;
+type FunctionResult<T> = Result<T, Box<EvalAltResult>>;
+
+pub fn is_rhai_file<P: AsRef<Path>>(path: P) -> bool {
+    return path.as_ref().extension().and_then(|extension| extension.to_str()).map(str::to_lowercase).as_deref() == Some(RHAI_EXTENSION);
+}
+
+fn runtime_error(context: &NativeCallContext, message: String) -> Box<EvalAltResult> {
+    return Box::new(EvalAltResult::ErrorRuntime(message.into(), context.position()));
+}
+
+fn read_key(context: &NativeCallContext, name: &str) -> FunctionResult<enigo::Key> {
+    return lookup_key(name).ok_or_else(|| runtime_error(context, format!("{} is not a key", name)));
+}
+
+fn read_mouse_button(context: &NativeCallContext, name: &str) -> FunctionResult<enigo::MouseButton> {
+    return lookup_mouse_button(name).ok_or_else(|| runtime_error(context, format!("{} is not a mouse button", name)));
+}
+
+fn read_ms(context: &NativeCallContext, value: i64) -> FunctionResult<u64> {
+    return u64::try_from(value).map_err(|_| runtime_error(context, format!("{} is not a number of milliseconds", value)));
+}
+
+fn read_coordinate(context: &NativeCallContext, value: i64) -> FunctionResult<i32> {
+    return i32::try_from(value).map_err(|_| runtime_error(context, format!("{} is not a screen coordinate", value)));
+}
+
+// Commands are numbered by the line of the script that called them, for the statistics, the trace and the debugger
+fn run_command(executor: &SharedExecutor, context: &NativeCallContext, command: ParsedCommand) -> FunctionResult<()> {
+    let script_line = ScriptLine {
+        line_number: context.position().line().unwrap_or(0),
+        text: command.to_string(),
+        command
+    };
+
+    if executor.borrow_mut().execute_command(&script_line) == ExecutionResult::Stopped {
+        return Err(runtime_error(context, String::from("the run was stopped")));
+    }
+
+    return Ok(());
+}
+
+fn register_key_functions(engine: &mut Engine, executor: &SharedExecutor) {
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("key_click", move |context: NativeCallContext, key: &str| -> FunctionResult<()> {
+        let key = read_key(&context, key)?;
+        return run_command(&shared_executor, &context, key_use_command(key, ButtonAction::Click));
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("key_down", move |context: NativeCallContext, key: &str| -> FunctionResult<()> {
+        let key = read_key(&context, key)?;
+        return run_command(&shared_executor, &context, key_use_command(key, ButtonAction::Press));
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("key_up", move |context: NativeCallContext, key: &str| -> FunctionResult<()> {
+        let key = read_key(&context, key)?;
+        return run_command(&shared_executor, &context, key_use_command(key, ButtonAction::Release));
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("key_hold", move |context: NativeCallContext, key: &str, duration_ms: i64| -> FunctionResult<()> {
+        let command = ParsedCommand::KeyHold{key: read_key(&context, key)?, duration_ms: read_ms(&context, duration_ms)?};
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("type_text", move |context: NativeCallContext, text: &str| -> FunctionResult<()> {
+        return run_command(&shared_executor, &context, ParsedCommand::KeySequence(text.to_string()));
+    });
+}
+
+fn register_mouse_functions(engine: &mut Engine, executor: &SharedExecutor) {
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("mouse_click", move |context: NativeCallContext, mouse_button: &str| -> FunctionResult<()> {
+        let command = ParsedCommand::MouseClick(read_mouse_button(&context, mouse_button)?);
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("mouse_down", move |context: NativeCallContext, mouse_button: &str| -> FunctionResult<()> {
+        let command = ParsedCommand::MouseDown(read_mouse_button(&context, mouse_button)?);
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("mouse_up", move |context: NativeCallContext, mouse_button: &str| -> FunctionResult<()> {
+        let command = ParsedCommand::MouseRelease(read_mouse_button(&context, mouse_button)?);
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("mouse_hold", move |context: NativeCallContext, mouse_button: &str, duration_ms: i64| -> FunctionResult<()> {
+        let command = ParsedCommand::MouseHold{mouse_button: read_mouse_button(&context, mouse_button)?, duration_ms: read_ms(&context, duration_ms)?};
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("mouse_move", move |context: NativeCallContext, x: i64, y: i64| -> FunctionResult<()> {
+        let command = ParsedCommand::MouseMove{x: read_coordinate(&context, x)?, y: read_coordinate(&context, y)?};
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("mouse_move", move |context: NativeCallContext, x: i64, y: i64, duration_ms: i64| -> FunctionResult<()> {
+        let movement = MouseMovement {
+            duration_ms: read_ms(&context, duration_ms)?,
+            path_type: MousePathType::Linear,
+            jitter: 0,
+            overshoot: 0,
+            step_interval_ms: DEFAULT_MOUSE_STEP_INTERVAL_MS
+        };
+        let command = ParsedCommand::MouseMoveSmooth{x: read_coordinate(&context, x)?, y: read_coordinate(&context, y)?, movement};
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("mouse_move_relative", move |context: NativeCallContext, x: i64, y: i64| -> FunctionResult<()> {
+        let command = ParsedCommand::MouseMoveRelative{x: read_coordinate(&context, x)?, y: read_coordinate(&context, y)?};
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("mouse_drag", move |context: NativeCallContext, mouse_button: &str, start_x: i64, start_y: i64, end_x: i64, end_y: i64, duration_ms: i64| -> FunctionResult<()> {
+        let command = ParsedCommand::MouseDrag {
+            mouse_button: read_mouse_button(&context, mouse_button)?,
+            start_x: read_coordinate(&context, start_x)?,
+            start_y: read_coordinate(&context, start_y)?,
+            end_x: read_coordinate(&context, end_x)?,
+            end_y: read_coordinate(&context, end_y)?,
+            duration_ms: read_ms(&context, duration_ms)?
+        };
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("mouse_location", move || -> Array {
+        let (x, y) = shared_executor.borrow().mouse_location();
+        return vec![Dynamic::from(x as i64), Dynamic::from(y as i64)];
+    });
+}
+
+fn register_timing_functions(engine: &mut Engine, executor: &SharedExecutor) {
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("wait", move |context: NativeCallContext, duration_ms: i64| -> FunctionResult<()> {
+        let command = ParsedCommand::Wait(read_ms(&context, duration_ms)?);
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("wait_random", move |context: NativeCallContext, min_ms: i64, max_ms: i64| -> FunctionResult<()> {
+        let command = ParsedCommand::WaitRandom{min_ms: read_ms(&context, min_ms)?, max_ms: read_ms(&context, max_ms)?, distribution: WaitDistribution::Uniform};
+        return run_command(&shared_executor, &context, command);
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("elapsed_ms", move || -> i64 {
+        return shared_executor.borrow().elapsed().as_millis() as i64;
+    });
+
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("stop", move |context: NativeCallContext, exit_code: i64| -> FunctionResult<()> {
+        let exit_code = i32::try_from(exit_code).ok().filter(|exit_code| is_valid_exit_code(*exit_code))
+            .ok_or_else(|| runtime_error(&context, format!("{} is not an exit code a script can use", exit_code)))?;
+        return run_command(&shared_executor, &context, ParsedCommand::Exit(exit_code));
+    });
+}
+
+fn register_screen_functions(engine: &mut Engine, executor: &SharedExecutor) {
+    // Scores are percentages, the same as the match thresholds of screen_compare_key_click
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("screen_match", move |context: NativeCallContext, x: i64, y: i64, input_file_path: &str| -> FunctionResult<f64> {
+        let (start_x, start_y) = (read_coordinate(&context, x)?, read_coordinate(&context, y)?);
+        let match_percentage = shared_executor.borrow_mut().compare_screen_to_image_file(&input_file_path.to_string(), start_x, start_y)
+            .map_err(|error_reason| runtime_error(&context, format!("Could not compare with {}: {}", input_file_path, error_reason)))?;
+        return Ok(match_percentage * 100.0);
+    });
+
+    // Gives [x, y] of the top left corner where the image was found, or () if it was not
+    let shared_executor = Rc::clone(executor);
+    engine.register_fn("find_image", move |context: NativeCallContext, input_file_path: &str| -> FunctionResult<Dynamic> {
+        let found_location = shared_executor.borrow_mut().find_image_on_screen(&input_file_path.to_string())
+            .map_err(|error_reason| runtime_error(&context, format!("Could not look for {}: {}", input_file_path, error_reason)))?;
+        return Ok(match found_location {
+            Some((x, y)) => Dynamic::from_array(vec![Dynamic::from(x as i64), Dynamic::from(y as i64)]),
+            None => Dynamic::UNIT
+        });
+    });
+}
+
+fn create_engine(executor: &SharedExecutor) -> Engine {
+    let mut engine = Engine::new();
+
+    register_key_functions(&mut engine, executor);
+    register_mouse_functions(&mut engine, executor);
+    register_timing_functions(&mut engine, executor);
+    register_screen_functions(&mut engine, executor);
+
+    let shared_executor = Rc::clone(executor);
+    engine.on_progress(move |operations| {
+        if operations % STOP_CHECK_OPERATIONS == 0 && shared_executor.borrow_mut().check_stop() {
+            return Some(Dynamic::UNIT);
+        }
+        return None;
+    });
+
+    return engine;
+}
+
+// A script for the embedded Rhai engine, for logic that a list of commands cannot express
+pub struct RhaiScript {
+    ast: AST
+}
+
+impl RhaiScript {
+    pub fn compile(text: &str) -> Result<RhaiScript, Error> {
+        return match Engine::new().compile(text) {
+            Ok(ast) => Ok(RhaiScript{ast}),
+            Err(error_reason) => Err(Error::Rhai{message: error_reason.to_string()})
+        };
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<RhaiScript, Error> {
+        let path = path.as_ref();
+
+        return match std::fs::read_to_string(path) {
+            Ok(text) => RhaiScript::compile(&text),
+            Err(source) => Err(Error::Io{path: path.display().to_string(), source})
+        };
+    }
+
+    // Runs the whole script as many times as run_options.repeat asks, the same as Executor::run
+    pub fn run(&self, executor: &mut CommandExecutor, run_options: RunOptions) -> Result<ExecutionResult, Error> {
+        // The engine's functions have to own what they use, so the executor is lent to them for
+        // the run and a stand-in that does nothing is left in its place
+        let stand_in_executor = CommandExecutor::new(Box::new(DryRunBackend::new(None, 0.0)));
+        let shared_executor = Rc::new(RefCell::new(std::mem::replace(executor, stand_in_executor)));
+
+        let run_result = self.run_shared(&shared_executor, run_options);
+
+        let shared_executor = Rc::try_unwrap(shared_executor).ok().expect("The script engine should have been dropped by the end of the run");
+        *executor = shared_executor.into_inner();
+
+        return run_result;
+    }
+
+    fn run_shared(&self, executor: &SharedExecutor, run_options: RunOptions) -> Result<ExecutionResult, Error> {
+        let engine = create_engine(executor);
+        let mut run_progress = executor.borrow_mut().start_run(run_options);
+
+        while !executor.borrow().is_run_finished(&run_progress) {
+            let iteration_start_time = executor.borrow_mut().start_iteration();
+            let eval_result = engine.run_ast(&self.ast);
+
+            // A stopped run ends the script with an error, which is expected rather than a failure
+            if executor.borrow_mut().finish_iteration(iteration_start_time) == ExecutionResult::Stopped {
+                return Ok(ExecutionResult::Stopped);
+            }
+            if let Err(error_reason) = eval_result {
+                return Err(Error::Rhai{message: error_reason.to_string()});
+            }
+
+            run_progress.completed_iterations += 1;
+        }
+
+        return Ok(ExecutionResult::Finished);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::time::Duration;
+    use super::*;
+    use crate::stop_conditions::StopReason;
+
+    fn dry_run_executor() -> CommandExecutor {
+        return CommandExecutor::new(Box::new(DryRunBackend::new(None, 100.0)));
+    }
+
+    #[test]
+    fn test_rhai_script_runs_commands() {
+        let rhai_script = RhaiScript::compile(r#"
+            for x in 0..3 {
+                mouse_move(x * 10, 20);
+                wait(100);
+            }
+            if screen_match(0, 0, "bobber.png") > 90.0 {
+                key_click("a");
+            }
+            if find_image("bobber.png") == () {
+                wait(5);
+            }
+        "#).unwrap();
+        let mut executor = dry_run_executor();
+
+        assert_eq!(rhai_script.run(&mut executor, RunOptions{repeat: Repeat::Times(2), ..Default::default()}).unwrap(), ExecutionResult::Finished);
+        assert_eq!(executor.elapsed(), Duration::from_millis(610));
+        assert_eq!(executor.mouse_location(), (20, 20));
+        assert_eq!(executor.statistics().iterations.completed, 2);
+        assert_eq!(executor.statistics().commands[&4].executions, 6);
+    }
+
+    #[test]
+    fn test_rhai_script_stop() {
+        let rhai_script = RhaiScript::compile("wait(10); stop(4); wait(10);").unwrap();
+        let mut executor = dry_run_executor();
+
+        assert_eq!(rhai_script.run(&mut executor, RunOptions::default()).unwrap(), ExecutionResult::Stopped);
+        assert_eq!(executor.stop_reason(), Some(StopReason::ExitCommand(4)));
+        assert_eq!(executor.elapsed(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_rhai_script_errors() {
+        assert!(matches!(RhaiScript::compile("wait(10"), Err(Error::Rhai{..})));
+
+        let rhai_script = RhaiScript::compile(r#"key_click("no_such_key");"#).unwrap();
+        assert!(matches!(rhai_script.run(&mut dry_run_executor(), RunOptions::default()), Err(Error::Rhai{..})));
+        let rhai_script = RhaiScript::compile("stop(2);").unwrap();
+        assert!(matches!(rhai_script.run(&mut dry_run_executor(), RunOptions::default()), Err(Error::Rhai{..})));
+
+        let mut executor = CommandExecutor::new(Box::new(DryRunBackend::new(Some(image::RgbaImage::new(10, 10)), 100.0)));
+        let rhai_script = RhaiScript::compile(r#"find_image("no_such_image.png");"#).unwrap();
+        assert!(matches!(rhai_script.run(&mut executor, RunOptions::default()), Err(Error::Rhai{..})));
+        let rhai_script = RhaiScript::compile(r#"screen_match(0, 0, "no_such_image.png");"#).unwrap();
+        assert!(matches!(rhai_script.run(&mut executor, RunOptions::default()), Err(Error::Rhai{..})));
+    }
+
+    #[test]
+    fn test_is_rhai_file() {
+        assert!(is_rhai_file("fishing.rhai"));
+        assert!(is_rhai_file("fishing.RHAI"));
+        assert!(!is_rhai_file("fishing.txt"));
+    }
+}
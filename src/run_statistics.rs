@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use serde::Serialize;
+
+use crate::command_types::*;
+
+// Screen comparison scores are counted in buckets of 10 percent
+const MATCH_HISTOGRAM_BUCKETS: usize = 10;
+
+#[derive(Serialize, Default, Clone)]
+pub struct CommandStatistics {
+    pub line_number: usize,
+    pub text: String,
+    pub executions: u64,
+    pub total_ms: f64
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct ComparisonStatistics {
+    pub line_number: usize,
+    pub text: String,
+    pub attempts: u64,
+    pub matches: u64,
+    pub hit_rate: f64,
+    // Index 0 counts scores from 0% up to 10%, index 9 counts scores from 90% up to and including 100%
+    pub match_histogram: [u64; MATCH_HISTOGRAM_BUCKETS]
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct IterationStatistics {
+    pub completed: u64,
+    pub total_ms: f64,
+    pub min_ms: Option<f64>,
+    pub max_ms: Option<f64>
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct RunStatistics {
+    pub total_ms: f64,
+    pub iterations: IterationStatistics,
+    // Keyed by line number, so the report lists commands in script order
+    pub commands: BTreeMap<usize, CommandStatistics>,
+    pub comparisons: BTreeMap<usize, ComparisonStatistics>
+}
+
+fn to_ms(duration: Duration) -> f64 {
+    return duration.as_secs_f64() * 1000.0;
+}
+
+impl RunStatistics {
+    pub fn new() -> RunStatistics {
+        return RunStatistics::default();
+    }
+
+    pub fn record_command(&mut self, script_line: &ScriptLine, duration: Duration) {
+        let command_statistics = self.commands.entry(script_line.line_number).or_insert_with(|| CommandStatistics {
+            line_number: script_line.line_number,
+            text: script_line.text.clone(),
+            ..Default::default()
+        });
+
+        command_statistics.executions += 1;
+        command_statistics.total_ms += to_ms(duration);
+    }
+
+    pub fn record_comparison(&mut self, script_line: &ScriptLine, match_percentage: f64, matched: bool) {
+        let comparison_statistics = self.comparisons.entry(script_line.line_number).or_insert_with(|| ComparisonStatistics {
+            line_number: script_line.line_number,
+            text: script_line.text.clone(),
+            ..Default::default()
+        });
+
+        let bucket = ((match_percentage * MATCH_HISTOGRAM_BUCKETS as f64) as usize).min(MATCH_HISTOGRAM_BUCKETS - 1);
+        comparison_statistics.match_histogram[bucket] += 1;
+        comparison_statistics.attempts += 1;
+        if matched {
+            comparison_statistics.matches += 1;
+        }
+        comparison_statistics.hit_rate = comparison_statistics.matches as f64 / comparison_statistics.attempts as f64;
+    }
+
+    pub fn record_iteration(&mut self, duration: Duration) {
+        let duration_ms = to_ms(duration);
+
+        self.iterations.completed += 1;
+        self.iterations.total_ms += duration_ms;
+        self.iterations.min_ms = Some(self.iterations.min_ms.map_or(duration_ms, |min_ms| min_ms.min(duration_ms)));
+        self.iterations.max_ms = Some(self.iterations.max_ms.map_or(duration_ms, |max_ms| max_ms.max(duration_ms)));
+    }
+
+    pub fn set_total_duration(&mut self, duration: Duration) {
+        self.total_ms = to_ms(duration);
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = String::from("Run statistics\n");
+
+        summary += &format!("  Total time: {:.3} s\n", self.total_ms / 1000.0);
+        summary += &format!("  Completed iterations: {}", self.iterations.completed);
+        if let (Some(min_ms), Some(max_ms)) = (self.iterations.min_ms, self.iterations.max_ms) {
+            summary += &format!(" (average {:.3} s, min {:.3} s, max {:.3} s)",
+                                self.iterations.total_ms / self.iterations.completed as f64 / 1000.0,
+                                min_ms / 1000.0,
+                                max_ms / 1000.0);
+        }
+        summary += "\n\n";
+
+        summary += &format!("  {:>6}  {:>10}  {:>12}  {}\n", "Line", "Executions", "Total (s)", "Command");
+        for command_statistics in self.commands.values() {
+            summary += &format!("  {:>6}  {:>10}  {:>12.3}  {}\n",
+                                command_statistics.line_number,
+                                command_statistics.executions,
+                                command_statistics.total_ms / 1000.0,
+                                command_statistics.text);
+        }
+
+        if !self.comparisons.is_empty() {
+            summary += &format!("\n  {:>6}  {:>8}  {:>7}  {:>8}  {}\n", "Line", "Attempts", "Matches", "Hit rate", "Scores by 10% bucket");
+            for comparison_statistics in self.comparisons.values() {
+                let histogram: Vec<String> = comparison_statistics.match_histogram.iter().map(|count| count.to_string()).collect();
+
+                summary += &format!("  {:>6}  {:>8}  {:>7}  {:>7.1}%  {}\n",
+                                    comparison_statistics.line_number,
+                                    comparison_statistics.attempts,
+                                    comparison_statistics.matches,
+                                    comparison_statistics.hit_rate * 100.0,
+                                    histogram.join(" "));
+            }
+        }
+
+        return summary;
+    }
+
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string_pretty(self).expect("Run statistics could not be written as JSON");
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_record_comparison() {
+        let script_line = ScriptLine{line_number: 4, text: String::from("screen_compare_key_click: g 0 0 50 fish.png"), command: ParsedCommand::Wait(1)};
+        let mut run_statistics = RunStatistics::new();
+
+        run_statistics.record_comparison(&script_line, 0.05, false);
+        run_statistics.record_comparison(&script_line, 0.55, true);
+        run_statistics.record_comparison(&script_line, 1.0, true);
+        run_statistics.record_comparison(&script_line, 0.42, false);
+
+        let comparison_statistics = &run_statistics.comparisons[&4];
+        assert_eq!(comparison_statistics.attempts, 4);
+        assert_eq!(comparison_statistics.matches, 2);
+        assert_eq!(comparison_statistics.hit_rate, 0.5);
+        assert_eq!(comparison_statistics.match_histogram, [1, 0, 0, 0, 1, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_record_iteration() {
+        let mut run_statistics = RunStatistics::new();
+
+        run_statistics.record_iteration(Duration::from_millis(300));
+        run_statistics.record_iteration(Duration::from_millis(100));
+
+        assert_eq!(run_statistics.iterations.completed, 2);
+        assert_eq!(run_statistics.iterations.total_ms, 400.0);
+        assert_eq!(run_statistics.iterations.min_ms, Some(100.0));
+        assert_eq!(run_statistics.iterations.max_ms, Some(300.0));
+    }
+}
\ No newline at end of file
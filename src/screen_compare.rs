@@ -1,18 +1,71 @@
+use std::fmt;
 use std::io::Cursor;
-use image::{self, GenericImageView, RgbaImage, GenericImage};
+use image::{self, DynamicImage, GenericImageView, RgbaImage, GenericImage};
 use screenshots::Screen;
 use log::info;
 
-pub fn compare_screen_to_image_file(input_file_path: &String, start_x: i32, start_y: i32) -> f64 {
-    let img1 = image::open(input_file_path).expect("File not found");
+// Why an area of the screen could not be captured, or an image could not be looked for on it
+#[derive(PartialEq, Debug, Clone)]
+pub enum CaptureError {
+    // A dry run without a fake screen
+    NoScreen,
+    // Some of the area is off the screen
+    OutsideScreen,
+    Failed(String),
+    // The image file is missing or is not an image
+    UnreadableImage(String)
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            CaptureError::NoScreen => write!(formatter, "there is no screen to capture"),
+            CaptureError::OutsideScreen => write!(formatter, "the area is outside the screen"),
+            CaptureError::Failed(reason) => write!(formatter, "the screen could not be captured: {}", reason),
+            CaptureError::UnreadableImage(reason) => write!(formatter, "the image could not be read: {}", reason)
+        };
+    }
+}
+
+pub fn open_image_file(input_file_path: &str) -> Result<DynamicImage, CaptureError> {
+    return image::open(input_file_path).map_err(|error_reason| CaptureError::UnreadableImage(format!("{}: {}", input_file_path, error_reason)));
+}
+
+pub fn compare_screen_to_image_file(input_file_path: &String, start_x: i32, start_y: i32) -> Result<f64, CaptureError> {
+    let img1 = open_image_file(input_file_path)?;
 
     let (width, height) = img1.dimensions();
 
-    let screens = Screen::all().unwrap();
-    let screen = screens[0];
+    // An image that does not fit on the screen cannot be on it
+    let rgba_screen_image = match capture_screen_area(start_x, start_y, width, height) {
+        Ok(rgba_screen_image) => rgba_screen_image,
+        Err(CaptureError::OutsideScreen) => {
+            info!(target: "commands_debug", "{} at {} {} does not fit on the screen", input_file_path, start_x, start_y);
+            return Ok(0.0);
+        },
+        Err(error_reason) => { return Err(error_reason); }
+    };
+
+    return Ok(compare_image_to_screen_area(&img1, &rgba_screen_image));
+}
+
+pub fn is_area_on_screen(screen_width: u32, screen_height: u32, start_x: i32, start_y: i32, width: u32, height: u32) -> bool {
+    return start_x >= 0
+        && start_y >= 0
+        && start_x as u64 + width as u64 <= screen_width as u64
+        && start_y as u64 + height as u64 <= screen_height as u64;
+}
+
+pub fn capture_screen_area(start_x: i32, start_y: i32, width: u32, height: u32) -> Result<RgbaImage, CaptureError> {
+    let screens = Screen::all().map_err(|error_reason| CaptureError::Failed(error_reason.to_string()))?;
+    let screen = *screens.first().ok_or(CaptureError::NoScreen)?;
     info!(target: "commands_debug", "capturer {screen:?}");
 
-    let screen_area = screen.capture_area(start_x, start_y, width, height).unwrap();
+    if !is_area_on_screen(screen.display_info.width, screen.display_info.height, start_x, start_y, width, height) {
+        return Err(CaptureError::OutsideScreen);
+    }
+
+    let screen_area = screen.capture_area(start_x, start_y, width, height).map_err(|error_reason| CaptureError::Failed(error_reason.to_string()))?;
 
     let pixels = screen_area.buffer();
 
@@ -21,11 +74,99 @@ pub fn compare_screen_to_image_file(input_file_path: &String, start_x: i32, star
 
     let screenshot = image::io::Reader::new(Cursor::new(pixels))
             .with_guessed_format()
-            .unwrap()
+            .map_err(|error_reason| CaptureError::Failed(error_reason.to_string()))?
+            .decode()
+            .map_err(|error_reason| CaptureError::Failed(error_reason.to_string()))?;
+
+    rgba_screen_image.copy_from(&screenshot, 0, 0).map_err(|error_reason| CaptureError::Failed(error_reason.to_string()))?;
+
+    return Ok(rgba_screen_image);
+}
+
+// The whole of the main screen
+fn capture_screen() -> Result<RgbaImage, CaptureError> {
+    let screens = Screen::all().map_err(|error_reason| CaptureError::Failed(error_reason.to_string()))?;
+    let screen = screens.first().ok_or(CaptureError::NoScreen)?;
+    let screenshot = screen.capture().map_err(|error_reason| CaptureError::Failed(error_reason.to_string()))?;
+
+    let rgba_screen_image = image::io::Reader::new(Cursor::new(screenshot.buffer()))
+            .with_guessed_format()
+            .map_err(|error_reason| CaptureError::Failed(error_reason.to_string()))?
             .decode()
-            .unwrap();
+            .map_err(|error_reason| CaptureError::Failed(error_reason.to_string()))?
+            .to_rgba8();
+
+    return Ok(rgba_screen_image);
+}
+
+pub fn find_image_on_screen(input_file_path: &String) -> Result<Option<(i32, i32)>, CaptureError> {
+    let img1 = open_image_file(input_file_path)?;
+    let rgba_screen_image = capture_screen()?;
+
+    return Ok(find_image_in_screen_image(&img1, &rgba_screen_image));
+}
+
+pub fn capture_screenshot(output_file_path: &str) -> std::io::Result<()> {
+    let screens = Screen::all().map_err(|error_reason| std::io::Error::new(std::io::ErrorKind::Other, error_reason.to_string()))?;
+    let screen = screens.first().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, CaptureError::NoScreen.to_string()))?;
+    let screenshot = screen.capture().map_err(|error_reason| std::io::Error::new(std::io::ErrorKind::Other, error_reason.to_string()))?;
+
+    // The captured buffer is already encoded as a PNG
+    return std::fs::write(output_file_path, screenshot.buffer());
+}
 
-    rgba_screen_image.copy_from(&screenshot, 0, 0).unwrap();
+// Compares against an image standing in for the screen, so that no real screen is needed
+pub fn compare_fake_screen_to_image_file(fake_screen: &RgbaImage, input_file_path: &String, start_x: i32, start_y: i32) -> Result<f64, CaptureError> {
+    let img1 = open_image_file(input_file_path)?;
+
+    let (width, height) = img1.dimensions();
+
+    let rgba_screen_image = capture_fake_screen_area(fake_screen, start_x, start_y, width, height);
+
+    return Ok(compare_image_to_screen_area(&img1, &rgba_screen_image));
+}
+
+// Parts of the area that are off the fake screen, on any side, are left transparent black
+pub fn capture_fake_screen_area(fake_screen: &RgbaImage, start_x: i32, start_y: i32, width: u32, height: u32) -> RgbaImage {
+    let mut rgba_screen_image: RgbaImage = RgbaImage::new(width, height);
+
+    image::imageops::replace(&mut rgba_screen_image, fake_screen, -(start_x as i64), -(start_y as i64));
+
+    return rgba_screen_image;
+}
+
+pub fn find_image_on_fake_screen(fake_screen: &RgbaImage, input_file_path: &String) -> Result<Option<(i32, i32)>, CaptureError> {
+    let img1 = open_image_file(input_file_path)?;
+
+    return Ok(find_image_in_screen_image(&img1, fake_screen));
+}
+
+// Looks for an exact copy of the image, going along each row from the top left
+fn find_image_in_screen_image(img1: &DynamicImage, rgba_screen_image: &RgbaImage) -> Option<(i32, i32)> {
+    let (width, height) = img1.dimensions();
+    let (screen_width, screen_height) = rgba_screen_image.dimensions();
+    if width == 0 || height == 0 || width > screen_width || height > screen_height {
+        return None;
+    }
+
+    let rgba_image = img1.to_rgba8();
+    for start_y in 0 ..= screen_height - height {
+        for start_x in 0 ..= screen_width - width {
+            let is_match = (0 .. height).all(|y| (0 .. width).all(|x| {
+                rgba_image.get_pixel(x, y) == rgba_screen_image.get_pixel(start_x + x, start_y + y)
+            }));
+
+            if is_match {
+                return Some((start_x as i32, start_y as i32));
+            }
+        }
+    }
+
+    return None;
+}
+
+fn compare_image_to_screen_area(img1: &DynamicImage, rgba_screen_image: &RgbaImage) -> f64 {
+    let (width, height) = img1.dimensions();
 
     let total_pixels = width * height;
     let mut matching_pixels = 0;
@@ -38,4 +179,41 @@ pub fn compare_screen_to_image_file(input_file_path: &String, start_x: i32, star
     }
 
     return matching_pixels as f64/total_pixels as f64;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_capture_fake_screen_area() {
+        let mut fake_screen = RgbaImage::new(10, 10);
+        fake_screen.put_pixel(0, 0, Rgba([1, 2, 3, 255]));
+        fake_screen.put_pixel(9, 9, Rgba([4, 5, 6, 255]));
+
+        let screen_area = capture_fake_screen_area(&fake_screen, -2, -3, 4, 4);
+        assert_eq!(*screen_area.get_pixel(2, 3), Rgba([1, 2, 3, 255]));
+        assert_eq!(*screen_area.get_pixel(0, 0), Rgba([0, 0, 0, 0]));
+
+        let screen_area = capture_fake_screen_area(&fake_screen, 8, 8, 4, 4);
+        assert_eq!(*screen_area.get_pixel(1, 1), Rgba([4, 5, 6, 255]));
+        assert_eq!(*screen_area.get_pixel(3, 3), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_is_area_on_screen() {
+        assert!(is_area_on_screen(10, 10, 0, 0, 10, 10));
+        assert!(is_area_on_screen(10, 10, 9, 9, 1, 1));
+        assert!(!is_area_on_screen(10, 10, -5, -5, 1, 1));
+        assert!(!is_area_on_screen(10, 10, 9, 5, 2, 1));
+        assert!(!is_area_on_screen(10, 10, i32::MAX, 5, u32::MAX, 1));
+    }
+
+    #[test]
+    fn test_find_image_on_fake_screen_with_unreadable_image() {
+        let fake_screen = RgbaImage::new(10, 10);
+
+        assert!(matches!(find_image_on_fake_screen(&fake_screen, &String::from("no_such_image.png")), Err(CaptureError::UnreadableImage(..))));
+    }
+}
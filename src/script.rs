@@ -0,0 +1,182 @@
+use std::path::Path;
+use log::error;
+
+use crate::command_types::*;
+use crate::error::Error;
+use crate::structured_script::*;
+
+pub const COMMENT_PREFIX: &str = "#";
+
+pub fn is_comment(line: &str) -> bool {
+    return line.trim_start().starts_with(COMMENT_PREFIX);
+}
+
+// Leaving out an exit: would run on past where the script meant to stop, so one that is not valid is never skipped
+fn is_exit_line(line: &str) -> bool {
+    return line.starts_with("exit:");
+}
+
+// What to do with a line that is not a command
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BadLines {
+    // Give an Error::Parse for the first one
+    Reject,
+    // Log it and leave it out, which is what the program does. An exit: line that is not valid is still an Error::Parse
+    Skip
+}
+
+// A parsed script, ready to be run by an executor
+pub struct Script {
+    lines: Vec<ScriptLine>
+}
+
+impl Script {
+    // Empty lines and comments are skipped. Any other line that is not a command is an error
+    pub fn parse(text: &str) -> Result<Script, Error> {
+        return Script::parse_with(text, BadLines::Reject);
+    }
+
+    pub fn parse_with(text: &str, bad_lines: BadLines) -> Result<Script, Error> {
+        let mut lines: Vec<ScriptLine> = vec![];
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim_start();
+            if line.is_empty() || is_comment(line) {
+                continue;
+            }
+
+            let line_number = line_index + 1;
+            let text = line.to_string();
+            let command = match (try_parse_command_from_line(&text), bad_lines) {
+                (Some(command), _) => command,
+                (None, BadLines::Reject) => { return Err(Error::Parse{line_number, text}); },
+                (None, BadLines::Skip) if is_exit_line(&text) => { return Err(Error::Parse{line_number, text}); },
+                (None, BadLines::Skip) => {
+                    error!(target: "commands_debug", "Line {} is not formatted properly, not using it as a command: {}", line_number, text);
+                    continue;
+                }
+            };
+
+            lines.push(ScriptLine{line_number, text, command});
+        }
+
+        let script = Script{lines};
+        script.check_blocks()?;
+        return Ok(script);
+    }
+
+    // Every every: needs an end_every: after it, and every end_every: an every: before it
+    fn check_blocks(&self) -> Result<(), Error> {
+        let mut open_blocks: Vec<&ScriptLine> = vec![];
+
+        for script_line in &self.lines {
+            match script_line.command {
+                ParsedCommand::Every{..} => { open_blocks.push(script_line); },
+                ParsedCommand::EndEvery => {
+                    if open_blocks.pop().is_none() {
+                        return Err(Error::UnmatchedBlock{line_number: script_line.line_number, text: script_line.text.clone()});
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        if let Some(script_line) = open_blocks.pop() {
+            return Err(Error::UnmatchedBlock{line_number: script_line.line_number, text: script_line.text.clone()});
+        }
+
+        return Ok(());
+    }
+
+    // A YAML, JSON or TOML script. Its commands are numbered in order in place of line numbers
+    pub fn parse_structured(text: &str, format: ScriptFileFormat) -> Result<Script, Error> {
+        let script = Script::from_commands(parse_structured_commands(text, format)?);
+        script.check_blocks()?;
+        return Ok(script);
+    }
+
+    // Lines are numbered in order, and their text is the command written out as a script line
+    pub fn from_commands(commands: Vec<ParsedCommand>) -> Script {
+        let lines = commands.into_iter().enumerate().map(|(line_index, command)| ScriptLine {
+            line_number: line_index + 1,
+            text: command.to_string(),
+            command
+        }).collect();
+
+        return Script{lines};
+    }
+
+    // The format is chosen by the file extension, with anything other than .yaml, .yml, .json or .toml read as text
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Script, Error> {
+        return Script::from_file_with(path, BadLines::Reject);
+    }
+
+    // bad_lines only applies to text scripts. A structured script that is not valid is always an error
+    pub fn from_file_with<P: AsRef<Path>>(path: P, bad_lines: BadLines) -> Result<Script, Error> {
+        let path = path.as_ref();
+
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(source) => { return Err(Error::Io{path: path.display().to_string(), source}); }
+        };
+
+        return match ScriptFileFormat::from_path(path) {
+            ScriptFileFormat::Text => Script::parse_with(&text, bad_lines),
+            format => Script::parse_structured(&text, format)
+        };
+    }
+
+    pub fn lines(&self) -> &[ScriptLine] {
+        return &self.lines;
+    }
+
+    pub fn commands(&self) -> Vec<ParsedCommand> {
+        return self.lines.iter().map(|script_line| script_line.command.clone()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_parse_keeps_line_numbers() {
+        let script = Script::parse("mouse_move: 10 20\n\n# then wait\n    wait: 100\r\n").unwrap();
+
+        assert_eq!(script.lines().len(), 2);
+        assert_eq!(script.lines()[1].line_number, 4);
+        assert_eq!(script.lines()[1].text, "wait: 100");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(Script::parse("wait: 100\nwait: soon"), Err(Error::Parse{line_number: 2, ..})));
+        assert!(matches!(Script::parse("every: 100\nwait: 10"), Err(Error::UnmatchedBlock{line_number: 1, ..})));
+        assert!(matches!(Script::parse("end_every:"), Err(Error::UnmatchedBlock{line_number: 1, ..})));
+    }
+
+    #[test]
+    fn test_parse_skipping_bad_lines() {
+        let script = Script::parse_with("wait: 100\nfunction_key: tab click\nwait: soon\nkey: a click", BadLines::Skip).unwrap();
+
+        assert_eq!(script.lines().len(), 2);
+        assert_eq!(script.lines()[1].line_number, 4);
+        assert!(matches!(Script::parse_with("every: 100\nwait: soon", BadLines::Skip), Err(Error::UnmatchedBlock{line_number: 1, ..})));
+        assert!(matches!(Script::parse_with("wait: 100\nexit: 2", BadLines::Skip), Err(Error::Parse{line_number: 2, ..})));
+        assert!(matches!(Script::parse_with("exit: soon", BadLines::Skip), Err(Error::Parse{line_number: 1, ..})));
+    }
+
+    #[test]
+    fn test_parse_structured() {
+        let script = Script::parse_structured("commands:\n  - every: {interval_ms: 500}\n  - mouse_click: left\n  - end_every\n", ScriptFileFormat::Yaml).unwrap();
+
+        assert_eq!(script.commands(), vec![ParsedCommand::Every{interval_ms: 500, times: None},
+                                           ParsedCommand::MouseClick(enigo::MouseButton::Left),
+                                           ParsedCommand::EndEvery]);
+        assert_eq!(script.lines()[1].line_number, 2);
+        assert_eq!(script.lines()[1].text, "mouse_click: left");
+
+        assert!(matches!(Script::parse_structured(r#"{"commands": ["end_every"]}"#, ScriptFileFormat::Json), Err(Error::UnmatchedBlock{line_number: 1, ..})));
+        assert!(matches!(Script::parse_structured(r#"{"commands": [{"wait": "soon"}]}"#, ScriptFileFormat::Json), Err(Error::Structured{format: "JSON", ..})));
+    }
+}
@@ -0,0 +1,207 @@
+use enigo::{Key, MouseButton};
+
+use crate::command_types::*;
+use crate::error::Error;
+use crate::mouse_path::*;
+use crate::timing::*;
+use crate::script::Script;
+
+// Builds a script in code instead of parsing it from text. Every method adds the command the
+// matching script line would parse to, so a built script runs the same as its text
+#[derive(Default)]
+pub struct ScriptBuilder {
+    commands: Vec<ParsedCommand>
+}
+
+impl ScriptBuilder {
+    pub fn new() -> ScriptBuilder {
+        return ScriptBuilder::default();
+    }
+
+    pub fn command(mut self, command: ParsedCommand) -> ScriptBuilder {
+        self.commands.push(command);
+        return self;
+    }
+
+    fn key_use(self, key: Key, button_action: ButtonAction) -> ScriptBuilder {
+        return match key {
+            Key::Layout(layout_key) => self.command(ParsedCommand::LayoutKeyUse(layout_key, button_action)),
+            _ => self.command(ParsedCommand::FunctionKeyUse(key, button_action))
+        };
+    }
+
+    pub fn key_click(self, key: Key) -> ScriptBuilder {
+        return self.key_use(key, ButtonAction::Click);
+    }
+
+    pub fn key_down(self, key: Key) -> ScriptBuilder {
+        return self.key_use(key, ButtonAction::Press);
+    }
+
+    pub fn key_up(self, key: Key) -> ScriptBuilder {
+        return self.key_use(key, ButtonAction::Release);
+    }
+
+    pub fn key_hold(self, key: Key, duration_ms: u64) -> ScriptBuilder {
+        return self.command(ParsedCommand::KeyHold{key, duration_ms});
+    }
+
+    pub fn type_text(self, text: &str) -> ScriptBuilder {
+        return self.command(ParsedCommand::KeySequence(text.to_string()));
+    }
+
+    // Types one character every delay_ms milliseconds
+    pub fn type_text_with_delay(self, text: &str, delay_ms: u64) -> ScriptBuilder {
+        return self.command(ParsedCommand::KeySequenceWithDelay{sequence: text.to_string(), delay_ms});
+    }
+
+    pub fn typing_delay(self, delay_ms: u64) -> ScriptBuilder {
+        return self.command(ParsedCommand::SetKeySequenceDelay(delay_ms));
+    }
+
+    pub fn click(self, mouse_button: MouseButton) -> ScriptBuilder {
+        return self.command(ParsedCommand::MouseClick(mouse_button));
+    }
+
+    pub fn mouse_down(self, mouse_button: MouseButton) -> ScriptBuilder {
+        return self.command(ParsedCommand::MouseDown(mouse_button));
+    }
+
+    pub fn mouse_up(self, mouse_button: MouseButton) -> ScriptBuilder {
+        return self.command(ParsedCommand::MouseRelease(mouse_button));
+    }
+
+    pub fn mouse_hold(self, mouse_button: MouseButton, duration_ms: u64) -> ScriptBuilder {
+        return self.command(ParsedCommand::MouseHold{mouse_button, duration_ms});
+    }
+
+    pub fn move_to(self, x: i32, y: i32) -> ScriptBuilder {
+        return self.command(ParsedCommand::MouseMove{x, y});
+    }
+
+    // Travels to x, y over duration_ms along a path of path_type, with no jitter or overshoot
+    pub fn move_to_over(self, x: i32, y: i32, duration_ms: u64, path_type: MousePathType) -> ScriptBuilder {
+        let movement = MouseMovement{duration_ms,
+                                     path_type,
+                                     jitter: 0,
+                                     overshoot: 0,
+                                     step_interval_ms: DEFAULT_MOUSE_STEP_INTERVAL_MS};
+
+        return self.command(ParsedCommand::MouseMoveSmooth{x, y, movement});
+    }
+
+    pub fn move_by(self, x: i32, y: i32) -> ScriptBuilder {
+        return self.command(ParsedCommand::MouseMoveRelative{x, y});
+    }
+
+    pub fn drag(self, mouse_button: MouseButton, start: (i32, i32), end: (i32, i32), duration_ms: u64) -> ScriptBuilder {
+        return self.command(ParsedCommand::MouseDrag{mouse_button,
+                                                     start_x: start.0,
+                                                     start_y: start.1,
+                                                     end_x: end.0,
+                                                     end_y: end.1,
+                                                     duration_ms});
+    }
+
+    pub fn wait_ms(self, wait_time_ms: u64) -> ScriptBuilder {
+        return self.command(ParsedCommand::Wait(wait_time_ms));
+    }
+
+    pub fn wait_random_ms(self, min_ms: u64, max_ms: u64, distribution: WaitDistribution) -> ScriptBuilder {
+        return self.command(ParsedCommand::WaitRandom{min_ms, max_ms, distribution});
+    }
+
+    // Clicks key if the screen area at start_x, start_y matches the image by at least match_threshold percent
+    pub fn screen_compare_key_click(self, key: Key, input_file_path: &str, start_x: i32, start_y: i32, match_threshold: f64) -> ScriptBuilder {
+        let input_file_path = input_file_path.to_string();
+
+        return match key {
+            Key::Layout(layout_key) => self.command(ParsedCommand::ScreenCompareLayoutKeyClick{layout_key, input_file_path, start_x, start_y, match_threshold}),
+            _ => self.command(ParsedCommand::ScreenCompareFunctionKeyClick{function_key: key, input_file_path, start_x, start_y, match_threshold})
+        };
+    }
+
+    pub fn label(self, label: &str) -> ScriptBuilder {
+        return self.command(ParsedCommand::Label(label.to_string()));
+    }
+
+    pub fn breakpoint(self) -> ScriptBuilder {
+        return self.command(ParsedCommand::Breakpoint);
+    }
+
+    // exit_code is not checked here, so use one that is_valid_exit_code accepts, or to_text will fail
+    pub fn exit(self, exit_code: i32) -> ScriptBuilder {
+        return self.command(ParsedCommand::Exit(exit_code));
+    }
+
+    // Adds an every: block whose body is built by the given function
+    pub fn every<F>(self, interval_ms: u64, times: Option<u64>, build_body: F) -> ScriptBuilder
+        where F: FnOnce(ScriptBuilder) -> ScriptBuilder {
+        let mut builder = self.command(ParsedCommand::Every{interval_ms, times});
+        builder = build_body(builder);
+
+        return builder.command(ParsedCommand::EndEvery);
+    }
+
+    pub fn commands(&self) -> &[ParsedCommand] {
+        return &self.commands;
+    }
+
+    pub fn build(self) -> Script {
+        return Script::from_commands(self.commands);
+    }
+
+    // The script in the text format, one command per line. Fails on the first command whose line would
+    // not parse back to it, like an exit: with a reserved code, a label with spaces or text with a line break
+    pub fn to_text(&self) -> Result<String, Error> {
+        let mut lines: Vec<String> = vec![];
+
+        for (line_index, command) in self.commands.iter().enumerate() {
+            if !has_text_line(command) {
+                return Err(Error::Parse{line_number: line_index + 1, text: command.to_string()});
+            }
+
+            lines.push(command.to_string());
+        }
+
+        return Ok(lines.join("\n") + "\n");
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_built_script_matches_its_parsed_text() {
+        let builder = ScriptBuilder::new()
+            .move_to(500, 500)
+            .click(MouseButton::Left)
+            .wait_ms(100)
+            .type_text("hello world")
+            .key_click(Key::Tab)
+            .every(1000, Some(3), |body| body.key_hold(Key::Layout('w'), 250).wait_random_ms(100, 200, WaitDistribution::Normal))
+            .screen_compare_key_click(Key::Layout('g'), "fish.png", 10, 20, 95.0);
+
+        let text = builder.to_text().unwrap();
+        let parsed_script = Script::parse(&text).unwrap();
+        let built_script = builder.build();
+
+        assert_eq!(built_script.lines().len(), 9);
+        for (built_line, parsed_line) in built_script.lines().iter().zip(parsed_script.lines()) {
+            assert_eq!(built_line.command, parsed_line.command);
+            assert_eq!(built_line.line_number, parsed_line.line_number);
+            assert_eq!(built_line.text, parsed_line.text);
+        }
+        assert!(text.starts_with("mouse_move: 500 500\nmouse_click: left\nwait: 100\nkey_sequence: hello world\nkey: tab click\nevery: 1000 3\n"));
+    }
+
+    #[test]
+    fn test_to_text_rejects_commands_it_cannot_write() {
+        assert!(matches!(ScriptBuilder::new().wait_ms(10).exit(2).to_text(), Err(Error::Parse{line_number: 2, ..})));
+        assert!(ScriptBuilder::new().type_text("a\nb").to_text().is_err());
+        assert!(ScriptBuilder::new().label("a b").to_text().is_err());
+        assert!(ScriptBuilder::new().key_click(Key::Raw(65)).to_text().is_err());
+        assert!(ScriptBuilder::new().exit(4).label("start").to_text().is_ok());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::command_types::*;
+use crate::error::Error;
+use crate::script::*;
+
+// Lines inside an every: block are indented this much more than the block
+const INDENT: &str = "    ";
+
+// Takes the first word_count words off the start of text, and returns them along with the rest of it
+fn split_first_words(text: &str, word_count: usize) -> (Vec<&str>, &str) {
+    let mut words: Vec<&str> = vec![];
+    let mut rest = text.trim_start();
+
+    while words.len() < word_count && !rest.is_empty() {
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        words.push(&rest[..word_end]);
+        rest = rest[word_end..].trim_start();
+    }
+
+    return (words, rest);
+}
+
+// Puts exactly one space after the colon and between parameters. The text of a key sequence and
+// the image path of a screen comparison are left as written, since spaces in them matter
+fn normalize_spacing(line: &str) -> String {
+    let line = line.trim_start();
+
+    let (name, parameters) = match line.split_once(':') {
+        Some((name, parameters)) => (name.trim_end(), parameters),
+        None => { return line.trim_end().to_string(); }
+    };
+
+    let normalized_parameters = match name {
+        "key_sequence" => { return line.to_string(); },
+        // Only the typing speed is a parameter, the text after it is typed
+        "key_sequence_delay" => match parameters.trim_start().split_once(' ') {
+            Some((option, sequence)) => format!("{} {}", option, sequence),
+            None => parameters.trim().to_string()
+        },
+        "screen_compare_key_click" => {
+            let (mut words, image_path) = split_first_words(parameters, 4);
+            let image_path = image_path.trim_end();
+            if !image_path.is_empty() {
+                words.push(image_path);
+            }
+            words.join(" ")
+        },
+        _ => parameters.split_whitespace().collect::<Vec<&str>>().join(" ")
+    };
+
+    if normalized_parameters.is_empty() {
+        return format!("{}:", name);
+    }
+
+    return format!("{}: {}", name, normalized_parameters);
+}
+
+fn format_comment(comment_line: &str) -> String {
+    let comment = comment_line.trim().strip_prefix(COMMENT_PREFIX).unwrap_or_default();
+
+    if comment.is_empty() || comment.starts_with(' ') || comment.starts_with(COMMENT_PREFIX) {
+        return format!("{}{}", COMMENT_PREFIX, comment);
+    }
+
+    return format!("{} {}", COMMENT_PREFIX, comment);
+}
+
+// Rewrites a script in its canonical form: commands as Display writes them, every: blocks
+// indented, comments kept in place and runs of empty lines made into one. Lines end the way
+// the first line of text does, so Windows line endings are kept
+pub fn format_script(text: &str) -> Result<String, Error> {
+    let line_ending = match text.find('\n') {
+        Some(line_end) if text[..line_end].ends_with('\r') => "\r\n",
+        _ => "\n"
+    };
+    let mut formatted_lines: Vec<String> = vec![];
+    let mut open_blocks: Vec<(usize, String)> = vec![];
+    let mut is_previous_line_empty = true;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+
+        if line.trim().is_empty() {
+            if !is_previous_line_empty {
+                formatted_lines.push(String::new());
+            }
+            is_previous_line_empty = true;
+            continue;
+        }
+        is_previous_line_empty = false;
+
+        if is_comment(line) {
+            formatted_lines.push(INDENT.repeat(open_blocks.len()) + &format_comment(line));
+            continue;
+        }
+
+        let command = match try_parse_command_from_line(&normalize_spacing(line)) {
+            Some(command) => command,
+            None => { return Err(Error::Parse{line_number, text: line.trim().to_string()}); }
+        };
+
+        if command == ParsedCommand::EndEvery && open_blocks.pop().is_none() {
+            return Err(Error::UnmatchedBlock{line_number, text: line.trim().to_string()});
+        }
+
+        formatted_lines.push(INDENT.repeat(open_blocks.len()) + &command.to_string());
+
+        if let ParsedCommand::Every{..} = command {
+            open_blocks.push((line_number, line.trim().to_string()));
+        }
+    }
+
+    if let Some((line_number, text)) = open_blocks.pop() {
+        return Err(Error::UnmatchedBlock{line_number, text});
+    }
+
+    if formatted_lines.last().map(String::is_empty) == Some(true) {
+        formatted_lines.pop();
+    }
+
+    return Ok(formatted_lines.join(line_ending) + line_ending);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_format_script() {
+        let text = "\n#start\nmouse_move:   500  500\nevery: 1000 3\nkey: TAB click\n  wait: 10..20 uniform\n\n\n# typed as written\nkey_sequence: two  spaces\nkey_sequence_delay:  cpm=600  two  spaces\nend_every:\nscreen_compare_key_click:  g 1 2  95.0  my  fish.png  \n\n";
+
+        assert_eq!(format_script(text).unwrap(),
+                   "# start\nmouse_move: 500 500\nevery: 1000 3\n    key: tab click\n    wait: 10..20\n\n    # typed as written\n    key_sequence: two  spaces\n    key_sequence_delay: delay=100  two  spaces\nend_every:\nscreen_compare_key_click: g 1 2 95 my  fish.png\n");
+    }
+
+    #[test]
+    fn test_format_script_is_stable() {
+        let formatted = format_script("every: 100\nwait: 5\nend_every:\n").unwrap();
+
+        assert_eq!(format_script(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_script_keeps_line_endings() {
+        let text = "mouse_move: 1 2\r\nevery: 100\r\nwait: 5\r\nend_every:\r\n";
+
+        assert_eq!(format_script(text).unwrap(), "mouse_move: 1 2\r\nevery: 100\r\n    wait: 5\r\nend_every:\r\n");
+        assert_eq!(format_script("wait: 5").unwrap(), "wait: 5\n");
+    }
+
+    #[test]
+    fn test_format_script_errors() {
+        assert!(matches!(format_script("wait: 1\nmouse_click: sideways"), Err(Error::Parse{line_number: 2, ..})));
+        assert!(matches!(format_script("every: 100\nwait: 1"), Err(Error::UnmatchedBlock{line_number: 1, ..})));
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use chrono::{Local, NaiveTime};
+use log::error;
+
+use crate::input_backend::*;
+
+// Image stop conditions need a screen capture, so they are checked less often than the rest
+const IMAGE_CHECK_INTERVAL_MS: u64 = 500;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum StopReason {
+    Hotkey,
+    MouseCorner,
+    ImageAppeared,
+    ImageDisappeared,
+    TimeReached,
+    ComparisonsFailed,
+    SentinelFile,
+    Requested,
+    // Ctrl+C or a termination signal
+    Signal,
+    ExitCommand(i32)
+}
+
+// The exit codes the program uses for its own outcomes: 1 for an error, 101 for a crash and the
+// stop reasons. exit: commands cannot use them, so a calling script can always tell the outcomes apart
+pub const RESERVED_EXIT_CODES: [i32; 11] = [1, 2, 3, 10, 11, 12, 13, 14, 15, 101, 130];
+
+// 0 means the run finished, and codes above 255 are cut down to one of the others on most systems
+pub fn is_valid_exit_code(exit_code: i32) -> bool {
+    return (1..=255).contains(&exit_code) && !RESERVED_EXIT_CODES.contains(&exit_code);
+}
+
+impl StopReason {
+    // Each reason has its own process exit code, so a calling script can tell them apart.
+    // exit: commands choose their own
+    pub fn exit_code(&self) -> i32 {
+        return match self {
+            StopReason::Hotkey => 2,
+            StopReason::MouseCorner => 3,
+            StopReason::ImageAppeared => 10,
+            StopReason::ImageDisappeared => 11,
+            StopReason::TimeReached => 12,
+            StopReason::ComparisonsFailed => 13,
+            StopReason::SentinelFile => 14,
+            StopReason::Requested => 15,
+            StopReason::Signal => 130,
+            StopReason::ExitCommand(exit_code) => *exit_code
+        };
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            StopReason::Hotkey => write!(formatter, "the stop hotkey was pressed"),
+            StopReason::MouseCorner => write!(formatter, "the mouse cursor reached a screen corner"),
+            StopReason::ImageAppeared => write!(formatter, "the stop image appeared"),
+            StopReason::ImageDisappeared => write!(formatter, "the stop image disappeared"),
+            StopReason::TimeReached => write!(formatter, "the stop time was reached"),
+            StopReason::ComparisonsFailed => write!(formatter, "too many screen comparisons in a row did not match"),
+            StopReason::SentinelFile => write!(formatter, "the stop file appeared"),
+            StopReason::Requested => write!(formatter, "a stop was requested"),
+            StopReason::Signal => write!(formatter, "a stop signal was received"),
+            StopReason::ExitCommand(exit_code) => write!(formatter, "an exit command ran with code {}", exit_code)
+        };
+    }
+}
+
+// An area of the screen compared the same way as screen_compare_key_click
+#[derive(PartialEq, Debug, Clone)]
+pub struct ImageCondition {
+    pub input_file_path: String,
+    pub start_x: i32,
+    pub start_y: i32,
+    pub match_threshold: f64
+}
+
+// Reads <start_x> <start_y> <match_threshold> <image_file_path>
+pub fn parse_image_condition(values: &[String]) -> Option<ImageCondition> {
+    if values.len() != 4 {
+        return None;
+    }
+
+    return Some(ImageCondition {
+        start_x: values[0].parse::<i32>().ok()?,
+        start_y: values[1].parse::<i32>().ok()?,
+        match_threshold: values[2].parse::<f64>().ok()?,
+        input_file_path: values[3].clone()
+    });
+}
+
+// How long from now until the next time the local clock reads <time>, which is HH:MM or HH:MM:SS
+pub fn parse_duration_until(time: &str) -> Option<Duration> {
+    let stop_time = NaiveTime::parse_from_str(time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+        .ok()?;
+
+    return Some(duration_until(Local::now().time(), stop_time));
+}
+
+fn duration_until(now: NaiveTime, stop_time: NaiveTime) -> Duration {
+    let mut milliseconds_until = (stop_time - now).num_milliseconds();
+    // A time that has already passed today means that time tomorrow
+    if milliseconds_until <= 0 {
+        milliseconds_until += 24 * 60 * 60 * 1000;
+    }
+
+    return Duration::from_millis(milliseconds_until as u64);
+}
+
+#[derive(Default)]
+pub struct StopConditions {
+    pub image_appears: Option<ImageCondition>,
+    pub image_disappears: Option<ImageCondition>,
+    // Compared with the backend's elapsed time, so a dry run simulates it too
+    pub stop_time: Option<Duration>,
+    pub max_consecutive_failed_comparisons: Option<u64>,
+    pub sentinel_file: Option<PathBuf>,
+    // Set from outside, for example by the control server, to stop the run
+    pub stop_requested: Option<Arc<AtomicBool>>,
+    // Set by a Ctrl+C or termination signal handler
+    pub stop_signal: Option<Arc<AtomicBool>>,
+    consecutive_failed_comparisons: u64,
+    last_image_check_time: Option<Duration>
+}
+
+impl StopConditions {
+    pub fn new() -> StopConditions {
+        return StopConditions::default();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.image_appears.is_none()
+            && self.image_disappears.is_none()
+            && self.stop_time.is_none()
+            && self.max_consecutive_failed_comparisons.is_none()
+            && self.sentinel_file.is_none()
+            && self.stop_requested.is_none()
+            && self.stop_signal.is_none();
+    }
+
+    // Whether waits have to be cut into short pieces to check the conditions. The failed comparison
+    // count only changes when a comparison runs, so it never needs checking during a wait
+    pub fn needs_polling(&self) -> bool {
+        return self.image_appears.is_some()
+            || self.image_disappears.is_some()
+            || self.stop_time.is_some()
+            || self.sentinel_file.is_some()
+            || self.stop_requested.is_some()
+            || self.stop_signal.is_some();
+    }
+
+    fn is_image_matched(backend: &mut dyn InputBackend, image_condition: &ImageCondition) -> bool {
+        // An image that cannot be compared is treated as not being on the screen
+        let match_percentage = match backend.compare_screen_to_image_file(&image_condition.input_file_path,
+                                                                          image_condition.start_x,
+                                                                          image_condition.start_y) {
+            Ok(match_percentage) => match_percentage,
+            Err(error_reason) => {
+                error!(target: "commands_debug", "Checking the stop image {} failed because {}", image_condition.input_file_path, error_reason);
+                0.0
+            }
+        };
+
+        return image_condition.match_threshold <= (match_percentage * 100.0);
+    }
+
+    pub fn check(&mut self, backend: &mut dyn InputBackend) -> Option<StopReason> {
+        let now = backend.elapsed();
+
+        if let Some(stop_time) = self.stop_time {
+            if now >= stop_time {
+                return Some(StopReason::TimeReached);
+            }
+        }
+
+        if let Some(stop_requested) = &self.stop_requested {
+            if stop_requested.load(Ordering::Relaxed) {
+                return Some(StopReason::Requested);
+            }
+        }
+
+        if let Some(stop_signal) = &self.stop_signal {
+            if stop_signal.load(Ordering::Relaxed) {
+                return Some(StopReason::Signal);
+            }
+        }
+
+        if let Some(sentinel_file) = &self.sentinel_file {
+            if sentinel_file.exists() {
+                return Some(StopReason::SentinelFile);
+            }
+        }
+
+        if self.image_appears.is_none() && self.image_disappears.is_none() {
+            return None;
+        }
+
+        let is_image_check_due = match self.last_image_check_time {
+            Some(last_image_check_time) => now >= last_image_check_time + Duration::from_millis(IMAGE_CHECK_INTERVAL_MS),
+            None => true
+        };
+        if !is_image_check_due {
+            return None;
+        }
+        self.last_image_check_time = Some(now);
+
+        if let Some(image_condition) = &self.image_appears {
+            if StopConditions::is_image_matched(backend, image_condition) {
+                return Some(StopReason::ImageAppeared);
+            }
+        }
+
+        if let Some(image_condition) = &self.image_disappears {
+            if !StopConditions::is_image_matched(backend, image_condition) {
+                return Some(StopReason::ImageDisappeared);
+            }
+        }
+
+        return None;
+    }
+
+    // Called with the outcome of every screen comparison command in the script
+    pub fn record_comparison(&mut self, matched: bool) -> Option<StopReason> {
+        if matched {
+            self.consecutive_failed_comparisons = 0;
+            return None;
+        }
+
+        self.consecutive_failed_comparisons += 1;
+
+        return match self.max_consecutive_failed_comparisons {
+            Some(max_consecutive_failed_comparisons) if self.consecutive_failed_comparisons >= max_consecutive_failed_comparisons => Some(StopReason::ComparisonsFailed),
+            _ => None
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_duration_until_wraps_to_tomorrow() {
+        let now = NaiveTime::from_hms_opt(23, 0, 0).unwrap();
+
+        assert_eq!(duration_until(now, NaiveTime::from_hms_opt(23, 30, 0).unwrap()), Duration::from_secs(30 * 60));
+        assert_eq!(duration_until(now, NaiveTime::from_hms_opt(6, 30, 0).unwrap()), Duration::from_secs(7 * 60 * 60 + 30 * 60));
+    }
+
+    #[test]
+    fn test_consecutive_failed_comparisons() {
+        let mut stop_conditions = StopConditions::new();
+        stop_conditions.max_consecutive_failed_comparisons = Some(2);
+
+        assert_eq!(stop_conditions.record_comparison(false), None);
+        assert_eq!(stop_conditions.record_comparison(true), None);
+        assert_eq!(stop_conditions.record_comparison(false), None);
+        assert_eq!(stop_conditions.record_comparison(false), Some(StopReason::ComparisonsFailed));
+    }
+
+    #[test]
+    fn test_stop_time_uses_backend_time() {
+        let mut dry_run_backend = DryRunBackend::new(None, 100.0);
+        let mut stop_conditions = StopConditions::new();
+        stop_conditions.stop_time = Some(Duration::from_secs(60));
+
+        assert_eq!(stop_conditions.check(&mut dry_run_backend), None);
+        dry_run_backend.sleep(Duration::from_secs(60));
+        assert_eq!(stop_conditions.check(&mut dry_run_backend), Some(StopReason::TimeReached));
+    }
+
+    #[test]
+    fn test_stop_signal() {
+        let mut dry_run_backend = DryRunBackend::new(None, 100.0);
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let mut stop_conditions = StopConditions::new();
+        stop_conditions.stop_signal = Some(Arc::clone(&stop_signal));
+
+        assert!(stop_conditions.needs_polling());
+        assert_eq!(stop_conditions.check(&mut dry_run_backend), None);
+        stop_signal.store(true, Ordering::Relaxed);
+        assert_eq!(stop_conditions.check(&mut dry_run_backend), Some(StopReason::Signal));
+        assert_eq!(StopReason::Signal.exit_code(), 130);
+    }
+}
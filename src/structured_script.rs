@@ -0,0 +1,185 @@
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use crate::command_types::*;
+use crate::error::Error;
+use crate::script_format::*;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ScriptFileFormat {
+    Text,
+    Yaml,
+    Json,
+    Toml
+}
+
+impl ScriptFileFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> ScriptFileFormat {
+        let extension = path.as_ref().extension().and_then(|extension| extension.to_str()).map(str::to_lowercase);
+
+        return match extension.as_deref() {
+            Some("yaml") | Some("yml") => ScriptFileFormat::Yaml,
+            Some("json") => ScriptFileFormat::Json,
+            Some("toml") => ScriptFileFormat::Toml,
+            _ => ScriptFileFormat::Text
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ScriptFileFormat::Text => "text",
+            ScriptFileFormat::Yaml => "YAML",
+            ScriptFileFormat::Json => "JSON",
+            ScriptFileFormat::Toml => "TOML"
+        };
+    }
+}
+
+// The top level of a structured script. TOML needs a table there, so the commands are a field of one
+#[derive(Serialize, Deserialize)]
+struct ScriptDocument {
+    commands: Vec<ParsedCommand>
+}
+
+fn structured_error(format: ScriptFileFormat, message: String) -> Error {
+    return Error::Structured{format: format.name(), message};
+}
+
+pub fn parse_structured_commands(text: &str, format: ScriptFileFormat) -> Result<Vec<ParsedCommand>, Error> {
+    let document: ScriptDocument = match format {
+        // YAML names commands with a one-entry map like the other formats, rather than with a !tag
+        ScriptFileFormat::Yaml => serde_yaml::with::singleton_map_recursive::deserialize(serde_yaml::Deserializer::from_str(text))
+            .map_err(|error_reason| structured_error(format, error_reason.to_string()))?,
+        ScriptFileFormat::Json => serde_json::from_str(text)
+            .map_err(|error_reason| structured_error(format, error_reason.to_string()))?,
+        ScriptFileFormat::Toml => toml::from_str(text)
+            .map_err(|error_reason| structured_error(format, error_reason.to_string()))?,
+        ScriptFileFormat::Text => {
+            return Err(structured_error(format, String::from("text scripts are read with Script::parse")));
+        }
+    };
+
+    // Every value a text line is checked for, like the exit code or a label without spaces, is checked here too
+    if let Some(command_index) = document.commands.iter().position(|command| !has_text_line(command)) {
+        return Err(structured_error(format, format!("command {} is not valid: {}", command_index + 1, document.commands[command_index])));
+    }
+
+    return Ok(document.commands);
+}
+
+// Text scripts are written in their canonical form, so every: blocks come out indented
+pub fn write_commands(commands: &[ParsedCommand], format: ScriptFileFormat) -> Result<String, Error> {
+    let document = ScriptDocument{commands: commands.to_vec()};
+
+    return match format {
+        ScriptFileFormat::Text => {
+            let lines: Vec<String> = commands.iter().map(|command| command.to_string()).collect();
+            format_script(&lines.join("\n"))
+        },
+        ScriptFileFormat::Yaml => {
+            let mut yaml: Vec<u8> = vec![];
+            serde_yaml::with::singleton_map_recursive::serialize(&document, &mut serde_yaml::Serializer::new(&mut yaml))
+                .map_err(|error_reason| structured_error(format, error_reason.to_string()))?;
+            Ok(String::from_utf8(yaml).expect("YAML is always written as UTF-8"))
+        },
+        ScriptFileFormat::Json => serde_json::to_string_pretty(&document)
+            .map(|json| json + "\n")
+            .map_err(|error_reason| structured_error(format, error_reason.to_string())),
+        ScriptFileFormat::Toml => toml::to_string(&document)
+            .map_err(|error_reason| structured_error(format, error_reason.to_string()))
+    };
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const EXAMPLE_SCRIPT: &str = "key: a click
+key: tab press
+key_sequence_delay: delay=40 Hello there
+mouse_move: 100 200 300 bezier jitter=2
+mouse_drag: left 0 0 50 50 250
+wait: 100..200 normal
+label: cast
+every: 500 3
+    screen_compare_key_click: f 10 20 90.5 resources/bobber.png
+end_every:
+breakpoint:
+exit: 4
+";
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(ScriptFileFormat::from_path("fishing.YAML"), ScriptFileFormat::Yaml);
+        assert_eq!(ScriptFileFormat::from_path("fishing.yml"), ScriptFileFormat::Yaml);
+        assert_eq!(ScriptFileFormat::from_path("fishing.json"), ScriptFileFormat::Json);
+        assert_eq!(ScriptFileFormat::from_path("fishing.toml"), ScriptFileFormat::Toml);
+        assert_eq!(ScriptFileFormat::from_path("fishing.txt"), ScriptFileFormat::Text);
+        assert_eq!(ScriptFileFormat::from_path("fishing"), ScriptFileFormat::Text);
+    }
+
+    #[test]
+    fn test_structured_formats_round_trip() {
+        let commands = crate::script::Script::parse(EXAMPLE_SCRIPT).unwrap().commands();
+
+        for format in [ScriptFileFormat::Yaml, ScriptFileFormat::Json, ScriptFileFormat::Toml] {
+            let text = write_commands(&commands, format).unwrap();
+            assert_eq!(parse_structured_commands(&text, format).unwrap(), commands, "{}", text);
+        }
+
+        assert_eq!(write_commands(&commands, ScriptFileFormat::Text).unwrap(), EXAMPLE_SCRIPT);
+    }
+
+    #[test]
+    fn test_structured_defaults() {
+        let commands = parse_structured_commands(r#"{"commands": [
+            {"mouse_move_smooth": {"x": 1, "y": 2, "movement": {"duration_ms": 300}}},
+            {"wait_random": {"min_ms": 100, "max_ms": 200}},
+            {"function_key_use": ["Escape", "click"]}
+        ]}"#, ScriptFileFormat::Json).unwrap();
+
+        assert_eq!(commands[0].to_string(), "mouse_move: 1 2 300");
+        assert_eq!(commands[1].to_string(), "wait: 100..200");
+        assert_eq!(commands[2].to_string(), "key: escape click");
+    }
+
+    #[test]
+    fn test_written_scripts_match_the_schema() {
+        let schema_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/script.schema.json");
+        let schema: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(schema_path).unwrap()).unwrap();
+        let schema = jsonschema::JSONSchema::options().with_draft(jsonschema::Draft::Draft202012).compile(&schema).unwrap();
+        let script_text = String::from(EXAMPLE_SCRIPT) + "key_sequence:\nkey_sequence_delay: delay=5\nmouse_click: right\nevery: 100\nend_every:\n";
+        let commands = crate::script::Script::parse(&script_text).unwrap().commands();
+
+        for format in [ScriptFileFormat::Yaml, ScriptFileFormat::Json, ScriptFileFormat::Toml] {
+            let text = write_commands(&commands, format).unwrap();
+            let document: serde_json::Value = match format {
+                ScriptFileFormat::Yaml => serde_yaml::from_str(&text).unwrap(),
+                ScriptFileFormat::Toml => toml::from_str(&text).unwrap(),
+                _ => serde_json::from_str(&text).unwrap()
+            };
+            assert!(schema.is_valid(&document), "{}", text);
+        }
+
+        // The loader takes names in any case, so the schema does too
+        let document = serde_json::json!({"commands": [{"function_key_use": ["Escape", "click"]}, {"mouse_click": "LEFT"}]});
+        assert!(schema.is_valid(&document));
+        let document = serde_json::json!({"commands": [{"function_key_use": ["no_such_key", "click"]}]});
+        assert!(!schema.is_valid(&document));
+    }
+
+    #[test]
+    fn test_structured_reserved_exit_code() {
+        assert!(matches!(parse_structured_commands(r#"{"commands": [{"exit": 2}]}"#, ScriptFileFormat::Json), Err(Error::Structured{..})));
+        assert!(matches!(parse_structured_commands("commands:\n  - exit: 0\n", ScriptFileFormat::Yaml), Err(Error::Structured{..})));
+    }
+
+    #[test]
+    fn test_structured_commands_the_text_format_rejects() {
+        for document in [r#"{"commands": [{"every": {"interval_ms": 0, "times": null}}]}"#,
+                         r#"{"commands": [{"label": "two words"}]}"#,
+                         r#"{"commands": [{"key_sequence": "line\nbreak"}]}"#] {
+            assert!(matches!(parse_structured_commands(document, ScriptFileFormat::Json), Err(Error::Structured{..})), "{}", document);
+        }
+    }
+}
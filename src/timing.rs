@@ -0,0 +1,118 @@
+use std::fmt;
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::{Serialize, Deserialize};
+
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitDistribution {
+    #[default]
+    Uniform,
+    Normal
+}
+
+// The name a script uses for the distribution
+impl fmt::Display for WaitDistribution {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            WaitDistribution::Uniform => write!(formatter, "uniform"),
+            WaitDistribution::Normal => write!(formatter, "normal")
+        };
+    }
+}
+
+pub fn sample_wait_ms<R: Rng>(rng: &mut R, min_ms: u64, max_ms: u64, distribution: WaitDistribution) -> u64 {
+    if min_ms >= max_ms {
+        return min_ms;
+    }
+
+    match distribution {
+        WaitDistribution::Uniform => {
+            return rng.gen_range(min_ms..=max_ms);
+        },
+        WaitDistribution::Normal => {
+            // Centre the curve in the range and let three standard deviations reach either end
+            let mean = (min_ms + max_ms) as f64 / 2.0;
+            let standard_deviation = (max_ms - min_ms) as f64 / 6.0;
+            let normal = Normal::new(mean, standard_deviation).expect("Invalid normal distribution");
+
+            let sampled_ms = normal.sample(rng).round().clamp(min_ms as f64, max_ms as f64);
+            return sampled_ms as u64;
+        }
+    }
+}
+
+// More jitter than this only shortens more waits to nothing, and a much larger range of random numbers cannot be drawn from
+pub const MAX_JITTER_PERCENTAGE: f64 = 1000.0;
+
+// For jitter given by a user, which NaN, infinity or a negative number would make no sense for
+pub fn check_jitter_percentage(jitter_percentage: f64) -> Result<f64, String> {
+    if !(0.0..=MAX_JITTER_PERCENTAGE).contains(&jitter_percentage) {
+        return Err(format!("The jitter percentage must be between 0 and {}, not {}", MAX_JITTER_PERCENTAGE, jitter_percentage));
+    }
+    return Ok(jitter_percentage);
+}
+
+// Jitter that check_jitter_percentage would reject is treated as none, or as the most there can be
+pub fn apply_jitter<R: Rng>(rng: &mut R, wait_ms: u64, jitter_percentage: f64) -> u64 {
+    if jitter_percentage.is_nan() || jitter_percentage <= 0.0 || wait_ms == 0 {
+        return wait_ms;
+    }
+
+    let jitter_percentage = jitter_percentage.min(MAX_JITTER_PERCENTAGE);
+    let jitter_fraction = rng.gen_range(-jitter_percentage..=jitter_percentage) / 100.0;
+    let jittered_ms = (wait_ms as f64 * (1.0 + jitter_fraction)).round().max(0.0);
+
+    return jittered_ms as u64;
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_sample_wait_ms_stays_in_range() {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _i in 0..1000 {
+            let uniform_ms = sample_wait_ms(&mut rng, 100, 250, WaitDistribution::Uniform);
+            let normal_ms = sample_wait_ms(&mut rng, 100, 250, WaitDistribution::Normal);
+
+            assert!((100..=250).contains(&uniform_ms));
+            assert!((100..=250).contains(&normal_ms));
+        }
+    }
+
+    #[test]
+    fn test_sample_wait_ms_is_reproducible() {
+        let first_run: Vec<u64> = (0..10).scan(StdRng::seed_from_u64(42), |rng, _| Some(sample_wait_ms(rng, 0, 1000, WaitDistribution::Uniform))).collect();
+        let second_run: Vec<u64> = (0..10).scan(StdRng::seed_from_u64(42), |rng, _| Some(sample_wait_ms(rng, 0, 1000, WaitDistribution::Uniform))).collect();
+
+        assert_eq!(first_run, second_run);
+    }
+
+    #[test]
+    fn test_apply_jitter() {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        assert_eq!(apply_jitter(&mut rng, 100, 0.0), 100);
+        for _i in 0..1000 {
+            let jittered_ms = apply_jitter(&mut rng, 100, 10.0);
+            assert!((90..=110).contains(&jittered_ms));
+        }
+        assert_eq!(apply_jitter(&mut rng, 100, f64::NAN), 100);
+        assert!(apply_jitter(&mut rng, 100, f64::INFINITY) <= 1100);
+    }
+
+    #[test]
+    fn test_check_jitter_percentage() {
+        assert_eq!(check_jitter_percentage(0.0), Ok(0.0));
+        assert_eq!(check_jitter_percentage(25.5), Ok(25.5));
+        assert!(check_jitter_percentage(-1.0).is_err());
+        assert!(check_jitter_percentage(f64::NAN).is_err());
+        assert!(check_jitter_percentage(f64::INFINITY).is_err());
+        assert!(check_jitter_percentage(MAX_JITTER_PERCENTAGE + 1.0).is_err());
+    }
+}
\ No newline at end of file